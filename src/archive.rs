@@ -0,0 +1,291 @@
+use chrono::{DateTime, TimeZone};
+use chrono_tz::Tz;
+use tracing::{debug, warn};
+use nostr_sdk::hashes::hmac::{Hmac, HmacEngine};
+use nostr_sdk::hashes::sha256;
+use nostr_sdk::hashes::{Hash, HashEngine};
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::env;
+
+use crate::database::{ArchivedMessage, Database, MessageDirection, MessageMapping};
+
+/// Domain-Separation für die Archiv-Schlüssel (nie ändern, sonst ist das Archiv unlesbar!)
+const ARCHIVE_KDF_SALT: &[u8] = b"nostr-telegram-bridge/archive/v1";
+
+/// Maximale Länge eines Treffers in Suchergebnissen (Zeichen)
+const HIT_PREVIEW_CHARS: usize = 200;
+
+/// Aus dem Bridge-Secret abgeleitete Schlüssel des Archivs.
+///
+/// Der Inhalt wird per NIP-44 an den eigenen Archiv-Key verschlüsselt. Für die
+/// Volltextsuche landen nur HMACs der Wörter im FTS5-Index (Blind Index), so
+/// dass die Datenbank ohne Bridge-Secret weder Text noch Suchbegriffe preisgibt.
+/// Gesucht wird daher nach ganzen Wörtern, ohne Präfix- oder Teilwortsuche.
+pub struct ArchiveKeys {
+    keys: Keys,
+    index_key: Vec<u8>,
+}
+
+/// Verschlüsselter Inhalt eines Archiv-Eintrags
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ArchivePayload {
+    sender: String,
+    content: String,
+}
+
+/// Entschlüsselter Suchtreffer
+#[derive(Debug, Clone)]
+pub struct ArchiveHit {
+    pub timestamp: i64,
+    pub direction: MessageDirection,
+    pub sender: String,
+    pub content: String,
+}
+
+impl ArchiveKeys {
+    /// Leitet Verschlüsselungs- und Index-Key per HKDF-SHA256 aus dem Bridge-Secret ab
+    pub fn derive(bridge_keys: &Keys) -> std::result::Result<Self, String> {
+        let secret = bridge_keys.secret_key().map_err(|e| e.to_string())?;
+        let prk = hkdf::extract(ARCHIVE_KDF_SALT, &secret.secret_bytes());
+
+        let okm = hkdf::expand(prk.as_byte_array(), b"encryption", 32);
+        let secret_key = SecretKey::from_slice(&okm).map_err(|e| e.to_string())?;
+
+        Ok(ArchiveKeys {
+            keys: Keys::new(secret_key),
+            index_key: hkdf::expand(prk.as_byte_array(), b"search-index", 32),
+        })
+    }
+
+    fn encrypt(&self, payload: &ArchivePayload) -> std::result::Result<String, String> {
+        let json = serde_json::to_string(payload).map_err(|e| e.to_string())?;
+        let secret_key = self.keys.secret_key().map_err(|e| e.to_string())?;
+        nip44::encrypt(secret_key, &self.keys.public_key(), json, nip44::Version::V2)
+            .map_err(|e| e.to_string())
+    }
+
+    fn decrypt(&self, payload: &str) -> std::result::Result<ArchivePayload, String> {
+        let secret_key = self.keys.secret_key().map_err(|e| e.to_string())?;
+        let json = nip44::decrypt(secret_key, &self.keys.public_key(), payload)
+            .map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    /// Geblindete Suchbegriffe eines Textes (HMAC pro Wort, ohne Duplikate)
+    fn blind_tokens(&self, text: &str) -> Vec<String> {
+        words(text)
+            .into_iter()
+            .map(|word| {
+                let mut engine = HmacEngine::<sha256::Hash>::new(&self.index_key);
+                engine.input(word.as_bytes());
+                let hmac = Hmac::<sha256::Hash>::from_engine(engine);
+                // 16 Byte reichen für die Suche und halten den Index klein
+                hex::encode(&hmac.as_byte_array()[..16])
+            })
+            .collect()
+    }
+}
+
+/// Zerlegt einen Text in kleingeschriebene, eindeutige Wörter
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Archiviert eine weitergeleitete Nachricht zu ihrem Mapping
+pub async fn store(db: &Database, bridge_keys: &Keys, mapping: &MessageMapping, sender: &str, content: &str) {
+    match try_store(db, bridge_keys, mapping, sender, content).await {
+        Ok(id) => debug!("Nachricht archiviert (#{})", id),
+        Err(e) => warn!("Fehler beim Archivieren der Nachricht: {}", e),
+    }
+}
+
+async fn try_store(
+    db: &Database,
+    bridge_keys: &Keys,
+    mapping: &MessageMapping,
+    sender: &str,
+    content: &str,
+) -> std::result::Result<i64, String> {
+    let archive_keys = ArchiveKeys::derive(bridge_keys)?;
+    let payload = ArchivePayload {
+        sender: sender.to_string(),
+        content: content.to_string(),
+    };
+    let encrypted = archive_keys.encrypt(&payload)?;
+    let tokens = archive_keys.blind_tokens(&format!("{} {}", sender, content)).join(" ");
+
+    let message = ArchivedMessage {
+        id: None,
+        telegram_chat_id: mapping.telegram_chat_id,
+        telegram_message_id: mapping.telegram_message_id,
+        nostr_event_id: mapping.nostr_event_id.clone(),
+        direction: mapping.direction.clone(),
+        payload: encrypted,
+        timestamp: mapping.timestamp,
+    };
+    db.archive_message(&message, &tokens).await.map_err(|e| e.to_string())
+}
+
+/// Baut fehlende Einträge des Suchindex neu auf (z.B. nach einem Import, da
+/// der Index selbst nicht exportiert wird). Gibt die Anzahl neu indizierter
+/// Einträge zurück.
+pub async fn reindex(db: &Database, bridge_keys: &Keys) -> std::result::Result<usize, String> {
+    let archive_keys = ArchiveKeys::derive(bridge_keys)?;
+    let mut indexed = 0;
+
+    for (id, payload) in db.unindexed_archive_messages().await.map_err(|e| e.to_string())? {
+        match archive_keys.decrypt(&payload) {
+            Ok(payload) => {
+                let tokens = archive_keys
+                    .blind_tokens(&format!("{} {}", payload.sender, payload.content))
+                    .join(" ");
+                db.index_archive_message(id, &tokens).await.map_err(|e| e.to_string())?;
+                indexed += 1;
+            }
+            Err(e) => warn!("Archiv-Eintrag {} nicht lesbar: {}", id, e),
+        }
+    }
+
+    Ok(indexed)
+}
+
+/// Durchsucht das Archiv nach Nachrichten, die alle Wörter der Suche enthalten
+pub async fn search(
+    db: &Database,
+    bridge_keys: &Keys,
+    query: &str,
+    telegram_chat_id: Option<i64>,
+    limit: usize,
+) -> std::result::Result<Vec<ArchiveHit>, String> {
+    let archive_keys = ArchiveKeys::derive(bridge_keys)?;
+
+    let tokens = archive_keys.blind_tokens(query);
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+    // Hex-Tokens in Anführungszeichen, mehrere Begriffe sind UND-verknüpft
+    let fts_query = tokens
+        .iter()
+        .map(|token| format!("\"{}\"", token))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let messages = db
+        .search_archive(&fts_query, telegram_chat_id, limit).await
+        .map_err(|e| e.to_string())?;
+
+    let mut hits = Vec::with_capacity(messages.len());
+    for message in messages {
+        match archive_keys.decrypt(&message.payload) {
+            Ok(payload) => hits.push(ArchiveHit {
+                timestamp: message.timestamp,
+                direction: message.direction,
+                sender: payload.sender,
+                content: payload.content,
+            }),
+            Err(e) => warn!("Archiv-Eintrag {:?} nicht lesbar: {}", message.id, e),
+        }
+    }
+
+    Ok(hits)
+}
+
+/// Formatiert einen Treffer für Telegram bzw. die Kommandozeile
+pub fn format_hit(hit: &ArchiveHit) -> String {
+    let tz: Tz = env::var("TIMEZONE")
+        .unwrap_or_else(|_| "Europe/Berlin".to_string())
+        .parse()
+        .unwrap_or(chrono_tz::Europe::Berlin);
+
+    let time_str = DateTime::from_timestamp(hit.timestamp, 0)
+        .map(|dt| tz.from_utc_datetime(&dt.naive_utc()).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+
+    let arrow = match hit.direction {
+        MessageDirection::TelegramToNostr => "📱→🟣",
+        MessageDirection::NostrToTelegram => "🟣→📱",
+    };
+
+    let mut content: String = hit.content.chars().take(HIT_PREVIEW_CHARS).collect();
+    if hit.content.chars().count() > HIT_PREVIEW_CHARS {
+        content.push('…');
+    }
+
+    format!("{} {} {}\n{}", time_str, arrow, hit.sender, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_archive_roundtrip_and_search() {
+        let db = Database::new(":memory:").unwrap();
+        let bridge_keys = Keys::generate();
+
+        let mapping = |id: i64, direction: MessageDirection| MessageMapping {
+            id: None,
+            telegram_chat_id: -100,
+            telegram_message_id: id,
+            telegram_thread_id: None,
+            nostr_event_id: format!("archive-event{}", id),
+            nostr_recipient_pubkey: "npub1test".to_string(),
+            direction,
+            timestamp: 1000 + id,
+        };
+
+        store(&db, &bridge_keys, &mapping(1, MessageDirection::TelegramToNostr), "Alice", "Treffen am Dienstag?").await;
+        store(&db, &bridge_keys, &mapping(2, MessageDirection::NostrToTelegram), "Bob", "Dienstag passt, 18 Uhr").await;
+
+        // Klartext landet weder im Archiv noch im Index
+        let raw = db.search_archive(&format!("\"{}\"", "dienstag"), None, 10).await.unwrap();
+        assert!(raw.is_empty());
+
+        let hits = search(&db, &bridge_keys, "DIENSTAG", Some(-100), 10).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].sender, "Bob");
+        assert_eq!(hits[0].content, "Dienstag passt, 18 Uhr");
+
+        let hits = search(&db, &bridge_keys, "alice dienstag", None, 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].direction, MessageDirection::TelegramToNostr);
+
+        assert!(search(&db, &bridge_keys, "  ", None, 10).await.unwrap().is_empty());
+
+        // Ein anderer Bridge-Key findet und entschlüsselt nichts
+        let other_keys = Keys::generate();
+        assert!(search(&db, &other_keys, "dienstag", None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reindex_after_import() {
+        let bridge_keys = Keys::generate();
+        let source = Database::new(":memory:").unwrap();
+        let mapping = MessageMapping {
+            id: None,
+            telegram_chat_id: -100,
+            telegram_message_id: 1,
+            telegram_thread_id: None,
+            nostr_event_id: "archive-event1".to_string(),
+            nostr_recipient_pubkey: "npub1test".to_string(),
+            direction: MessageDirection::TelegramToNostr,
+            timestamp: 1000,
+        };
+        store(&source, &bridge_keys, &mapping, "Alice", "Umzug auf neuen Server").await;
+
+        let (_, exported) = crate::export::export(&source, Vec::new()).await.unwrap();
+
+        let target = Database::new(":memory:").unwrap();
+        crate::export::import(&target, std::io::Cursor::new(exported), crate::export::ConflictPolicy::Skip).await.unwrap();
+        assert!(search(&target, &bridge_keys, "umzug", None, 10).await.unwrap().is_empty());
+
+        assert_eq!(reindex(&target, &bridge_keys).await.unwrap(), 1);
+        assert_eq!(reindex(&target, &bridge_keys).await.unwrap(), 0);
+        assert_eq!(search(&target, &bridge_keys, "umzug", None, 10).await.unwrap().len(), 1);
+    }
+}
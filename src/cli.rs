@@ -0,0 +1,170 @@
+use nostr_sdk::prelude::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+use crate::archive;
+use crate::config::Config;
+use crate::database::{Database, DatabaseError};
+use crate::export::{self, ConflictPolicy};
+use crate::BridgeError;
+
+/// Standard-Trefferzahl für `search`
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+pub const USAGE: &str = "Verwendung:
+  nostr-telegram-bridge                          Bridge starten
+  nostr-telegram-bridge search [--limit N] <Begriffe>
+                                                 Nachrichtenarchiv durchsuchen
+  nostr-telegram-bridge export <Datei.jsonl>     Datenbank als JSONL-Archiv exportieren
+  nostr-telegram-bridge import [--replace] <Datei.jsonl>
+                                                 JSONL-Archiv importieren (Konflikte
+                                                 überspringen bzw. mit --replace ersetzen)
+  nostr-telegram-bridge backup <Datei.db>        Konsistente Kopie der Datenbank erstellen
+  nostr-telegram-bridge help                     Diese Hilfe anzeigen";
+
+/// Befehl der Kommandozeile
+#[derive(Debug, PartialEq)]
+pub enum CliCommand {
+    Run,
+    Search { query: String, limit: usize },
+    Export { path: PathBuf },
+    Import { path: PathBuf, policy: ConflictPolicy },
+    Backup { path: PathBuf },
+    Help,
+}
+
+/// Liest den Befehl aus den Argumenten (ohne Programmnamen)
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> std::result::Result<CliCommand, String> {
+    let Some(command) = args.next() else {
+        return Ok(CliCommand::Run);
+    };
+
+    match command.as_str() {
+        "run" => Ok(CliCommand::Run),
+        "help" | "--help" | "-h" => Ok(CliCommand::Help),
+        "search" => {
+            let mut limit = DEFAULT_SEARCH_LIMIT;
+            let mut words = Vec::new();
+            while let Some(arg) = args.next() {
+                if arg == "--limit" {
+                    limit = args
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| "--limit erwartet eine positive Zahl".to_string())?;
+                } else {
+                    words.push(arg);
+                }
+            }
+            if words.is_empty() {
+                return Err("search erwartet mindestens einen Suchbegriff".to_string());
+            }
+            Ok(CliCommand::Search { query: words.join(" "), limit })
+        }
+        "export" => Ok(CliCommand::Export { path: single_path(args, "export")? }),
+        "backup" => Ok(CliCommand::Backup { path: single_path(args, "backup")? }),
+        "import" => {
+            let mut policy = ConflictPolicy::Skip;
+            let mut rest = Vec::new();
+            for arg in args {
+                if arg == "--replace" {
+                    policy = ConflictPolicy::Replace;
+                } else {
+                    rest.push(arg);
+                }
+            }
+            Ok(CliCommand::Import { path: single_path(rest.into_iter(), "import")?, policy })
+        }
+        other => Err(format!("Unbekannter Befehl: {}", other)),
+    }
+}
+
+fn single_path<I: Iterator<Item = String>>(mut args: I, command: &str) -> std::result::Result<PathBuf, String> {
+    match (args.next(), args.next()) {
+        (Some(path), None) => Ok(PathBuf::from(path)),
+        _ => Err(format!("{} erwartet genau einen Dateipfad", command)),
+    }
+}
+
+/// Führt einen Datenbank-Befehl aus (ohne Verbindung zu Telegram oder Nostr)
+pub async fn run(command: CliCommand, config: &Config, keys: &Keys) -> Result<(), BridgeError> {
+    match command {
+        CliCommand::Search { query, limit } => run_search(config, keys, &query, limit).await,
+        CliCommand::Export { path } => {
+            let db = Database::new(&config.database_path)?;
+            let file = File::create(&path).map_err(DatabaseError::from)?;
+            let (rows, _) = export::export(&db, BufWriter::new(file)).await?;
+            println!("{} Zeilen nach {} exportiert", rows, path.display());
+            Ok(())
+        }
+        CliCommand::Import { path, policy } => {
+            let db = Database::new(&config.database_path)?;
+            let file = File::open(&path).map_err(DatabaseError::from)?;
+            let stats = export::import(&db, BufReader::new(file), policy).await?;
+            println!("{} Zeilen importiert, {} Konflikte übersprungen", stats.imported, stats.skipped);
+
+            // Der Suchindex des Archivs ist nicht Teil des Exports
+            let indexed = archive::reindex(&db, keys).await.map_err(BridgeError::Archive)?;
+            if indexed > 0 {
+                println!("{} Archiv-Einträge neu indiziert", indexed);
+            }
+            Ok(())
+        }
+        CliCommand::Backup { path } => {
+            export::backup(&config.database_path, &path)?;
+            println!("Backup nach {} geschrieben", path.display());
+            Ok(())
+        }
+        CliCommand::Run | CliCommand::Help => Ok(()),
+    }
+}
+
+/// `search`: durchsucht das Archiv und gibt die Treffer auf stdout aus
+async fn run_search(config: &Config, keys: &Keys, query: &str, limit: usize) -> Result<(), BridgeError> {
+    if !config.archive_enabled {
+        eprintln!("Hinweis: ARCHIVE_ENABLED ist nicht gesetzt, neue Nachrichten werden nicht archiviert");
+    }
+
+    let db = Database::new(&config.database_path)?;
+    let hits = archive::search(&db, keys, query, None, limit).await.map_err(BridgeError::Archive)?;
+
+    if hits.is_empty() {
+        println!("Keine Treffer für „{}“", query);
+    }
+    for hit in &hits {
+        println!("{}\n", archive::format_hit(hit));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> impl Iterator<Item = String> {
+        list.iter().map(|s| s.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse(args(&[])).unwrap(), CliCommand::Run);
+        assert_eq!(parse(args(&["help"])).unwrap(), CliCommand::Help);
+        assert_eq!(
+            parse(args(&["search", "--limit", "5", "Treffen", "Dienstag"])).unwrap(),
+            CliCommand::Search { query: "Treffen Dienstag".to_string(), limit: 5 }
+        );
+        assert!(parse(args(&["search"])).is_err());
+        assert!(parse(args(&["search", "--limit", "0", "x"])).is_err());
+        assert_eq!(
+            parse(args(&["import", "--replace", "alt.jsonl"])).unwrap(),
+            CliCommand::Import { path: PathBuf::from("alt.jsonl"), policy: ConflictPolicy::Replace }
+        );
+        assert_eq!(
+            parse(args(&["backup", "kopie.db"])).unwrap(),
+            CliCommand::Backup { path: PathBuf::from("kopie.db") }
+        );
+        assert!(parse(args(&["export"])).is_err());
+        assert!(parse(args(&["unbekannt"])).is_err());
+    }
+}
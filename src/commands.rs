@@ -0,0 +1,291 @@
+use teloxide::prelude::*;
+use teloxide::types::{Message, User};
+use nostr_sdk::prelude::*;
+use tracing::{debug, warn};
+
+use crate::archive;
+use crate::config::Config;
+use crate::database::Database;
+use crate::identity;
+use crate::sources::Sources;
+
+/// Maximale Anzahl Treffer für /search
+const SEARCH_LIMIT: usize = 10;
+
+/// Ein erkannter Bot-Befehl mit seinen Argumenten
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Link(Option<&'a str>),
+    Unlink,
+    Search(String),
+}
+
+impl Command<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Link(_) => "link",
+            Command::Unlink => "unlink",
+            Command::Search(_) => "search",
+        }
+    }
+}
+
+/// Zerlegt eine Nachricht in Befehl und Argumente, `None` wenn sie kein
+/// bekannter Befehl ist
+fn parse_command(text: &str) -> Option<Command<'_>> {
+    let mut parts = text.split_whitespace();
+    // In Gruppen hängt Telegram den Bot-Namen an: "/link@MeinBot"
+    let command = parts.next()?.strip_prefix('/')?;
+    let command = command.split('@').next().unwrap_or(command);
+
+    match command {
+        "link" => Some(Command::Link(parts.next())),
+        "unlink" => Some(Command::Unlink),
+        "search" => Some(Command::Search(parts.collect::<Vec<_>>().join(" "))),
+        _ => None,
+    }
+}
+
+/// Liest das Argument von `/link`: npub mit oder ohne `nostr:`-Präfix
+fn parse_npub(arg: &str) -> Option<PublicKey> {
+    PublicKey::from_bech32(arg.trim_start_matches("nostr:")).ok()
+}
+
+/// Verarbeitet Bot-Befehle (/link, /unlink, /search). Gibt `true` zurück wenn
+/// die Nachricht ein Befehl war und nicht weitergeleitet werden soll.
+pub async fn handle_command(bot: &Bot, message: &Message, db: &Database, keys: &Keys, config: &Config) -> bool {
+    let Some(text) = message.text() else {
+        return false;
+    };
+
+    let Some(command) = parse_command(text) else {
+        return false;
+    };
+
+    let reply = match &command {
+        Command::Link(arg) => link_command(bot, message, db, keys, config, *arg).await,
+        Command::Unlink => unlink_command(message, db).await,
+        Command::Search(query) => search_command(message, db, keys, config, query).await,
+    };
+
+    debug!("Befehl /{} verarbeitet", command.name());
+    if let Err(e) = bot
+        .send_message(message.chat.id, reply)
+        .reply_to_message_id(message.id)
+        .await
+    {
+        warn!("Fehler beim Beantworten des Befehls /{}: {}", command.name(), e);
+    }
+    true
+}
+
+/// `/link npub1…`: startet die Verknüpfung, `/link` ohne Argument zeigt den Status
+async fn link_command(
+    bot: &Bot,
+    message: &Message,
+    db: &Database,
+    keys: &Keys,
+    config: &Config,
+    arg: Option<&str>,
+) -> String {
+    let Some(user) = message.from() else {
+        return "❌ Absender unbekannt".to_string();
+    };
+
+    let Some(npub) = arg else {
+        return match identity::linked_pubkey(db, user.id.0 as i64).await {
+            Some(pubkey) => format!(
+                "🔗 Verknüpft mit {}\nMit /unlink lösen.",
+                pubkey.to_bech32().unwrap_or_default()
+            ),
+            None => "Verwendung: /link npub1…".to_string(),
+        };
+    };
+
+    // Jede Anfrage legt eine offene Verknüpfung an, daher nur für User der Bridge
+    if !is_bridge_user(bot, message, user, config.telegram_group_id, &config.sources).await {
+        return "❌ /link ist nur für Mitglieder der Bridge-Gruppe verfügbar".to_string();
+    }
+
+    let Some(nostr_pubkey) = parse_npub(npub) else {
+        return "❌ Ungültiger npub".to_string();
+    };
+
+    match identity::start_link(db, user, &nostr_pubkey).await {
+        Ok(challenge) => format!(
+            "Sende innerhalb von {} Minuten eine DM (NIP-04 oder NIP-17) mit dem Code\n\n{}\n\nvon {} an {}",
+            identity::LINK_CHALLENGE_TTL_SECS / 60,
+            challenge,
+            nostr_pubkey.to_bech32().unwrap_or_default(),
+            keys.public_key().to_bech32().unwrap_or_default(),
+        ),
+        Err(e) => {
+            warn!("Fehler beim Speichern der Verknüpfungsanfrage: {}", e);
+            "❌ Interner Fehler, bitte später erneut versuchen".to_string()
+        }
+    }
+}
+
+/// Prüft ob ein User zur Bridge gehört: Nachrichten aus der Bridge-Gruppe und
+/// eingerichteten Privatchats (TELEGRAM_PRIVATE_CHATS), sonst die aktuelle
+/// Mitgliedschaft in der Gruppe
+async fn is_bridge_user(bot: &Bot, message: &Message, user: &User, group_id: i64, sources: &Sources) -> bool {
+    let chat_id = message.chat.id.0;
+    if chat_id == group_id || sources.private_chat_partner(chat_id).is_some() {
+        return true;
+    }
+
+    match bot.get_chat_member(ChatId(group_id), user.id).await {
+        Ok(member) => member.is_present(),
+        Err(e) => {
+            warn!("Mitgliedschaft von Telegram-User {} nicht prüfbar: {}", user.id, e);
+            false
+        }
+    }
+}
+
+/// `/unlink`: entfernt die Verknüpfung des Absenders
+async fn unlink_command(message: &Message, db: &Database) -> String {
+    let Some(user) = message.from() else {
+        return "❌ Absender unbekannt".to_string();
+    };
+
+    match db.remove_link(user.id.0 as i64).await {
+        Ok(true) => "✅ Verknüpfung entfernt".to_string(),
+        Ok(false) => "Keine Verknüpfung vorhanden".to_string(),
+        Err(e) => {
+            warn!("Fehler beim Entfernen der Verknüpfung: {}", e);
+            "❌ Interner Fehler, bitte später erneut versuchen".to_string()
+        }
+    }
+}
+
+/// `/search <begriffe>`: durchsucht das Archiv nach Nachrichten dieses Chats
+async fn search_command(message: &Message, db: &Database, keys: &Keys, config: &Config, query: &str) -> String {
+    if !config.archive_enabled {
+        return "Das Archiv ist deaktiviert (ARCHIVE_ENABLED)".to_string();
+    }
+    // Das Archiv enthält die Unterhaltung der Bridge-Gruppe, nicht von Privatchats
+    if message.chat.id.0 != config.telegram_group_id {
+        return "❌ /search ist nur in der Bridge-Gruppe verfügbar".to_string();
+    }
+    if query.trim().is_empty() {
+        return "Verwendung: /search <Begriffe>".to_string();
+    }
+
+    match archive::search(db, keys, query, Some(config.telegram_group_id), SEARCH_LIMIT).await {
+        Ok(hits) if hits.is_empty() => format!("🔎 Keine Treffer für „{}“", query),
+        Ok(hits) => {
+            let results: Vec<String> = hits.iter().map(archive::format_hit).collect();
+            format!("🔎 {} Treffer für „{}“:\n\n{}", hits.len(), query, results.join("\n\n"))
+        }
+        Err(e) => {
+            warn!("Fehler bei der Archiv-Suche: {}", e);
+            "❌ Interner Fehler, bitte später erneut versuchen".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let cases = [
+            ("/link npub1abc", Some(Command::Link(Some("npub1abc")))),
+            ("/link@MeinBot   nostr:npub1abc weiterer Text", Some(Command::Link(Some("nostr:npub1abc")))),
+            ("/link", Some(Command::Link(None))),
+            ("  /link  ", Some(Command::Link(None))),
+            ("/unlink", Some(Command::Unlink)),
+            ("/unlink@MeinBot npub1abc", Some(Command::Unlink)),
+            ("/search  foo \n bar", Some(Command::Search("foo bar".to_string()))),
+            ("/search", Some(Command::Search(String::new()))),
+            ("/linked npub1abc", None),
+            ("/LINK npub1abc", None),
+            ("link npub1abc", None),
+            ("Hallo /link", None),
+            ("", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(parse_command(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_npub() {
+        let pubkey = Keys::generate().public_key();
+        let npub = pubkey.to_bech32().unwrap();
+
+        let cases = [
+            (npub.clone(), Some(pubkey)),
+            (format!("nostr:{npub}"), Some(pubkey)),
+            (pubkey.to_hex(), None),
+            (npub[..npub.len() - 1].to_string(), None),
+            (EventId::all_zeros().to_bech32().unwrap(), None),
+            ("npub1".to_string(), None),
+        ];
+
+        for (arg, expected) in cases {
+            assert_eq!(parse_npub(&arg), expected, "{:?}", arg);
+        }
+    }
+
+    /// Lokale Attrappe der Telegram-Bot-API: getChatMember meldet User 7 als
+    /// Mitglied, User 9 als unbekannt und alle anderen als ausgetreten
+    async fn mock_telegram_api() -> Bot {
+        async fn api(axum::Json(body): axum::Json<serde_json::Value>) -> axum::Json<serde_json::Value> {
+            let user_id = body["user_id"].as_i64().unwrap_or_default();
+            let user = serde_json::json!({"id": user_id, "is_bot": false, "first_name": "Test"});
+            axum::Json(match user_id {
+                7 => serde_json::json!({"ok": true, "result": {"status": "member", "user": user}}),
+                9 => serde_json::json!({"ok": false, "error_code": 400, "description": "Bad Request: user not found"}),
+                _ => serde_json::json!({"ok": true, "result": {"status": "left", "user": user}}),
+            })
+        }
+
+        let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let router = axum::Router::new().fallback(api);
+        tokio::spawn(axum::Server::from_tcp(socket).unwrap().serve(router.into_make_service()));
+
+        Bot::new("123:test").set_api_url(format!("http://{}/", addr).parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_is_bridge_user() {
+        let bot = mock_telegram_api().await;
+        let group_id = -1001234567890;
+        let group = serde_json::json!({"id": group_id, "type": "supergroup", "title": "Bridge"});
+        let private = |id: i64| serde_json::json!({"id": id, "type": "private", "first_name": "Test"});
+
+        // (Chat, Absender, gehört zur Bridge?)
+        let cases = [
+            // In der Gruppe ohne Rückfrage, auch wenn die API den User nicht kennt
+            (group, 8, true),
+            (private(7), 7, true),
+            (private(8), 8, false),
+            (private(9), 9, false),
+        ];
+
+        for (chat, user_id, expected) in cases {
+            let message: Message = serde_json::from_value(serde_json::json!({
+                "message_id": 1,
+                "date": 0,
+                "chat": chat,
+                "from": {"id": user_id, "is_bot": false, "first_name": "Test"},
+                "text": "/link npub1abc",
+            }))
+            .unwrap();
+            let user = message.from().unwrap();
+
+            assert_eq!(
+                is_bridge_user(&bot, &message, user, group_id, &Sources::default()).await,
+                expected,
+                "User {}",
+                user_id
+            );
+        }
+    }
+}
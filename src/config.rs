@@ -1,7 +1,12 @@
 use std::env;
+use std::net::SocketAddr;
 use std::result::Result;
 use thiserror::Error;
 
+use crate::templates::Templates;
+use crate::sources::Sources;
+use crate::topics::TopicRoutes;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Umgebungsvariable '{0}' fehlt")]
@@ -31,6 +36,76 @@ impl EncryptionType {
             }),
         }
     }
+
+    /// Name wie in ENCRYPTION_TYPE (z.B. für Metrik-Labels)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncryptionType::Nip04 => "nip04",
+            EncryptionType::Nip17 => "nip17",
+            EncryptionType::Public => "public",
+            EncryptionType::Group => "group",
+        }
+    }
+}
+
+/// Profil-Angaben für den Bridge-Key (Kind-0-Metadata und NIP-05)
+#[derive(Debug, Clone)]
+pub struct BridgeProfileConfig {
+    /// Anzeigename des Bridge-Bots
+    pub name: String,
+    /// Beschreibung (about)
+    pub about: Option<String>,
+    /// Avatar-URL
+    pub picture: Option<String>,
+    /// NIP-05-Identifier (z.B. bridge@example.org)
+    pub nip05: Option<String>,
+    /// Markiert das Profil als Bot (`"bot": true`)
+    pub bot: bool,
+    /// Intervall für das erneute Veröffentlichen des Profils in Sekunden
+    pub refresh_interval_secs: u64,
+}
+
+impl BridgeProfileConfig {
+    /// Lokaler Teil des NIP-05-Identifiers (z.B. "bridge" aus "bridge@example.org")
+    pub fn nip05_name(&self) -> Option<&str> {
+        self.nip05
+            .as_deref()
+            .and_then(|nip05| nip05.split_once('@'))
+            .map(|(name, _)| name)
+    }
+}
+
+/// Webhook statt Long Polling für Telegram-Updates (nur wenn TELEGRAM_WEBHOOK_URL gesetzt ist)
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Öffentliche HTTPS-URL, an die Telegram die Updates sendet
+    pub url: url::Url,
+    /// Lokale Adresse des Webhook-Listeners
+    pub listen_addr: SocketAddr,
+    /// Geheimnis für `X-Telegram-Bot-Api-Secret-Token` (sonst zufällig erzeugt)
+    pub secret_token: Option<String>,
+    /// Zertifikat und Schlüssel (PEM), wenn der Listener selbst HTTPS spricht
+    pub tls: Option<WebhookTls>,
+}
+
+/// TLS für den Webhook-Listener
+#[derive(Debug, Clone)]
+pub struct WebhookTls {
+    pub cert_path: String,
+    pub key_path: String,
+    /// Zertifikat an Telegram hochladen (selbstsignierte Zertifikate)
+    pub self_signed: bool,
+}
+
+/// Aufbewahrungsregeln für `message_mapping`
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Maximales Alter eines Mappings in Sekunden
+    pub max_age_secs: Option<i64>,
+    /// Maximale Anzahl Mappings (älteste werden zuerst gelöscht)
+    pub max_rows: Option<i64>,
+    /// Intervall der Bereinigung in Sekunden
+    pub interval_secs: u64,
 }
 
 /// Konfiguration für die Bridge, geladen aus Umgebungsvariablen
@@ -54,6 +129,35 @@ pub struct Config {
     pub nostr_group_relay: Option<String>,
     /// Pfad zur SQLite-Datenbank
     pub database_path: String,
+    /// Profil des Bridge-Keys (nur wenn BRIDGE_PROFILE_NAME gesetzt ist)
+    pub bridge_profile: Option<BridgeProfileConfig>,
+    /// Adresse für den eingebauten HTTP-Server (z.B. 0.0.0.0:8080)
+    pub http_listen_addr: Option<SocketAddr>,
+    /// Telegram-Webhook statt Long Polling (TELEGRAM_WEBHOOK_URL)
+    pub webhook: Option<WebhookConfig>,
+    /// Gültigkeit zwischengespeicherter Nostr-Profile in Sekunden
+    pub profile_cache_ttl_secs: u64,
+    /// Eigener Nostr-Key pro Telegram-User (nur public/group-Modus)
+    pub puppet_keys: bool,
+    /// URL-Vorlage für Links zu einem Nostr-Web-Client (`{id}` = Bech32)
+    pub nostr_web_client_url: String,
+    /// Vorschau für in DMs referenzierte Events (`nostr:nevent1…`, `nostr:naddr1…`)
+    pub nostr_event_previews: bool,
+    /// Retention für Mappings (nur wenn RETENTION_MAX_AGE_DAYS oder RETENTION_MAX_ROWS gesetzt ist)
+    pub retention: Option<RetentionConfig>,
+    /// Verschlüsseltes, durchsuchbares Nachrichtenarchiv (ARCHIVE_ENABLED)
+    pub archive_enabled: bool,
+    /// PostgreSQL-Verbindung für zentral gespeicherte Mappings (nur mit Feature `postgres`)
+    pub postgres_url: Option<String>,
+    /// Vorlagen für den Rahmen weitergeleiteter Nachrichten (MESSAGE_LANGUAGE, TEMPLATE_*)
+    pub templates: Templates,
+    /// Ab dieser Länge (Zeichen) gehen Nostr-Nachrichten als `.txt`-Datei statt
+    /// als Nachrichtenserie an Telegram (TELEGRAM_DOCUMENT_THRESHOLD)
+    pub telegram_document_threshold: Option<usize>,
+    /// Nostr-Ziele einzelner Forum-Themen (TELEGRAM_TOPICS)
+    pub topics: TopicRoutes,
+    /// Kanäle und Privatchats als weitere Quellen (TELEGRAM_CHANNELS, TELEGRAM_PRIVATE_CHATS)
+    pub sources: Sources,
 }
 
 impl Config {
@@ -81,10 +185,6 @@ impl Config {
                 env::var("NOSTR_DM_RECIPIENT")
                     .or_else(|_| env::var("NOSTR_PUBLIC_KEY"))
                     .ok()
-                    .or_else(|| {
-                        // Wenn keines gesetzt ist, Fehler
-                        None
-                    })
             }
             EncryptionType::Public | EncryptionType::Group => {
                 env::var("NOSTR_DM_RECIPIENT")
@@ -114,6 +214,88 @@ impl Config {
         let database_path = env::var("DATABASE_PATH")
             .unwrap_or_else(|_| "./bridge.db".to_string());
 
+        let bridge_profile = load_bridge_profile()?;
+
+        // Eingebauter HTTP-Server (optional)
+        let http_listen_addr = match env::var("HTTP_LISTEN_ADDR") {
+            Ok(addr) => Some(addr.parse::<SocketAddr>().map_err(|_| ConfigError::InvalidValue {
+                var: "HTTP_LISTEN_ADDR".to_string(),
+                msg: "Muss eine gültige Socket-Adresse sein (z.B. 0.0.0.0:8080)".to_string(),
+            })?),
+            Err(_) => None,
+        };
+
+        let webhook = load_webhook()?;
+        if let (Some(webhook), Some(http_addr)) = (&webhook, http_listen_addr) {
+            if addrs_conflict(webhook.listen_addr, http_addr) {
+                return Err(ConfigError::InvalidValue {
+                    var: "TELEGRAM_WEBHOOK_LISTEN_ADDR".to_string(),
+                    msg: "Belegt denselben Port wie HTTP_LISTEN_ADDR".to_string(),
+                });
+            }
+        }
+
+        let profile_cache_ttl_secs = env::var("PROFILE_CACHE_TTL_SECS")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse::<u64>()
+            .map_err(|_| ConfigError::InvalidValue {
+                var: "PROFILE_CACHE_TTL_SECS".to_string(),
+                msg: "Muss eine gültige Zahl sein".to_string(),
+            })?;
+
+        // Puppet-Keys (optional, Standard: aus)
+        let puppet_keys = env::var("PUPPET_KEYS")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+
+        // Web-Client für Links auf Profile und Events (NIP-19)
+        let nostr_web_client_url = env::var("NOSTR_WEB_CLIENT_URL")
+            .unwrap_or_else(|_| "https://njump.me/{id}".to_string());
+        if !nostr_web_client_url.contains("{id}") {
+            return Err(ConfigError::InvalidValue {
+                var: "NOSTR_WEB_CLIENT_URL".to_string(),
+                msg: "Muss den Platzhalter {id} enthalten".to_string(),
+            });
+        }
+
+        // Vorschauen referenzierter Events (Standard: an)
+        let nostr_event_previews = env::var("NOSTR_EVENT_PREVIEWS")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(true);
+
+        let retention = load_retention()?;
+
+        let templates = Templates::from_env(&encryption_type)?;
+
+        // Lange Nostr-Nachrichten als Datei senden (optional)
+        let telegram_document_threshold = match env::var("TELEGRAM_DOCUMENT_THRESHOLD") {
+            Ok(value) => Some(value.parse::<usize>().ok().filter(|&n| n > 0).ok_or_else(|| ConfigError::InvalidValue {
+                var: "TELEGRAM_DOCUMENT_THRESHOLD".to_string(),
+                msg: "Muss eine positive Zahl sein".to_string(),
+            })?),
+            Err(_) => None,
+        };
+
+        // Forum-Themen mit eigenem Nostr-Ziel (optional)
+        let topics = TopicRoutes::from_env(&encryption_type)?;
+
+        // Kanäle und Privatchats mit dem Bot (optional)
+        let sources = Sources::from_env()?;
+
+        // Nachrichtenarchiv (opt-in)
+        let archive_enabled = env::var("ARCHIVE_ENABLED")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+
+        // Zentraler Mapping-Speicher (optional, nur mit Feature `postgres`)
+        let postgres_url = env::var("POSTGRES_URL").ok().filter(|url| !url.is_empty());
+        if postgres_url.is_some() && !cfg!(feature = "postgres") {
+            return Err(ConfigError::InvalidValue {
+                var: "POSTGRES_URL".to_string(),
+                msg: "Die Bridge wurde ohne PostgreSQL-Unterstützung gebaut (cargo build --features postgres)".to_string(),
+            });
+        }
+
         // Validierung
         if nostr_relays.is_empty() {
             return Err(ConfigError::InvalidValue {
@@ -148,6 +330,20 @@ impl Config {
             nostr_group_event_id,
             nostr_group_relay,
             database_path,
+            bridge_profile,
+            http_listen_addr,
+            webhook,
+            profile_cache_ttl_secs,
+            puppet_keys,
+            nostr_web_client_url,
+            nostr_event_previews,
+            retention,
+            archive_enabled,
+            postgres_url,
+            templates,
+            telegram_document_threshold,
+            topics,
+            sources,
         })
     }
 
@@ -162,12 +358,155 @@ impl Config {
     }
 
 
+    /// Prüft ob Nachrichten mit Puppet-Keys signiert werden (nur öffentliche Modi)
+    pub fn uses_puppet_keys(&self) -> bool {
+        self.puppet_keys
+            && matches!(self.encryption_type, EncryptionType::Public | EncryptionType::Group)
+    }
+
     /// Prüft ob der Modus Verschlüsselung benötigt
     pub fn needs_encryption(&self) -> bool {
         matches!(self.encryption_type, EncryptionType::Nip04 | EncryptionType::Nip17)
     }
 }
 
+/// Lädt das Bridge-Profil; ohne BRIDGE_PROFILE_NAME wird kein Profil veröffentlicht
+fn load_bridge_profile() -> Result<Option<BridgeProfileConfig>, ConfigError> {
+    let name = match env::var("BRIDGE_PROFILE_NAME") {
+        Ok(name) if !name.trim().is_empty() => name,
+        _ => return Ok(None),
+    };
+
+    let nip05 = env::var("BRIDGE_PROFILE_NIP05").ok();
+    if let Some(ref nip05) = nip05 {
+        if !nip05.contains('@') {
+            return Err(ConfigError::InvalidValue {
+                var: "BRIDGE_PROFILE_NIP05".to_string(),
+                msg: "Muss die Form name@domain haben".to_string(),
+            });
+        }
+    }
+
+    let bot = env::var("BRIDGE_PROFILE_BOT")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true);
+
+    let refresh_interval_secs = env::var("BRIDGE_PROFILE_REFRESH_SECS")
+        .unwrap_or_else(|_| "86400".to_string())
+        .parse::<u64>()
+        .ok()
+        .filter(|secs| *secs > 0)
+        .ok_or_else(|| ConfigError::InvalidValue {
+            var: "BRIDGE_PROFILE_REFRESH_SECS".to_string(),
+            msg: "Muss eine positive Zahl sein".to_string(),
+        })?;
+
+    Ok(Some(BridgeProfileConfig {
+        name,
+        about: env::var("BRIDGE_PROFILE_ABOUT").ok(),
+        picture: env::var("BRIDGE_PROFILE_PICTURE").ok(),
+        nip05,
+        bot,
+        refresh_interval_secs,
+    }))
+}
+
+/// Lädt die Retention-Regeln; ohne Alter und Anzahl bleibt die Tabelle unbegrenzt
+fn load_retention() -> Result<Option<RetentionConfig>, ConfigError> {
+    let max_age_secs = parse_optional_positive("RETENTION_MAX_AGE_DAYS")?
+        .map(|days| {
+            days.checked_mul(24 * 60 * 60).ok_or_else(|| ConfigError::InvalidValue {
+                var: "RETENTION_MAX_AGE_DAYS".to_string(),
+                msg: format!("Darf höchstens {} sein", i64::MAX / (24 * 60 * 60)),
+            })
+        })
+        .transpose()?;
+    let max_rows = parse_optional_positive("RETENTION_MAX_ROWS")?;
+
+    if max_age_secs.is_none() && max_rows.is_none() {
+        return Ok(None);
+    }
+
+    let interval_secs = parse_optional_positive("RETENTION_INTERVAL_SECS")?.unwrap_or(3600) as u64;
+
+    Ok(Some(RetentionConfig {
+        max_age_secs,
+        max_rows,
+        interval_secs,
+    }))
+}
+
+/// Zwei Listener kollidieren bei gleichem Port, wenn die Adressen gleich sind
+/// oder eine davon alle Interfaces belegt (`0.0.0.0`, `[::]`)
+fn addrs_conflict(a: SocketAddr, b: SocketAddr) -> bool {
+    a.port() == b.port() && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+}
+
+fn load_webhook() -> Result<Option<WebhookConfig>, ConfigError> {
+    let url = match env::var("TELEGRAM_WEBHOOK_URL") {
+        Ok(url) if !url.is_empty() => url,
+        _ => return Ok(None),
+    };
+    let invalid = |var: &str, msg: &str| ConfigError::InvalidValue {
+        var: var.to_string(),
+        msg: msg.to_string(),
+    };
+
+    // Telegram liefert Webhooks nur über HTTPS aus
+    let url = url::Url::parse(&url)
+        .ok()
+        .filter(|url| url.scheme() == "https")
+        .ok_or_else(|| invalid("TELEGRAM_WEBHOOK_URL", "Muss eine HTTPS-URL sein (z.B. https://bridge.example.org/telegram)"))?;
+
+    let listen_addr = env::var("TELEGRAM_WEBHOOK_LISTEN_ADDR")
+        .unwrap_or_else(|_| "0.0.0.0:8443".to_string())
+        .parse::<SocketAddr>()
+        .map_err(|_| invalid("TELEGRAM_WEBHOOK_LISTEN_ADDR", "Muss eine gültige Socket-Adresse sein (z.B. 0.0.0.0:8443)"))?;
+
+    let secret_token = env::var("TELEGRAM_WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty());
+    if let Some(ref secret) = secret_token {
+        let valid = secret.len() <= 256
+            && secret.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(invalid("TELEGRAM_WEBHOOK_SECRET", "1-256 Zeichen, nur A-Z, a-z, 0-9, _ und -"));
+        }
+    }
+
+    let tls = match (env::var("TELEGRAM_WEBHOOK_TLS_CERT"), env::var("TELEGRAM_WEBHOOK_TLS_KEY")) {
+        (Ok(cert_path), Ok(key_path)) => Some(WebhookTls {
+            cert_path,
+            key_path,
+            self_signed: env::var("TELEGRAM_WEBHOOK_SELF_SIGNED")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
+        }),
+        (Err(_), Err(_)) => None,
+        _ => return Err(invalid("TELEGRAM_WEBHOOK_TLS_CERT", "TELEGRAM_WEBHOOK_TLS_CERT und TELEGRAM_WEBHOOK_TLS_KEY müssen zusammen gesetzt sein")),
+    };
+
+    Ok(Some(WebhookConfig {
+        url,
+        listen_addr,
+        secret_token,
+        tls,
+    }))
+}
+
+fn parse_optional_positive(var_name: &str) -> Result<Option<i64>, ConfigError> {
+    match env::var(var_name) {
+        Ok(value) => value
+            .parse::<i64>()
+            .ok()
+            .filter(|v| *v > 0)
+            .map(Some)
+            .ok_or_else(|| ConfigError::InvalidValue {
+                var: var_name.to_string(),
+                msg: "Muss eine positive Zahl sein".to_string(),
+            }),
+        Err(_) => Ok(None),
+    }
+}
+
 fn get_env_var(var_name: &str) -> Result<String, ConfigError> {
     env::var(var_name).map_err(|_| ConfigError::MissingEnvVar(var_name.to_string()))
 }
\ No newline at end of file
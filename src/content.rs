@@ -0,0 +1,284 @@
+use nostr_sdk::prelude::*;
+use teloxide::types::{Contact, Location, Message, MessageEntity, Poll, PollType, Venue};
+
+use crate::templates::Language;
+
+/// NIP-88: Umfrage
+const KIND_POLL: u64 = 1068;
+
+/// Länge des genauesten Geohashs (~5 m)
+const GEOHASH_PRECISION: usize = 9;
+
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// vCard-Eigenschaften, die aus einer mitgesendeten vCard übernommen werden
+const VCARD_PROPERTIES: [&str; 6] = ["EMAIL", "ORG", "TITLE", "URL", "ADR", "NOTE"];
+
+/// Weiterleitbarer Inhalt einer Telegram-Nachricht
+pub enum TelegramContent<'a> {
+    /// Text mit Entities (Formatierung, Erwähnungen)
+    Text(&'a str, &'a [MessageEntity]),
+    /// Standort, Ort, Umfrage oder Kontakt, als Text dargestellt
+    Rendered {
+        text: String,
+        /// Zusätzliche Tags, z.B. `g` (Geohash) bei Standorten
+        tags: Vec<Tag>,
+        /// Umfrage, die als NIP-88-Event gesendet wird; `text` ist dann nur die Frage
+        poll: Option<&'a Poll>,
+    },
+}
+
+impl<'a> TelegramContent<'a> {
+    /// Liest den Inhalt einer Nachricht. Mit `nip88_polls` werden Umfragen als
+    /// eigenes Event statt als Text gesendet. Andere Nachrichten (Fotos ohne
+    /// Text, Sticker, …) ergeben `None`.
+    pub fn from_message(message: &'a Message, language: Language, nip88_polls: bool) -> Option<Self> {
+        if let Some(text) = message.text() {
+            return Some(TelegramContent::Text(text, message.entities().unwrap_or_default()));
+        }
+        // Ein Ort enthält auch einen Standort, deshalb zuerst
+        if let Some(venue) = message.venue() {
+            return Some(rendered(render_venue(venue), location_tags(&venue.location)));
+        }
+        if let Some(location) = message.location() {
+            return Some(rendered(render_location(location, language), location_tags(location)));
+        }
+        if let Some(poll) = message.poll() {
+            if nip88_polls {
+                return Some(TelegramContent::Rendered { text: poll.question.clone(), tags: Vec::new(), poll: Some(poll) });
+            }
+            return Some(rendered(render_poll(poll, language), Vec::new()));
+        }
+        message.contact().map(|contact| rendered(render_contact(contact, language), Vec::new()))
+    }
+
+    /// Text für das Archiv: bei Textnachrichten unverändert, sonst die Darstellung
+    pub fn archive_text(&self) -> &str {
+        match self {
+            TelegramContent::Text(text, _) => text,
+            TelegramContent::Rendered { text, .. } => text,
+        }
+    }
+}
+
+fn rendered<'a>(text: String, tags: Vec<Tag>) -> TelegramContent<'a> {
+    TelegramContent::Rendered { text, tags, poll: None }
+}
+
+/// Ursprung einer weitergeleiteten Nachricht für `{forwarded}`
+pub fn forwarded_from(message: &Message) -> Option<String> {
+    let user = message.forward_from_user().map(|user| match &user.username {
+        Some(username) => format!("{} (@{})", user.full_name(), username),
+        None => user.full_name(),
+    });
+    let chat = message.forward_from_chat().map(|chat| {
+        let name = chat.title()
+            .map(str::to_string)
+            .or_else(|| chat.username().map(|username| format!("@{}", username)))
+            .unwrap_or_else(|| chat.id.to_string());
+        match message.forward_signature() {
+            Some(signature) => format!("{} ({})", name, signature),
+            None => name,
+        }
+    });
+    user.or(chat).or_else(|| message.forward_from_sender_name().map(str::to_string))
+}
+
+fn render_location(location: &Location, language: Language) -> String {
+    let label = match (language, location.live_period.is_some()) {
+        (Language::De, false) => "Standort",
+        (Language::De, true) => "Live-Standort",
+        (Language::En, false) => "Location",
+        (Language::En, true) => "Live location",
+    };
+    format!(
+        "📍 {}: {:.5}, {:.5}\n{}",
+        label,
+        location.latitude,
+        location.longitude,
+        map_link(location)
+    )
+}
+
+fn render_venue(venue: &Venue) -> String {
+    format!("📍 {}\n{}\n{}", venue.title, venue.address, map_link(&venue.location))
+}
+
+fn map_link(location: &Location) -> String {
+    format!(
+        "https://www.openstreetmap.org/?mlat={lat:.5}&mlon={lon:.5}#map=16/{lat:.5}/{lon:.5}",
+        lat = location.latitude,
+        lon = location.longitude
+    )
+}
+
+/// `g`-Tags mit allen Genauigkeiten, damit Relays auch nach der Umgebung
+/// filtern können (Tag-Filter vergleichen nur exakt)
+fn location_tags(location: &Location) -> Vec<Tag> {
+    let hash = geohash(location.latitude, location.longitude, GEOHASH_PRECISION);
+    (1..=hash.len()).rev().map(|len| Tag::Geohash(hash[..len].to_string())).collect()
+}
+
+/// Kodiert Koordinaten als Geohash mit `precision` Zeichen
+pub fn geohash(latitude: f64, longitude: f64, precision: usize) -> String {
+    let mut latitude_range = (-90.0, 90.0);
+    let mut longitude_range = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let (mut index, mut bits, mut is_longitude) = (0, 0, true);
+
+    while hash.len() < precision {
+        let (range, value) = if is_longitude {
+            (&mut longitude_range, longitude)
+        } else {
+            (&mut latitude_range, latitude)
+        };
+        let mid = (range.0 + range.1) / 2.0;
+        index <<= 1;
+        if value >= mid {
+            index |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        is_longitude = !is_longitude;
+
+        bits += 1;
+        if bits == 5 {
+            hash.push(GEOHASH_ALPHABET[index] as char);
+            (index, bits) = (0, 0);
+        }
+    }
+    hash
+}
+
+fn render_poll(poll: &Poll, language: Language) -> String {
+    let label = match (language, &poll.poll_type) {
+        (Language::De, PollType::Quiz) => "Quiz",
+        (Language::De, PollType::Regular) => "Umfrage",
+        (Language::En, PollType::Quiz) => "Quiz",
+        (Language::En, PollType::Regular) => "Poll",
+    };
+    let mut text = format!("📊 {}: {}", label, poll.question);
+    if poll.allows_multiple_answers {
+        text.push_str(match language {
+            Language::De => " (Mehrfachauswahl)",
+            Language::En => " (multiple answers)",
+        });
+    }
+    for option in &poll.options {
+        text.push_str("\n○ ");
+        text.push_str(&option.text);
+    }
+    text
+}
+
+/// NIP-88-Umfrage (Kind 1068) mit `label` als Inhalt
+pub fn poll_event(poll: &Poll, label: &str, extra_tags: Vec<Tag>) -> EventBuilder {
+    let mut tags: Vec<Tag> = poll.options.iter().enumerate()
+        .map(|(index, option)| Tag::Generic(
+            TagKind::Custom("option".to_string()),
+            vec![index.to_string(), option.text.clone()],
+        ))
+        .collect();
+    let poll_type = if poll.allows_multiple_answers { "multiplechoice" } else { "singlechoice" };
+    tags.push(Tag::Generic(TagKind::Custom("polltype".to_string()), vec![poll_type.to_string()]));
+    if let Some(close_date) = poll.close_date {
+        tags.push(Tag::Generic(TagKind::Custom("endsAt".to_string()), vec![close_date.timestamp().to_string()]));
+    }
+    tags.extend(extra_tags);
+    EventBuilder::new(Kind::Custom(KIND_POLL), label, tags)
+}
+
+/// Kontakt als vCard-ähnlicher Text
+fn render_contact(contact: &Contact, language: Language) -> String {
+    let name = match &contact.last_name {
+        Some(last_name) => format!("{} {}", contact.first_name, last_name),
+        None => contact.first_name.clone(),
+    };
+    let label = match language {
+        Language::De => "Kontakt",
+        Language::En => "Contact",
+    };
+    let mut text = format!("📇 {}\nFN: {}\nTEL: {}", label, name, contact.phone_number);
+
+    // Weitere Angaben aus der vCard, Parameter wie `EMAIL;TYPE=work` entfallen
+    for line in contact.vcard.as_deref().unwrap_or_default().lines() {
+        let Some((property, value)) = line.trim().split_once(':') else {
+            continue;
+        };
+        let property = property.split(';').next().unwrap_or_default().to_uppercase();
+        if VCARD_PROPERTIES.contains(&property.as_str()) && !value.trim().is_empty() {
+            text.push_str(&format!("\n{}: {}", property, value.trim().replace(';', " ").trim()));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::PollOption;
+
+    #[test]
+    fn test_geohash_and_location_tags() {
+        assert_eq!(geohash(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(geohash(-33.8688, 151.2093, 5), "r3gx2");
+
+        let location = Location {
+            longitude: 13.405,
+            latitude: 52.52,
+            horizontal_accuracy: None,
+            live_period: None,
+            heading: None,
+            proximity_alert_radius: None,
+        };
+        let tags = location_tags(&location);
+        assert_eq!(tags.len(), GEOHASH_PRECISION);
+        assert_eq!(tags[0], Tag::Geohash(geohash(52.52, 13.405, GEOHASH_PRECISION)));
+        assert_eq!(tags[GEOHASH_PRECISION - 1], Tag::Geohash("u".to_string()));
+        assert_eq!(
+            render_location(&location, Language::De),
+            "📍 Standort: 52.52000, 13.40500\nhttps://www.openstreetmap.org/?mlat=52.52000&mlon=13.40500#map=16/52.52000/13.40500"
+        );
+    }
+
+    #[test]
+    fn test_render_poll_and_contact() {
+        let poll = Poll {
+            id: "1".to_string(),
+            question: "Wann treffen wir uns?".to_string(),
+            options: vec![
+                PollOption { text: "Montag".to_string(), voter_count: 0 },
+                PollOption { text: "Freitag".to_string(), voter_count: 0 },
+            ],
+            is_closed: false,
+            total_voter_count: 0,
+            is_anonymous: true,
+            poll_type: PollType::Regular,
+            allows_multiple_answers: true,
+            correct_option_id: None,
+            explanation: None,
+            explanation_entities: None,
+            open_period: None,
+            close_date: None,
+        };
+        assert_eq!(render_poll(&poll, Language::De), "📊 Umfrage: Wann treffen wir uns? (Mehrfachauswahl)\n○ Montag\n○ Freitag");
+
+        let event = poll_event(&poll, "Frage", Vec::new()).to_event(&Keys::generate()).unwrap();
+        assert_eq!(event.kind, Kind::Custom(KIND_POLL));
+        assert_eq!(event.tags[1].as_vec(), vec!["option", "1", "Freitag"]);
+        assert_eq!(event.tags[2].as_vec(), vec!["polltype", "multiplechoice"]);
+
+        let contact = Contact {
+            phone_number: "+49 30 123456".to_string(),
+            first_name: "Erika".to_string(),
+            last_name: Some("Mustermann".to_string()),
+            user_id: None,
+            vcard: Some("BEGIN:VCARD\nVERSION:3.0\nFN:Erika Mustermann\nEMAIL;TYPE=work:erika@example.org\nORG:Beispiel GmbH;\nEND:VCARD".to_string()),
+        };
+        assert_eq!(
+            render_contact(&contact, Language::En),
+            "📇 Contact\nFN: Erika Mustermann\nTEL: +49 30 123456\nEMAIL: erika@example.org\nORG: Beispiel GmbH"
+        );
+    }
+}
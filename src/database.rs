@@ -1,7 +1,234 @@
-use rusqlite::{Connection, Result as SqlResult, params};
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, Result as SqlResult, params};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
-use std::sync::Mutex;
-use log::{info, debug};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, debug, error, warn};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+use crate::storage::{event_id_hash, Storage, CLAIM_TIMEOUT_SECS};
+
+#[derive(Error, Debug)]
+pub enum DatabaseError {
+    #[error("SQLite-Fehler: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Datenbank-Schema (Version {found}) ist neuer als diese Bridge-Version unterstützt (Version {supported})")]
+    SchemaTooNew { found: i64, supported: i64 },
+    #[error("E/A-Fehler: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON-Fehler: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Ungültiges Export-Archiv: {0}")]
+    InvalidExport(String),
+    #[error("Datenbank-Thread nicht erreichbar")]
+    Closed,
+    #[error("Datenbank-Operation mit Panik abgebrochen")]
+    Panicked,
+    #[cfg(feature = "postgres")]
+    #[error("PostgreSQL-Fehler: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+}
+
+/// Eine Schema-Migration; nach erfolgreicher Ausführung gilt `user_version = version`
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// Alle Migrationen in aufsteigender Reihenfolge. Bestehende Einträge dürfen nie
+/// geändert werden – Schema-Änderungen immer als neue Migration anhängen.
+///
+/// Version 1 entspricht dem ursprünglichen Schema ohne Versionierung; deshalb
+/// verwenden die frühen Migrationen `IF NOT EXISTS`, damit bestehende
+/// `bridge.db`-Dateien (user_version = 0) sauber übernommen werden.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "message_mapping",
+        sql: "CREATE TABLE IF NOT EXISTS message_mapping (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                telegram_chat_id INTEGER NOT NULL,
+                telegram_message_id INTEGER NOT NULL,
+                nostr_event_id TEXT NOT NULL,
+                nostr_recipient_pubkey TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                UNIQUE(telegram_chat_id, telegram_message_id),
+                UNIQUE(nostr_event_id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_telegram_lookup 
+             ON message_mapping(telegram_chat_id, telegram_message_id);
+            CREATE INDEX IF NOT EXISTS idx_nostr_lookup 
+             ON message_mapping(nostr_event_id);",
+    },
+    Migration {
+        version: 2,
+        description: "profile_cache",
+        sql: "CREATE TABLE IF NOT EXISTS profile_cache (
+                pubkey TEXT PRIMARY KEY,
+                name TEXT,
+                display_name TEXT,
+                nip05 TEXT,
+                nip05_verified INTEGER NOT NULL DEFAULT 0,
+                event_created_at INTEGER NOT NULL,
+                fetched_at INTEGER NOT NULL
+            );",
+    },
+    Migration {
+        version: 3,
+        description: "identity_links",
+        sql: "CREATE TABLE IF NOT EXISTS identity_links (
+                telegram_user_id INTEGER PRIMARY KEY,
+                telegram_username TEXT,
+                telegram_name TEXT NOT NULL,
+                nostr_pubkey TEXT NOT NULL UNIQUE,
+                linked_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS pending_links (
+                telegram_user_id INTEGER PRIMARY KEY,
+                telegram_username TEXT,
+                telegram_name TEXT NOT NULL,
+                nostr_pubkey TEXT NOT NULL,
+                challenge TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );",
+    },
+    Migration {
+        version: 4,
+        description: "puppets",
+        sql: "CREATE TABLE IF NOT EXISTS puppets (
+                telegram_user_id INTEGER PRIMARY KEY,
+                nostr_pubkey TEXT NOT NULL UNIQUE,
+                display_name TEXT NOT NULL,
+                telegram_username TEXT,
+                profile_published_at INTEGER
+            );",
+    },
+    Migration {
+        version: 5,
+        description: "retention",
+        sql: "CREATE TABLE pruned_events (
+                hash INTEGER PRIMARY KEY
+            );
+            CREATE TABLE bridge_state (
+                key TEXT PRIMARY KEY,
+                value NOT NULL
+            );
+            CREATE INDEX idx_mapping_timestamp ON message_mapping(timestamp);",
+    },
+    Migration {
+        version: 6,
+        description: "message_archive",
+        sql: "CREATE TABLE message_archive (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                telegram_chat_id INTEGER NOT NULL,
+                telegram_message_id INTEGER NOT NULL,
+                nostr_event_id TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                timestamp INTEGER NOT NULL
+            );
+            CREATE INDEX idx_archive_chat ON message_archive(telegram_chat_id, timestamp);
+            CREATE VIRTUAL TABLE message_archive_fts USING fts5(tokens, content='', contentless_delete=1);",
+    },
+    Migration {
+        version: 7,
+        description: "message_archive_unique_event",
+        sql: "CREATE UNIQUE INDEX idx_archive_event ON message_archive(nostr_event_id);",
+    },
+    Migration {
+        version: 8,
+        description: "message_mapping_claims",
+        // Ein Claim reserviert die Nachricht vor dem Senden (status = 'pending');
+        // die ID der Gegenseite ist bis zum Abschluss noch unbekannt (NULL).
+        sql: "CREATE TABLE message_mapping_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                telegram_chat_id INTEGER NOT NULL,
+                telegram_message_id INTEGER,
+                nostr_event_id TEXT,
+                nostr_recipient_pubkey TEXT NOT NULL DEFAULT '',
+                direction TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                status TEXT NOT NULL DEFAULT 'sent',
+                UNIQUE(telegram_chat_id, telegram_message_id),
+                UNIQUE(nostr_event_id)
+            );
+            INSERT INTO message_mapping_new
+                (id, telegram_chat_id, telegram_message_id, nostr_event_id,
+                 nostr_recipient_pubkey, direction, timestamp)
+             SELECT id, telegram_chat_id, telegram_message_id, nostr_event_id,
+                    nostr_recipient_pubkey, direction, timestamp
+             FROM message_mapping;
+            DROP TABLE message_mapping;
+            ALTER TABLE message_mapping_new RENAME TO message_mapping;
+            CREATE INDEX idx_telegram_lookup ON message_mapping(telegram_chat_id, telegram_message_id);
+            CREATE INDEX idx_nostr_lookup ON message_mapping(nostr_event_id);
+            CREATE INDEX idx_mapping_timestamp ON message_mapping(timestamp);",
+    },
+    Migration {
+        version: 9,
+        description: "message_parts",
+        // Weitere Telegram-Nachrichten eines aufgeteilten Nostr-Events (ab Teil 2)
+        sql: "CREATE TABLE message_parts (
+                telegram_chat_id INTEGER NOT NULL,
+                telegram_message_id INTEGER NOT NULL,
+                nostr_event_id TEXT NOT NULL,
+                part INTEGER NOT NULL,
+                PRIMARY KEY (telegram_chat_id, telegram_message_id)
+            );
+            CREATE INDEX idx_parts_event ON message_parts(nostr_event_id);",
+    },
+    Migration {
+        version: 10,
+        description: "message_mapping_thread",
+        sql: "ALTER TABLE message_mapping ADD COLUMN telegram_thread_id INTEGER;",
+    },
+    Migration {
+        version: 11,
+        description: "pruned_events_timestamp",
+        // Zeitstempel des gelöschten Mappings; für ältere Hashes gilt die Migration
+        // als Zeitpunkt, sie bleiben also noch ein volles Neu-Abonnier-Fenster erhalten
+        sql: "ALTER TABLE pruned_events ADD COLUMN timestamp INTEGER NOT NULL DEFAULT 0;
+            UPDATE pruned_events SET timestamp = unixepoch();
+            CREATE INDEX idx_pruned_timestamp ON pruned_events(timestamp);",
+    },
+];
+
+/// Schlüssel in `bridge_state` für die Gesamtzahl gelöschter Mappings
+const STATE_PRUNED_ROWS: &str = "pruned_rows";
+
+/// Schlüssel in `bridge_state` für den Zeitstempel des neuesten empfangenen Nostr-Events
+const STATE_NOSTR_SINCE: &str = "nostr_since";
+
+/// Schlüssel in `bridge_state` für den letzten Schreibtest von `/readyz`
+pub const STATE_HEALTH_CHECK: &str = "health_checked_at";
+
+/// Führt alle ausstehenden Migrationen aus, jede in einer eigenen Transaktion.
+/// Eine Datenbank mit neuerem Schema wird abgelehnt statt sie zu beschädigen.
+pub fn run_migrations(conn: &mut Connection, migrations: &[Migration]) -> Result<(), DatabaseError> {
+    let current: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+
+    if current > latest {
+        return Err(DatabaseError::SchemaTooNew { found: current, supported: latest });
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration.sql)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+        info!("Datenbank-Migration {} ({}) ausgeführt", migration.version, migration.description);
+    }
+
+    debug!("Datenbank-Schema auf Version {}", latest);
+    Ok(())
+}
 
 /// Richtung der Nachricht
 #[derive(Debug, Clone, PartialEq)]
@@ -18,7 +245,6 @@ impl MessageDirection {
         }
     }
 
-    #[allow(dead_code)]
     pub fn from_string(s: &str) -> Option<Self> {
         match s {
             "telegram_to_nostr" => Some(MessageDirection::TelegramToNostr),
@@ -35,182 +261,834 @@ pub struct MessageMapping {
     pub id: Option<i64>,
     pub telegram_chat_id: i64,
     pub telegram_message_id: i64,
+    /// Forum-Thema (`message_thread_id`), `None` außerhalb von Themen
+    pub telegram_thread_id: Option<i64>,
     pub nostr_event_id: String,
     pub nostr_recipient_pubkey: String,
     pub direction: MessageDirection,
     pub timestamp: i64,
 }
 
-/// Datenbank-Handler für die Bridge (thread-safe)
+/// Zwischengespeichertes Nostr-Profil (Kind 0)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedProfile {
+    /// Pubkey als Hex
+    pub pubkey: String,
+    pub name: Option<String>,
+    pub display_name: Option<String>,
+    pub nip05: Option<String>,
+    /// Ergebnis der letzten NIP-05-Verifizierung
+    pub nip05_verified: bool,
+    /// `created_at` des Kind-0-Events (0 = kein Profil gefunden)
+    pub event_created_at: i64,
+    /// Zeitpunkt des letzten Abrufs (für TTL)
+    pub fetched_at: i64,
+}
+
+/// Bestätigte Verknüpfung eines Telegram-Users mit einem Nostr-Pubkey
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdentityLink {
+    pub telegram_user_id: i64,
+    pub telegram_username: Option<String>,
+    pub telegram_name: String,
+    /// Pubkey als Hex
+    pub nostr_pubkey: String,
+    pub linked_at: i64,
+}
+
+/// Puppet-Identität eines Telegram-Users (abgeleiteter Nostr-Key)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Puppet {
+    pub telegram_user_id: i64,
+    /// Pubkey als Hex
+    pub nostr_pubkey: String,
+    pub display_name: String,
+    pub telegram_username: Option<String>,
+    /// Zeitpunkt der letzten Profil-Veröffentlichung (Kind 0)
+    pub profile_published_at: Option<i64>,
+}
+
+/// Archivierte Nachricht; `payload` ist verschlüsselt (siehe `archive`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedMessage {
+    pub id: Option<i64>,
+    pub telegram_chat_id: i64,
+    pub telegram_message_id: i64,
+    pub nostr_event_id: String,
+    pub direction: MessageDirection,
+    pub payload: String,
+    pub timestamp: i64,
+}
+
+/// Ergebnis einer Datenbank-Operation
+pub type DbResult<T> = Result<T, DatabaseError>;
+
+/// Auftrag an den Datenbank-Thread
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// Kapazität des Caches für vorbereitete Statements
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// Wartezeit bei gesperrter Datenbank (z.B. parallele CLI-Befehle)
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Datenbank-Handler für die Bridge.
+///
+/// Die SQLite-Verbindung gehört einem eigenen Thread ("bridge-db"), der die
+/// Aufträge der Reihe nach abarbeitet. Die async-Methoden schicken ihre Abfrage
+/// dorthin und warten auf das Ergebnis, ohne einen Tokio-Worker zu blockieren.
+/// Eine Panik in einer Abfrage wird abgefangen und als Fehler gemeldet; die
+/// Verbindung bleibt danach nutzbar.
 pub struct Database {
-    conn: Mutex<Connection>,
+    jobs: mpsc::Sender<Job>,
+    /// Aufträge, die auf den Datenbank-Thread warten
+    queued: Arc<AtomicUsize>,
 }
 
 impl Database {
-    /// Erstellt oder öffnet die Datenbank
-    pub fn new<P: AsRef<Path>>(path: P) -> SqlResult<Self> {
-        let conn = Connection::open(path)?;
-        let db = Database { 
-            conn: Mutex::new(conn) 
-        };
-        db.init_schema()?;
+    /// Erstellt oder öffnet die Datenbank und bringt das Schema auf den neuesten Stand
+    pub fn new<P: AsRef<Path>>(path: P) -> DbResult<Self> {
+        let mut conn = Connection::open(path)?;
+        configure_connection(&conn)?;
+        run_migrations(&mut conn, MIGRATIONS)?;
+
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name("bridge-db".to_string())
+            .spawn(move || {
+                while let Ok(job) = receiver.recv() {
+                    job(&mut conn);
+                }
+                debug!("Datenbank-Thread beendet");
+            })?;
+
         info!("Datenbank initialisiert");
-        Ok(db)
+        Ok(Database { jobs, queued: Arc::new(AtomicUsize::new(0)) })
     }
 
-    /// Erstellt das Datenbank-Schema
-    fn init_schema(&self) -> SqlResult<()> {
-        let conn = self.conn.lock().unwrap();
-        
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS message_mapping (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                telegram_chat_id INTEGER NOT NULL,
-                telegram_message_id INTEGER NOT NULL,
-                nostr_event_id TEXT NOT NULL,
-                nostr_recipient_pubkey TEXT NOT NULL,
-                direction TEXT NOT NULL,
-                timestamp INTEGER NOT NULL,
-                UNIQUE(telegram_chat_id, telegram_message_id),
-                UNIQUE(nostr_event_id)
-            )",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_telegram_lookup 
-             ON message_mapping(telegram_chat_id, telegram_message_id)",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_nostr_lookup 
-             ON message_mapping(nostr_event_id)",
-            [],
-        )?;
-
-        debug!("Datenbank-Schema erstellt");
-        Ok(())
-    }
-
-    /// Speichert ein neues Mapping
-    pub fn save_mapping(&self, mapping: &MessageMapping) -> SqlResult<i64> {
-        let conn = self.conn.lock().unwrap();
-        
-        conn.execute(
-            "INSERT INTO message_mapping 
-             (telegram_chat_id, telegram_message_id, nostr_event_id, 
-              nostr_recipient_pubkey, direction, timestamp)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
+    /// Führt eine Operation auf dem Datenbank-Thread aus
+    pub async fn call<F, R>(&self, operation: F) -> DbResult<R>
+    where
+        F: FnOnce(&mut Connection) -> DbResult<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, response) = oneshot::channel();
+        let queued = self.queued.clone();
+        let job: Job = Box::new(move |conn| {
+            queued.fetch_sub(1, Ordering::Relaxed);
+            match panic::catch_unwind(AssertUnwindSafe(|| operation(conn))) {
+                Ok(result) => {
+                    let _ = reply.send(result);
+                }
+                Err(_) => {
+                    error!("Panik in einer Datenbank-Operation");
+                    let _ = reply.send(Err(DatabaseError::Panicked));
+                }
+            }
+        });
+
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if self.jobs.send(job).is_err() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(DatabaseError::Closed);
+        }
+        response.await.map_err(|_| DatabaseError::Closed)?
+    }
+
+    /// Anzahl der Aufträge, die noch auf den Datenbank-Thread warten
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Aktuelle Schema-Version der Datenbank (`PRAGMA user_version`)
+    pub async fn schema_version(&self) -> DbResult<i64> {
+        self.call(|conn| Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?))
+            .await
+    }
+
+    /// Liest ein Profil aus dem Cache
+    pub async fn get_cached_profile(&self, pubkey: &str) -> DbResult<Option<CachedProfile>> {
+        let pubkey = pubkey.to_string();
+        self.call(move |conn| {
+            let profile = conn
+                .prepare_cached(
+                    "SELECT pubkey, name, display_name, nip05, nip05_verified, event_created_at, fetched_at
+                     FROM profile_cache WHERE pubkey = ?1",
+                )?
+                .query_row(params![pubkey], |row| {
+                    Ok(CachedProfile {
+                        pubkey: row.get(0)?,
+                        name: row.get(1)?,
+                        display_name: row.get(2)?,
+                        nip05: row.get(3)?,
+                        nip05_verified: row.get(4)?,
+                        event_created_at: row.get(5)?,
+                        fetched_at: row.get(6)?,
+                    })
+                })
+                .optional()?;
+            Ok(profile)
+        })
+        .await
+    }
+
+    /// Speichert ein Profil im Cache. Ein älteres Kind-0-Event überschreibt nie
+    /// ein neueres; gibt `true` zurück wenn der Eintrag geändert wurde.
+    pub async fn upsert_profile(&self, profile: &CachedProfile) -> DbResult<bool> {
+        let profile = profile.clone();
+        self.call(move |conn| {
+            let changed = conn
+                .prepare_cached(
+                    "INSERT INTO profile_cache
+                     (pubkey, name, display_name, nip05, nip05_verified, event_created_at, fetched_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                     ON CONFLICT(pubkey) DO UPDATE SET
+                        name = excluded.name,
+                        display_name = excluded.display_name,
+                        nip05 = excluded.nip05,
+                        nip05_verified = excluded.nip05_verified,
+                        event_created_at = excluded.event_created_at,
+                        fetched_at = excluded.fetched_at
+                     WHERE excluded.event_created_at >= profile_cache.event_created_at",
+                )?
+                .execute(params![
+                    profile.pubkey,
+                    profile.name,
+                    profile.display_name,
+                    profile.nip05,
+                    profile.nip05_verified,
+                    profile.event_created_at,
+                    profile.fetched_at,
+                ])?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    /// Aktualisiert nur den Abrufzeitpunkt eines Profils (TTL verlängern)
+    pub async fn touch_profile(&self, pubkey: &str, fetched_at: i64) -> DbResult<()> {
+        let pubkey = pubkey.to_string();
+        self.call(move |conn| {
+            conn.prepare_cached("UPDATE profile_cache SET fetched_at = ?2 WHERE pubkey = ?1")?
+                .execute(params![pubkey, fetched_at])?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Setzt den NIP-05-Verifizierungsstatus eines Profils
+    pub async fn set_nip05_verified(&self, pubkey: &str, nip05: &str, verified: bool) -> DbResult<()> {
+        let (pubkey, nip05) = (pubkey.to_string(), nip05.to_string());
+        self.call(move |conn| {
+            // Nur setzen wenn sich der NIP-05-Eintrag inzwischen nicht geändert hat
+            conn.prepare_cached(
+                "UPDATE profile_cache SET nip05_verified = ?3 WHERE pubkey = ?1 AND nip05 = ?2",
+            )?
+            .execute(params![pubkey, nip05, verified])?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Gibt alle Pubkeys im Profil-Cache zurück
+    pub async fn cached_profile_pubkeys(&self) -> DbResult<Vec<String>> {
+        self.call(|conn| {
+            let mut stmt = conn.prepare_cached("SELECT pubkey FROM profile_cache")?;
+            let pubkeys = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<SqlResult<Vec<_>>>()?;
+            Ok(pubkeys)
+        })
+        .await
+    }
+
+    /// Legt eine offene Verknüpfungsanfrage an (ersetzt eine ältere desselben Users)
+    pub async fn create_pending_link(&self, link: &IdentityLink, challenge: &str) -> DbResult<()> {
+        let (link, challenge) = (link.clone(), challenge.to_string());
+        self.call(move |conn| {
+            conn.prepare_cached(
+                "INSERT OR REPLACE INTO pending_links
+                 (telegram_user_id, telegram_username, telegram_name, nostr_pubkey, challenge, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?
+            .execute(params![
+                link.telegram_user_id,
+                link.telegram_username,
+                link.telegram_name,
+                link.nostr_pubkey,
+                challenge,
+                link.linked_at,
+            ])?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Bestätigt eine offene Verknüpfung, wenn der Pubkey den passenden Code
+    /// geschickt hat. Anfragen älter als `not_before` gelten als abgelaufen.
+    pub async fn confirm_pending_link(
+        &self,
+        nostr_pubkey: &str,
+        challenge: &str,
+        not_before: i64,
+        linked_at: i64,
+    ) -> DbResult<Option<IdentityLink>> {
+        let (nostr_pubkey, challenge) = (nostr_pubkey.to_string(), challenge.to_string());
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+
+            let pending = tx
+                .prepare_cached(
+                    "SELECT telegram_user_id, telegram_username, telegram_name FROM pending_links
+                     WHERE nostr_pubkey = ?1 AND challenge = ?2 AND created_at >= ?3",
+                )?
+                .query_row(params![nostr_pubkey, challenge, not_before], |row| {
+                    Ok(IdentityLink {
+                        telegram_user_id: row.get(0)?,
+                        telegram_username: row.get(1)?,
+                        telegram_name: row.get(2)?,
+                        nostr_pubkey: nostr_pubkey.clone(),
+                        linked_at,
+                    })
+                })
+                .optional()?;
+
+            let Some(link) = pending else {
+                return Ok(None);
+            };
+
+            // Ein Pubkey gehört zu genau einem Telegram-User: REPLACE entfernt alte Verknüpfungen
+            tx.prepare_cached(
+                "INSERT OR REPLACE INTO identity_links
+                 (telegram_user_id, telegram_username, telegram_name, nostr_pubkey, linked_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                link.telegram_user_id,
+                link.telegram_username,
+                link.telegram_name,
+                link.nostr_pubkey,
+                link.linked_at,
+            ])?;
+            tx.prepare_cached("DELETE FROM pending_links WHERE telegram_user_id = ?1")?
+                .execute(params![link.telegram_user_id])?;
+            tx.commit()?;
+
+            Ok(Some(link))
+        })
+        .await
+    }
+
+    /// Findet die Verknüpfung eines Telegram-Users
+    pub async fn find_link_by_telegram_user(&self, telegram_user_id: i64) -> DbResult<Option<IdentityLink>> {
+        self.call(move |conn| query_link(conn, "telegram_user_id = ?1", params![telegram_user_id]))
+            .await
+    }
+
+    /// Findet die Verknüpfung eines Nostr-Pubkeys (Hex)
+    pub async fn find_link_by_nostr_pubkey(&self, nostr_pubkey: &str) -> DbResult<Option<IdentityLink>> {
+        let nostr_pubkey = nostr_pubkey.to_string();
+        self.call(move |conn| query_link(conn, "nostr_pubkey = ?1", params![nostr_pubkey]))
+            .await
+    }
+
+    /// Findet die Verknüpfung über den Telegram-Username (ohne @, Groß-/Kleinschreibung egal)
+    pub async fn find_link_by_telegram_username(&self, username: &str) -> DbResult<Option<IdentityLink>> {
+        let username = username.to_string();
+        self.call(move |conn| query_link(conn, "telegram_username = ?1 COLLATE NOCASE", params![username]))
+            .await
+    }
+
+    /// Entfernt Verknüpfung und offene Anfrage eines Telegram-Users
+    pub async fn remove_link(&self, telegram_user_id: i64) -> DbResult<bool> {
+        self.call(move |conn| {
+            let removed = conn
+                .prepare_cached("DELETE FROM identity_links WHERE telegram_user_id = ?1")?
+                .execute(params![telegram_user_id])?;
+            conn.prepare_cached("DELETE FROM pending_links WHERE telegram_user_id = ?1")?
+                .execute(params![telegram_user_id])?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    /// Findet die Puppet-Identität eines Telegram-Users
+    pub async fn get_puppet(&self, telegram_user_id: i64) -> DbResult<Option<Puppet>> {
+        self.call(move |conn| query_puppet(conn, "telegram_user_id = ?1", params![telegram_user_id]))
+            .await
+    }
+
+    /// Findet den Telegram-User zu einem Puppet-Pubkey (Hex)
+    pub async fn find_puppet_by_nostr_pubkey(&self, nostr_pubkey: &str) -> DbResult<Option<Puppet>> {
+        let nostr_pubkey = nostr_pubkey.to_string();
+        self.call(move |conn| query_puppet(conn, "nostr_pubkey = ?1", params![nostr_pubkey]))
+            .await
+    }
+
+    /// Findet einen Puppet über den Telegram-Username (ohne @, Groß-/Kleinschreibung egal)
+    pub async fn find_puppet_by_telegram_username(&self, username: &str) -> DbResult<Option<Puppet>> {
+        let username = username.to_string();
+        self.call(move |conn| query_puppet(conn, "telegram_username = ?1 COLLATE NOCASE", params![username]))
+            .await
+    }
+
+    /// Speichert oder aktualisiert eine Puppet-Identität
+    pub async fn save_puppet(&self, puppet: &Puppet) -> DbResult<()> {
+        let puppet = puppet.clone();
+        self.call(move |conn| {
+            conn.prepare_cached(
+                "INSERT INTO puppets
+                 (telegram_user_id, nostr_pubkey, display_name, telegram_username, profile_published_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(telegram_user_id) DO UPDATE SET
+                    nostr_pubkey = excluded.nostr_pubkey,
+                    display_name = excluded.display_name,
+                    telegram_username = excluded.telegram_username,
+                    profile_published_at = excluded.profile_published_at",
+            )?
+            .execute(params![
+                puppet.telegram_user_id,
+                puppet.nostr_pubkey,
+                puppet.display_name,
+                puppet.telegram_username,
+                puppet.profile_published_at,
+            ])?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Speichert eine Nachricht im Archiv. `tokens` sind die (geblindeten)
+    /// Suchbegriffe für den FTS5-Index, durch Leerzeichen getrennt.
+    pub async fn archive_message(&self, message: &ArchivedMessage, tokens: &str) -> DbResult<i64> {
+        let (message, tokens) = (message.clone(), tokens.to_string());
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+
+            tx.prepare_cached(
+                "INSERT INTO message_archive
+                 (telegram_chat_id, telegram_message_id, nostr_event_id, direction, payload, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?
+            .execute(params![
+                message.telegram_chat_id,
+                message.telegram_message_id,
+                message.nostr_event_id,
+                message.direction.to_string(),
+                message.payload,
+                message.timestamp,
+            ])?;
+            let id = tx.last_insert_rowid();
+
+            tx.prepare_cached("INSERT INTO message_archive_fts (rowid, tokens) VALUES (?1, ?2)")?
+                .execute(params![id, tokens])?;
+            tx.commit()?;
+
+            Ok(id)
+        })
+        .await
+    }
+
+    /// Durchsucht das Archiv mit einer FTS5-Abfrage, neueste Treffer zuerst.
+    /// Mit `telegram_chat_id` werden nur Nachrichten dieses Chats geliefert.
+    pub async fn search_archive(
+        &self,
+        fts_query: &str,
+        telegram_chat_id: Option<i64>,
+        limit: usize,
+    ) -> DbResult<Vec<ArchivedMessage>> {
+        let fts_query = fts_query.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT a.id, a.telegram_chat_id, a.telegram_message_id, a.nostr_event_id,
+                        a.direction, a.payload, a.timestamp
+                 FROM message_archive_fts f
+                 JOIN message_archive a ON a.id = f.rowid
+                 WHERE message_archive_fts MATCH ?1
+                   AND (?2 IS NULL OR a.telegram_chat_id = ?2)
+                 ORDER BY a.timestamp DESC, a.id DESC
+                 LIMIT ?3",
+            )?;
+
+            let rows = stmt.query_map(params![fts_query, telegram_chat_id, limit as i64], |row| {
+                let direction: String = row.get(4)?;
+                Ok(ArchivedMessage {
+                    id: row.get(0)?,
+                    telegram_chat_id: row.get(1)?,
+                    telegram_message_id: row.get(2)?,
+                    nostr_event_id: row.get(3)?,
+                    direction: MessageDirection::from_string(&direction)
+                        .unwrap_or(MessageDirection::TelegramToNostr),
+                    payload: row.get(5)?,
+                    timestamp: row.get(6)?,
+                })
+            })?;
+
+            Ok(rows.collect::<SqlResult<Vec<_>>>()?)
+        })
+        .await
+    }
+
+    /// Archiv-Einträge ohne Eintrag im Suchindex (z.B. nach einem Import)
+    pub async fn unindexed_archive_messages(&self) -> DbResult<Vec<(i64, String)>> {
+        self.call(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT id, payload FROM message_archive
+                 WHERE id NOT IN (SELECT rowid FROM message_archive_fts)",
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            Ok(rows.collect::<SqlResult<Vec<_>>>()?)
+        })
+        .await
+    }
+
+    /// Setzt die Suchbegriffe eines bestehenden Archiv-Eintrags
+    pub async fn index_archive_message(&self, id: i64, tokens: &str) -> DbResult<()> {
+        let tokens = tokens.to_string();
+        self.call(move |conn| {
+            conn.prepare_cached("DELETE FROM message_archive_fts WHERE rowid = ?1")?
+                .execute(params![id])?;
+            conn.prepare_cached("INSERT INTO message_archive_fts (rowid, tokens) VALUES (?1, ?2)")?
+                .execute(params![id, tokens])?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Zeitstempel (`created_at`) des neuesten empfangenen Nostr-Events, ab dem
+    /// der Listener nach einem Neustart oder verpassten Notifications neu abonniert
+    pub async fn nostr_since(&self) -> DbResult<Option<i64>> {
+        self.call(|conn| {
+            let since = conn
+                .prepare_cached("SELECT value FROM bridge_state WHERE key = ?1")?
+                .query_row(params![STATE_NOSTR_SINCE], |row| row.get(0))
+                .optional()?;
+            Ok(since)
+        })
+        .await
+    }
+
+    /// Übernimmt das WAL in die Datenbankdatei (beim Beenden). Als letzter Auftrag
+    /// der Warteschlange läuft er erst, wenn alle vorherigen Schreibvorgänge erledigt sind.
+    pub async fn checkpoint(&self) -> DbResult<()> {
+        self.call(|conn| {
+            let (busy, frames): (i64, i64) = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+            if busy != 0 {
+                warn!("WAL-Checkpoint unvollständig, Datenbank ist noch in Benutzung");
+            } else {
+                debug!("WAL-Checkpoint: {} Seiten übernommen", frames.max(0));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Rückt den Since-Cursor vor; ältere Zeitstempel lassen ihn unverändert
+    pub async fn advance_nostr_since(&self, created_at: i64) -> DbResult<()> {
+        self.call(move |conn| {
+            conn.prepare_cached(
+                "INSERT INTO bridge_state (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = MAX(value, excluded.value)",
+            )?
+            .execute(params![STATE_NOSTR_SINCE, created_at])?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Storage for Database {
+    /// Der Upsert scheitert still an den UNIQUE-Constraints, wenn die Nachricht
+    /// schon einen Eintrag hat – außer einem verfallenen Claim, den er mit neuer
+    /// Claim-Zeit übernimmt
+    async fn claim_telegram_message(&self, chat_id: i64, message_id: i64, timestamp: i64) -> DbResult<Option<i64>> {
+        self.call(move |conn| {
+            let claim_id = conn
+                .prepare_cached(
+                    "INSERT INTO message_mapping
+                     (telegram_chat_id, telegram_message_id, direction, timestamp, status)
+                     VALUES (?1, ?2, ?3, ?4, 'pending')
+                     ON CONFLICT(telegram_chat_id, telegram_message_id) DO UPDATE SET timestamp = excluded.timestamp
+                     WHERE status = 'pending' AND timestamp < ?5
+                     RETURNING id",
+                )?
+                .query_row(
+                    params![
+                        chat_id,
+                        message_id,
+                        MessageDirection::TelegramToNostr.to_string(),
+                        timestamp,
+                        timestamp - CLAIM_TIMEOUT_SECS,
+                    ],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(claim_id)
+        })
+        .await
+    }
+
+    async fn claim_nostr_event(&self, event_id: &str, chat_id: i64, timestamp: i64) -> DbResult<Option<i64>> {
+        let event_id = event_id.to_string();
+        self.call(move |conn| {
+            let claim_id = conn
+                .prepare_cached(
+                    "INSERT INTO message_mapping
+                     (telegram_chat_id, nostr_event_id, direction, timestamp, status)
+                     SELECT ?1, ?2, ?3, ?4, 'pending'
+                     WHERE NOT EXISTS(SELECT 1 FROM pruned_events WHERE hash = ?5)
+                     ON CONFLICT(nostr_event_id) DO UPDATE
+                     SET telegram_chat_id = excluded.telegram_chat_id, timestamp = excluded.timestamp
+                     WHERE status = 'pending' AND timestamp < ?6
+                     RETURNING id",
+                )?
+                .query_row(
+                    params![
+                        chat_id,
+                        event_id,
+                        MessageDirection::NostrToTelegram.to_string(),
+                        timestamp,
+                        event_id_hash(&event_id),
+                        timestamp - CLAIM_TIMEOUT_SECS,
+                    ],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(claim_id)
+        })
+        .await
+    }
+
+    async fn complete_claim(&self, claim_id: i64, mapping: &MessageMapping) -> DbResult<()> {
+        let mapping = mapping.clone();
+        self.call(move |conn| {
+            conn.prepare_cached(
+                "UPDATE message_mapping
+                 SET telegram_chat_id = ?2, telegram_message_id = ?3, telegram_thread_id = ?4,
+                     nostr_event_id = ?5, nostr_recipient_pubkey = ?6, timestamp = ?7, status = 'sent'
+                 WHERE id = ?1 AND status = 'pending'",
+            )?
+            .execute(params![
+                claim_id,
                 mapping.telegram_chat_id,
                 mapping.telegram_message_id,
+                mapping.telegram_thread_id,
                 mapping.nostr_event_id,
                 mapping.nostr_recipient_pubkey,
-                mapping.direction.to_string(),
                 mapping.timestamp,
-            ],
-        )?;
+            ])?;
+            debug!("Claim {} abgeschlossen", claim_id);
+            Ok(())
+        })
+        .await
+    }
 
-        let id = conn.last_insert_rowid();
-        debug!("Mapping gespeichert: ID {}", id);
-        Ok(id)
+    async fn release_claim(&self, claim_id: i64) -> DbResult<()> {
+        self.call(move |conn| {
+            conn.prepare_cached("DELETE FROM message_mapping WHERE id = ?1 AND status = 'pending'")?
+                .execute(params![claim_id])?;
+            Ok(())
+        })
+        .await
     }
 
-    /// Prüft ob eine Telegram-Nachricht bereits verarbeitet wurde (Loop-Schutz)
-    pub fn telegram_message_exists(&self, chat_id: i64, message_id: i64) -> SqlResult<bool> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT COUNT(*) FROM message_mapping 
-             WHERE telegram_chat_id = ?1 AND telegram_message_id = ?2"
-        )?;
+    async fn save_message_parts(&self, chat_id: i64, nostr_event_id: &str, message_ids: &[i64]) -> DbResult<()> {
+        let nostr_event_id = nostr_event_id.to_string();
+        let message_ids = message_ids.to_vec();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut insert = tx.prepare_cached(
+                    "INSERT INTO message_parts (telegram_chat_id, telegram_message_id, nostr_event_id, part)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                // Teil 1 steht im Mapping
+                for (index, message_id) in message_ids.iter().enumerate() {
+                    insert.execute(params![chat_id, message_id, nostr_event_id, index as i64 + 2])?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
 
-        let count: i64 = stmt.query_row(params![chat_id, message_id], |row| row.get(0))?;
-        Ok(count > 0)
+    /// Prüft ob ein Nostr-Event bereits verarbeitet wurde (Loop-Schutz).
+    /// Berücksichtigt auch Events, deren Mapping durch die Retention gelöscht wurde.
+    async fn nostr_event_exists(&self, event_id: &str, now: i64) -> DbResult<bool> {
+        let event_id = event_id.to_string();
+        self.call(move |conn| {
+            let exists = conn
+                .prepare_cached(
+                    "SELECT EXISTS(SELECT 1 FROM message_mapping WHERE nostr_event_id = ?1
+                                   AND (status = 'sent' OR timestamp >= ?3))
+                         OR EXISTS(SELECT 1 FROM pruned_events WHERE hash = ?2)",
+                )?
+                .query_row(params![event_id, event_id_hash(&event_id), now - CLAIM_TIMEOUT_SECS], |row| row.get(0))?;
+            Ok(exists)
+        })
+        .await
     }
 
-    /// Prüft ob ein Nostr-Event bereits verarbeitet wurde (Loop-Schutz)
-    pub fn nostr_event_exists(&self, event_id: &str) -> SqlResult<bool> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT COUNT(*) FROM message_mapping WHERE nostr_event_id = ?1"
-        )?;
+    /// Die Event-IDs gelöschter Mappings landen als Hash in `pruned_events`
+    async fn prune_mappings(&self, max_age_secs: Option<i64>, max_rows: Option<i64>, now: i64) -> DbResult<usize> {
+        if max_age_secs.is_none() && max_rows.is_none() {
+            return Ok(0);
+        }
 
-        let count: i64 = stmt.query_row(params![event_id], |row| row.get(0))?;
-        Ok(count > 0)
-    }
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
 
-    /// Findet das Nostr-Event zu einer Telegram-Nachricht (für Replys)
-    #[allow(dead_code)]
-    pub fn find_nostr_event_by_telegram(
-        &self,
-        chat_id: i64,
-        message_id: i64,
-    ) -> SqlResult<Option<String>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT nostr_event_id FROM message_mapping 
-             WHERE telegram_chat_id = ?1 AND telegram_message_id = ?2"
-        )?;
-
-        let result = stmt.query_row(params![chat_id, message_id], |row| {
-            row.get::<_, String>(0)
-        });
+            let cutoff = max_age_secs.map(|age| now - age).unwrap_or(i64::MIN);
+            // LIMIT -1 bedeutet in SQLite "ohne Begrenzung"
+            let keep = max_rows.unwrap_or(-1);
 
-        match result {
-            Ok(event_id) => Ok(Some(event_id)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e),
-        }
+            // Offene Claims werden gerade gesendet und weder gezählt noch gelöscht
+            let doomed: Vec<(i64, Option<String>, i64)> = {
+                let mut stmt = tx.prepare_cached(
+                    "SELECT id, nostr_event_id, timestamp FROM message_mapping
+                     WHERE status = 'sent'
+                       AND (timestamp < ?1
+                            OR id NOT IN (SELECT id FROM message_mapping WHERE status = 'sent'
+                                          ORDER BY timestamp DESC, id DESC LIMIT ?2))",
+                )?;
+                let rows = stmt.query_map(params![cutoff, keep], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+                rows.collect::<SqlResult<Vec<_>>>()?
+            };
+
+            {
+                let mut remember = tx.prepare_cached(
+                    "INSERT INTO pruned_events (hash, timestamp) VALUES (?1, ?2)
+                     ON CONFLICT(hash) DO UPDATE SET timestamp = max(timestamp, excluded.timestamp)",
+                )?;
+                let mut delete = tx.prepare_cached("DELETE FROM message_mapping WHERE id = ?1")?;
+                let mut delete_parts = tx.prepare_cached("DELETE FROM message_parts WHERE nostr_event_id = ?1")?;
+                for (id, event_id, timestamp) in &doomed {
+                    if let Some(event_id) = event_id {
+                        remember.execute(params![event_id_hash(event_id), timestamp])?;
+                        delete_parts.execute(params![event_id])?;
+                    }
+                    delete.execute(params![id])?;
+                }
+            }
+
+            tx.prepare_cached(
+                "INSERT INTO bridge_state (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = value + excluded.value",
+            )?
+            .execute(params![STATE_PRUNED_ROWS, doomed.len() as i64])?;
+            tx.commit()?;
+
+            if !doomed.is_empty() {
+                debug!("{} Mappings gelöscht (Retention)", doomed.len());
+            }
+            Ok(doomed.len())
+        })
+        .await
     }
 
-    /// Findet die Telegram-Nachricht zu einem Nostr-Event (für Replys)
-    #[allow(dead_code)]
-    pub fn find_telegram_message_by_nostr(
-        &self,
-        event_id: &str,
-    ) -> SqlResult<Option<(i64, i64)>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT telegram_chat_id, telegram_message_id FROM message_mapping 
-             WHERE nostr_event_id = ?1"
-        )?;
-
-        let result = stmt.query_row(params![event_id], |row| {
-            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
-        });
+    async fn forget_pruned_events(&self, before: i64) -> DbResult<usize> {
+        self.call(move |conn| {
+            let forgotten = conn
+                .prepare_cached("DELETE FROM pruned_events WHERE timestamp < ?1")?
+                .execute(params![before])?;
+            Ok(forgotten)
+        })
+        .await
+    }
 
-        match result {
-            Ok(ids) => Ok(Some(ids)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e),
-        }
+    /// Gesamtzahl der durch die Retention gelöschten Mappings
+    async fn get_pruned_count(&self) -> DbResult<i64> {
+        self.call(|conn| {
+            let count = conn
+                .prepare_cached("SELECT value FROM bridge_state WHERE key = ?1")?
+                .query_row(params![STATE_PRUNED_ROWS], |row| row.get(0))
+                .optional()?;
+            Ok(count.unwrap_or(0))
+        })
+        .await
     }
 
     /// Gibt Statistiken über die Datenbank zurück
-    pub fn get_stats(&self) -> SqlResult<(i64, i64, i64)> {
-        let conn = self.conn.lock().unwrap();
-        
-        let total: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM message_mapping",
-            [],
-            |row| row.get(0),
-        )?;
-
-        let telegram_to_nostr: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM message_mapping WHERE direction = 'telegram_to_nostr'",
-            [],
-            |row| row.get(0),
-        )?;
-
-        let nostr_to_telegram: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM message_mapping WHERE direction = 'nostr_to_telegram'",
-            [],
-            |row| row.get(0),
-        )?;
-
-        Ok((total, telegram_to_nostr, nostr_to_telegram))
+    async fn get_stats(&self) -> DbResult<(i64, i64, i64)> {
+        self.call(|conn| {
+            let stats = conn
+                .prepare_cached(
+                    "SELECT COUNT(*),
+                            COUNT(*) FILTER (WHERE direction = 'telegram_to_nostr'),
+                            COUNT(*) FILTER (WHERE direction = 'nostr_to_telegram')
+                     FROM message_mapping WHERE status = 'sent'",
+                )?
+                .query_row([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            Ok(stats)
+        })
+        .await
     }
+
+    async fn check_writable(&self, now: i64) -> DbResult<()> {
+        self.call(move |conn| {
+            conn.prepare_cached(
+                "INSERT INTO bridge_state (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            )?
+            .execute(params![STATE_HEALTH_CHECK, now])?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// WAL erlaubt parallele Leser (z.B. Backup/Export per CLI) während die Bridge schreibt
+fn configure_connection(conn: &Connection) -> SqlResult<()> {
+    let journal_mode: String = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    debug!("SQLite-Journal-Modus: {}", journal_mode);
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok(())
+}
+
+fn query_link(conn: &Connection, condition: &str, params: &[&dyn rusqlite::ToSql]) -> DbResult<Option<IdentityLink>> {
+    let link = conn
+        .prepare_cached(&format!(
+            "SELECT telegram_user_id, telegram_username, telegram_name, nostr_pubkey, linked_at
+             FROM identity_links WHERE {}",
+            condition
+        ))?
+        .query_row(params, |row| {
+            Ok(IdentityLink {
+                telegram_user_id: row.get(0)?,
+                telegram_username: row.get(1)?,
+                telegram_name: row.get(2)?,
+                nostr_pubkey: row.get(3)?,
+                linked_at: row.get(4)?,
+            })
+        })
+        .optional()?;
+    Ok(link)
+}
+
+fn query_puppet(conn: &Connection, condition: &str, params: &[&dyn rusqlite::ToSql]) -> DbResult<Option<Puppet>> {
+    let puppet = conn
+        .prepare_cached(&format!(
+            "SELECT telegram_user_id, nostr_pubkey, display_name, telegram_username, profile_published_at
+             FROM puppets WHERE {}",
+            condition
+        ))?
+        .query_row(params, |row| {
+            Ok(Puppet {
+                telegram_user_id: row.get(0)?,
+                nostr_pubkey: row.get(1)?,
+                display_name: row.get(2)?,
+                telegram_username: row.get(3)?,
+                profile_published_at: row.get(4)?,
+            })
+        })
+        .optional()?;
+    Ok(puppet)
 }
 
 #[cfg(test)]
@@ -229,124 +1107,295 @@ mod tests {
             .as_secs() as i64
     }
 
-    #[test]
-    fn test_database_creation() {
+    crate::storage::conformance::storage_conformance_tests!(async { Box::new(create_test_db()) });
+
+    #[tokio::test]
+    async fn test_database_creation() {
         let db = create_test_db();
-        let stats = db.get_stats().unwrap();
+        let stats = db.get_stats().await.unwrap();
         assert_eq!(stats.0, 0); // Total should be 0
     }
 
-    #[test]
-    fn test_save_and_check_telegram_message() {
+    #[tokio::test]
+    async fn test_nostr_since_only_moves_forward() {
         let db = create_test_db();
-        
-        let mapping = MessageMapping {
-            id: None,
-            telegram_chat_id: -1001234567890,
-            telegram_message_id: 123,
-            nostr_event_id: "abc123".to_string(),
-            nostr_recipient_pubkey: "npub1test".to_string(),
-            direction: MessageDirection::TelegramToNostr,
-            timestamp: get_timestamp(),
-        };
+        assert_eq!(db.nostr_since().await.unwrap(), None);
 
-        db.save_mapping(&mapping).unwrap();
-
-        assert!(db.telegram_message_exists(-1001234567890, 123).unwrap());
-        assert!(!db.telegram_message_exists(-1001234567890, 999).unwrap());
+        db.advance_nostr_since(1_700_000_100).await.unwrap();
+        db.advance_nostr_since(1_700_000_000).await.unwrap();
+        assert_eq!(db.nostr_since().await.unwrap(), Some(1_700_000_100));
     }
 
-    #[test]
-    fn test_save_and_check_nostr_event() {
+    #[tokio::test]
+    async fn test_profile_cache_keeps_newest_event() {
         let db = create_test_db();
-        
-        let mapping = MessageMapping {
-            id: None,
-            telegram_chat_id: -1001234567890,
-            telegram_message_id: 456,
-            nostr_event_id: "def456".to_string(),
-            nostr_recipient_pubkey: "npub1test".to_string(),
-            direction: MessageDirection::NostrToTelegram,
-            timestamp: get_timestamp(),
+
+        let newer = CachedProfile {
+            pubkey: "aa".repeat(32),
+            name: Some("alice".to_string()),
+            display_name: Some("Alice".to_string()),
+            nip05: Some("alice@example.org".to_string()),
+            nip05_verified: false,
+            event_created_at: 2000,
+            fetched_at: get_timestamp(),
         };
+        assert!(db.upsert_profile(&newer).await.unwrap());
 
-        db.save_mapping(&mapping).unwrap();
+        // Älteres Event darf das neuere nicht überschreiben
+        let older = CachedProfile {
+            display_name: Some("Alte Alice".to_string()),
+            event_created_at: 1000,
+            ..newer.clone()
+        };
+        assert!(!db.upsert_profile(&older).await.unwrap());
 
-        assert!(db.nostr_event_exists("def456").unwrap());
-        assert!(!db.nostr_event_exists("xyz999").unwrap());
+        db.set_nip05_verified(&newer.pubkey, "alice@example.org", true).await.unwrap();
+
+        let cached = db.get_cached_profile(&newer.pubkey).await.unwrap().unwrap();
+        assert_eq!(cached.display_name, Some("Alice".to_string()));
+        assert!(cached.nip05_verified);
+        assert_eq!(db.cached_profile_pubkeys().await.unwrap(), vec![newer.pubkey.clone()]);
+        assert!(db.get_cached_profile("unknown").await.unwrap().is_none());
     }
 
-    #[test]
-    fn test_find_mappings() {
+    #[tokio::test]
+    async fn test_identity_link_challenge() {
         let db = create_test_db();
-        
-        let mapping = MessageMapping {
-            id: None,
-            telegram_chat_id: -1001234567890,
-            telegram_message_id: 789,
-            nostr_event_id: "ghi789".to_string(),
-            nostr_recipient_pubkey: "npub1test".to_string(),
-            direction: MessageDirection::TelegramToNostr,
-            timestamp: get_timestamp(),
+
+        let link = IdentityLink {
+            telegram_user_id: 42,
+            telegram_username: Some("alice".to_string()),
+            telegram_name: "Alice".to_string(),
+            nostr_pubkey: "bb".repeat(32),
+            linked_at: 1000,
         };
+        db.create_pending_link(&link, "tg-link-1234").await.unwrap();
 
-        db.save_mapping(&mapping).unwrap();
+        // Falscher Code oder abgelaufene Anfrage bestätigen nichts
+        assert!(db.confirm_pending_link(&link.nostr_pubkey, "tg-link-0000", 0, 2000).await.unwrap().is_none());
+        assert!(db.confirm_pending_link(&link.nostr_pubkey, "tg-link-1234", 1500, 2000).await.unwrap().is_none());
+        assert!(db.find_link_by_telegram_user(42).await.unwrap().is_none());
 
-        // Test Telegram -> Nostr lookup
-        let nostr_event = db.find_nostr_event_by_telegram(-1001234567890, 789).unwrap();
-        assert_eq!(nostr_event, Some("ghi789".to_string()));
+        let confirmed = db.confirm_pending_link(&link.nostr_pubkey, "tg-link-1234", 0, 2000).await.unwrap();
+        assert_eq!(confirmed.map(|l| l.telegram_user_id), Some(42));
+        assert_eq!(
+            db.find_link_by_nostr_pubkey(&link.nostr_pubkey).await.unwrap().map(|l| l.telegram_username),
+            Some(Some("alice".to_string()))
+        );
 
-        // Test Nostr -> Telegram lookup
-        let telegram_msg = db.find_telegram_message_by_nostr("ghi789").unwrap();
-        assert_eq!(telegram_msg, Some((-1001234567890, 789)));
+        // Code ist nach der Bestätigung verbraucht
+        assert!(db.confirm_pending_link(&link.nostr_pubkey, "tg-link-1234", 0, 3000).await.unwrap().is_none());
+
+        assert!(db.remove_link(42).await.unwrap());
+        assert!(db.find_link_by_telegram_user(42).await.unwrap().is_none());
+    }
+
+    /// Temporäre Datenbank-Datei, die beim Drop gelöscht wird
+    struct TempDbFile(std::path::PathBuf);
+
+    impl TempDbFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("bridge-test-{}-{}.db", std::process::id(), name));
+            let _ = std::fs::remove_file(&path);
+            TempDbFile(path)
+        }
+    }
+
+    impl Drop for TempDbFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            // WAL-Modus legt Begleitdateien an
+            for suffix in ["-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_truncates_wal() {
+        let file = TempDbFile::new("checkpoint");
+        let db = Database::new(&file.0).unwrap();
+        db.advance_nostr_since(1_700_000_000).await.unwrap();
+
+        let mut wal = file.0.clone().into_os_string();
+        wal.push("-wal");
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+
+        db.checkpoint().await.unwrap();
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+        assert_eq!(db.nostr_since().await.unwrap(), Some(1_700_000_000));
+    }
+
+    #[tokio::test]
+    async fn test_migration_from_baseline_schema() {
+        let file = TempDbFile::new("baseline");
+
+        // bridge.db wie von der Version vor den Migrationen angelegt (user_version = 0)
+        {
+            let conn = Connection::open(&file.0).unwrap();
+            conn.execute_batch(include_str!("../tests/fixtures/baseline_schema.sql")).unwrap();
+        }
+
+        let db = Database::new(&file.0).unwrap();
+        assert_eq!(db.schema_version().await.unwrap(), MIGRATIONS.last().unwrap().version);
+
+        // Bestehende Mappings bleiben erhalten
+        let (total, t_to_n, n_to_t) = db.get_stats().await.unwrap();
+        assert_eq!((total, t_to_n, n_to_t), (2, 1, 1));
+        assert!(db.nostr_event_exists("baseline-event-1", get_timestamp()).await.unwrap());
+        assert!(db.claim_telegram_message(-1001234567890, 1, get_timestamp()).await.unwrap().is_none());
+
+        // Neue Tabellen sind nutzbar
+        assert!(db.get_cached_profile("unknown").await.unwrap().is_none());
+        assert!(db.find_link_by_telegram_user(1).await.unwrap().is_none());
+        assert!(db.get_puppet(1).await.unwrap().is_none());
+        drop(db);
+
+        // Erneutes Öffnen ist ein No-Op
+        let db = Database::new(&file.0).unwrap();
+        assert_eq!(db.get_stats().await.unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_refuses_newer_schema() {
+        let file = TempDbFile::new("newer");
+        let newer = MIGRATIONS.last().unwrap().version + 1;
+        {
+            let conn = Connection::open(&file.0).unwrap();
+            conn.pragma_update(None, "user_version", newer).unwrap();
+        }
+
+        match Database::new(&file.0) {
+            Err(DatabaseError::SchemaTooNew { found, .. }) => assert_eq!(found, newer),
+            other => panic!("SchemaTooNew erwartet, erhalten: {:?}", other.err()),
+        }
     }
 
     #[test]
-    fn test_duplicate_prevention() {
+    fn test_failed_migration_is_rolled_back() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let migrations = [
+            Migration { version: 1, description: "ok", sql: "CREATE TABLE a (x INTEGER);" },
+            Migration { version: 2, description: "kaputt", sql: "CREATE TABLE b (x INTEGER); SELEC kaputt;" },
+        ];
+
+        assert!(run_migrations(&mut conn, &migrations).is_err());
+
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, 1);
+        let b_exists: i64 = conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = 'b'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(b_exists, 0);
+    }
+
+    #[tokio::test]
+    async fn test_archive_search() {
         let db = create_test_db();
-        
-        let mapping = MessageMapping {
+
+        let message = |id: i64, chat: i64, timestamp: i64| ArchivedMessage {
             id: None,
-            telegram_chat_id: -1001234567890,
-            telegram_message_id: 111,
-            nostr_event_id: "unique123".to_string(),
-            nostr_recipient_pubkey: "npub1test".to_string(),
-            direction: MessageDirection::TelegramToNostr,
-            timestamp: get_timestamp(),
+            telegram_chat_id: chat,
+            telegram_message_id: id,
+            nostr_event_id: format!("archive-event{}", id),
+            direction: MessageDirection::NostrToTelegram,
+            payload: format!("verschluesselt{}", id),
+            timestamp,
         };
 
-        db.save_mapping(&mapping).unwrap();
+        db.archive_message(&message(1, -100, 1000), "aaa bbb").await.unwrap();
+        db.archive_message(&message(2, -100, 2000), "bbb ccc").await.unwrap();
+        db.archive_message(&message(3, -200, 3000), "bbb").await.unwrap();
+
+        let hits = db.search_archive("\"bbb\"", Some(-100), 10).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].telegram_message_id, 2);
+        assert_eq!(hits[0].payload, "verschluesselt2");
+        assert_eq!(hits[0].direction, MessageDirection::NostrToTelegram);
 
-        // Try to save duplicate - should fail
-        let result = db.save_mapping(&mapping);
-        assert!(result.is_err());
+        // Mehrere Begriffe = UND-Verknüpfung
+        let hits = db.search_archive("\"aaa\" \"bbb\"", None, 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].telegram_message_id, 1);
+
+        assert_eq!(db.search_archive("\"bbb\"", None, 1).await.unwrap().len(), 1);
+        assert!(db.search_archive("\"ddd\"", None, 10).await.unwrap().is_empty());
     }
 
-    #[test]
-    fn test_statistics() {
+    #[tokio::test]
+    async fn test_panic_in_operation_keeps_connection_usable() {
         let db = create_test_db();
-        
-        // Add some mappings
-        for i in 0..5 {
-            let mapping = MessageMapping {
-                id: None,
-                telegram_chat_id: -1001234567890,
-                telegram_message_id: i,
-                nostr_event_id: format!("event{}", i),
-                nostr_recipient_pubkey: "npub1test".to_string(),
-                direction: if i % 2 == 0 {
-                    MessageDirection::TelegramToNostr
-                } else {
-                    MessageDirection::NostrToTelegram
-                },
-                timestamp: get_timestamp(),
-            };
-            db.save_mapping(&mapping).unwrap();
+
+        let result: DbResult<()> = db.call(|_| panic!("Absichtliche Panik")).await;
+        assert!(matches!(result, Err(DatabaseError::Panicked)));
+
+        // Kein vergifteter Lock: die Verbindung arbeitet weiter
+        assert_eq!(db.get_stats().await.unwrap(), (0, 0, 0));
+    }
+
+    /// Durchsatz bei parallelem Bridging über den Claim-Pfad der Bridge:
+    /// abwechselnd Telegram → Nostr (Claim, Abschluss) und Nostr → Telegram
+    /// (Loop-Schutz, Claim, Abschluss).
+    /// Ausführen mit: cargo test --release -- --ignored bench_concurrent_bridging --nocapture
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn bench_concurrent_bridging() {
+        const TASKS: i64 = 8;
+        const MESSAGES_PER_TASK: i64 = 2_000;
+
+        let file = TempDbFile::new("bench");
+        let db = std::sync::Arc::new(Database::new(&file.0).unwrap());
+        let start = std::time::Instant::now();
+
+        let handles: Vec<_> = (0..TASKS)
+            .map(|task| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for i in 0..MESSAGES_PER_TASK {
+                        let message_id = task * MESSAGES_PER_TASK + i;
+                        let event_id = format!("bench-event-{}", message_id);
+                        let now = get_timestamp();
+
+                        let (claim_id, direction) = if i % 2 == 0 {
+                            let claim_id = db.claim_telegram_message(-100, message_id, now).await.unwrap();
+                            (claim_id, MessageDirection::TelegramToNostr)
+                        } else {
+                            assert!(!db.nostr_event_exists(&event_id, now).await.unwrap());
+                            let claim_id = db.claim_nostr_event(&event_id, -100, now).await.unwrap();
+                            (claim_id, MessageDirection::NostrToTelegram)
+                        };
+
+                        db.complete_claim(claim_id.unwrap(), &MessageMapping {
+                            id: None,
+                            telegram_chat_id: -100,
+                            telegram_message_id: message_id,
+                            telegram_thread_id: None,
+                            nostr_event_id: event_id,
+                            nostr_recipient_pubkey: "npub1bench".to_string(),
+                            direction,
+                            timestamp: now,
+                        }).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.await.unwrap();
         }
 
-        let (total, t_to_n, n_to_t) = db.get_stats().unwrap();
-        assert_eq!(total, 5);
-        assert_eq!(t_to_n, 3); // 0, 2, 4
-        assert_eq!(n_to_t, 2); // 1, 3
+        let elapsed = start.elapsed();
+        let total = TASKS * MESSAGES_PER_TASK;
+        assert_eq!(db.get_stats().await.unwrap().0, total);
+        println!(
+            "{} Nachrichten in {:.2?} ({:.0} Nachrichten/s, {} Tasks)",
+            total,
+            elapsed,
+            total as f64 / elapsed.as_secs_f64(),
+            TASKS
+        );
     }
 }
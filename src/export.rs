@@ -0,0 +1,406 @@
+use tracing::{debug, info};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::backup::Backup;
+use rusqlite::{params_from_iter, Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::database::{Database, DatabaseError};
+use crate::unix_now;
+
+/// Kennung in der Kopfzeile eines Export-Archivs
+pub const EXPORT_FORMAT: &str = "nostr-telegram-bridge-export";
+
+/// Version des JSONL-Formats (nicht des Datenbank-Schemas)
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// Eine exportierte Tabelle
+pub struct ExportTable {
+    pub name: &'static str,
+    /// Die `id`-Spalte ist ein lokaler Autoincrement-Schlüssel und wird beim
+    /// Import neu vergeben, damit sich Archive in bestehende Datenbanken mischen lassen
+    pub generated_id: bool,
+    /// Konflikte werden immer übersprungen, auch mit `ConflictPolicy::Replace`
+    pub always_skip_conflicts: bool,
+}
+
+/// Alle Tabellen mit Bridge-Zustand, in Import-Reihenfolge. Neue Tabellen hier
+/// ergänzen; der FTS5-Suchindex des Archivs fehlt bewusst, er wird nach dem
+/// Import aus dem Archiv neu aufgebaut.
+pub const EXPORT_TABLES: &[ExportTable] = &[
+    ExportTable { name: "message_mapping", generated_id: true, always_skip_conflicts: false },
+    ExportTable { name: "message_parts", generated_id: false, always_skip_conflicts: false },
+    ExportTable { name: "pruned_events", generated_id: false, always_skip_conflicts: true },
+    ExportTable { name: "bridge_state", generated_id: false, always_skip_conflicts: false },
+    ExportTable { name: "profile_cache", generated_id: false, always_skip_conflicts: false },
+    ExportTable { name: "identity_links", generated_id: false, always_skip_conflicts: false },
+    ExportTable { name: "pending_links", generated_id: false, always_skip_conflicts: false },
+    ExportTable { name: "puppets", generated_id: false, always_skip_conflicts: false },
+    ExportTable { name: "message_archive", generated_id: true, always_skip_conflicts: true },
+];
+
+/// Kopfzeile eines Export-Archivs
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub format: String,
+    pub version: u32,
+    pub schema_version: i64,
+    pub exported_at: i64,
+}
+
+/// Eine Datenzeile im Export-Archiv
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportRecord {
+    table: String,
+    row: Map<String, JsonValue>,
+}
+
+/// Verhalten bei Konflikten mit vorhandenen Zeilen (z.B. `UNIQUE(nostr_event_id)`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    /// Vorhandene Zeilen behalten
+    Skip,
+    /// Vorhandene Zeilen durch die importierten ersetzen
+    Replace,
+}
+
+/// Ergebnis eines Imports
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportStats {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+/// Schreibt alle Tabellen als versioniertes JSONL-Archiv. Der Export läuft in
+/// einer Lese-Transaktion und ist damit auch bei laufender Bridge konsistent.
+/// Gibt die Anzahl exportierter Zeilen und den Writer zurück.
+pub async fn export<W: Write + Send + 'static>(db: &Database, writer: W) -> Result<(usize, W), DatabaseError> {
+    db.call(move |conn| export_to(conn, writer)).await
+}
+
+fn export_to<W: Write>(conn: &mut Connection, mut writer: W) -> Result<(usize, W), DatabaseError> {
+    let tx = conn.transaction()?;
+
+    let header = ExportHeader {
+        format: EXPORT_FORMAT.to_string(),
+        version: EXPORT_FORMAT_VERSION,
+        schema_version: tx.pragma_query_value(None, "user_version", |row| row.get(0))?,
+        exported_at: unix_now(),
+    };
+    serde_json::to_writer(&mut writer, &header)?;
+    writeln!(writer)?;
+
+    let mut total = 0;
+    for table in EXPORT_TABLES {
+        let mut stmt = tx.prepare(&format!("SELECT * FROM {}", table.name))?;
+        let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+        let mut rows = stmt.query([])?;
+
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let mut map = Map::new();
+            for (i, column) in columns.iter().enumerate() {
+                map.insert(column.clone(), to_json(row.get_ref(i)?, table.name, column)?);
+            }
+
+            let record = ExportRecord { table: table.name.to_string(), row: map };
+            serde_json::to_writer(&mut writer, &record)?;
+            writeln!(writer)?;
+            count += 1;
+        }
+
+        debug!("Export: {} Zeilen aus {}", count, table.name);
+        total += count;
+    }
+
+    writer.flush()?;
+    Ok((total, writer))
+}
+
+/// Importiert ein JSONL-Archiv in einer einzigen Transaktion: bei einem Fehler
+/// bleibt die Datenbank unverändert.
+pub async fn import<R: BufRead + Send + 'static>(
+    db: &Database,
+    reader: R,
+    policy: ConflictPolicy,
+) -> Result<ImportStats, DatabaseError> {
+    db.call(move |conn| import_from(conn, reader, policy)).await
+}
+
+fn import_from<R: BufRead>(conn: &mut Connection, reader: R, policy: ConflictPolicy) -> Result<ImportStats, DatabaseError> {
+    let mut lines = reader.lines();
+
+    let header: ExportHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(DatabaseError::InvalidExport("Archiv ist leer".to_string())),
+    };
+    if header.format != EXPORT_FORMAT {
+        return Err(DatabaseError::InvalidExport(format!("Unbekanntes Format: {}", header.format)));
+    }
+    if header.version != EXPORT_FORMAT_VERSION {
+        return Err(DatabaseError::InvalidExport(format!(
+            "Format-Version {} wird nicht unterstützt (erwartet {})",
+            header.version, EXPORT_FORMAT_VERSION
+        )));
+    }
+
+    let schema_version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if header.schema_version > schema_version {
+        return Err(DatabaseError::SchemaTooNew { found: header.schema_version, supported: schema_version });
+    }
+
+    let tx = conn.transaction()?;
+    let mut stats = ImportStats::default();
+
+    for (number, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: ExportRecord = serde_json::from_str(&line)?;
+        let table = EXPORT_TABLES
+            .iter()
+            .find(|t| t.name == record.table)
+            .ok_or_else(|| DatabaseError::InvalidExport(format!(
+                "Zeile {}: unbekannte Tabelle {}", number + 2, record.table
+            )))?;
+
+        if insert_row(&tx, table, record.row, policy)? {
+            stats.imported += 1;
+        } else {
+            stats.skipped += 1;
+        }
+    }
+
+    tx.commit()?;
+    info!("📥 Import: {} Zeilen übernommen, {} Konflikte übersprungen", stats.imported, stats.skipped);
+    Ok(stats)
+}
+
+/// Fügt eine Zeile ein; `false` wenn sie wegen eines Konflikts übersprungen wurde
+fn insert_row(
+    conn: &Connection,
+    table: &ExportTable,
+    mut row: Map<String, JsonValue>,
+    policy: ConflictPolicy,
+) -> Result<bool, DatabaseError> {
+    if table.generated_id {
+        row.remove("id");
+    }
+
+    // Spaltennamen stammen aus dem Archiv und werden gegen das Schema geprüft
+    let known = table_columns(conn, table.name)?;
+    if let Some(unknown) = row.keys().find(|column| !known.contains(*column)) {
+        return Err(DatabaseError::InvalidExport(format!(
+            "Unbekannte Spalte {}.{}", table.name, unknown
+        )));
+    }
+
+    let verb = match policy {
+        ConflictPolicy::Replace if !table.always_skip_conflicts => "INSERT OR REPLACE",
+        _ => "INSERT OR IGNORE",
+    };
+    let columns: Vec<&str> = row.keys().map(|c| c.as_str()).collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+    let sql = format!(
+        "{} INTO {} ({}) VALUES ({})",
+        verb,
+        table.name,
+        columns.join(", "),
+        placeholders.join(", ")
+    );
+
+    let values = row.values().map(from_json).collect::<Result<Vec<Value>, _>>()?;
+    let changed = conn.prepare_cached(&sql)?.execute(params_from_iter(values))?;
+    Ok(changed > 0)
+}
+
+fn table_columns(conn: &Connection, table: &str) -> Result<HashSet<String>, DatabaseError> {
+    let mut stmt = conn.prepare_cached(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    Ok(columns.collect::<rusqlite::Result<_>>()?)
+}
+
+fn to_json(value: ValueRef<'_>, table: &str, column: &str) -> Result<JsonValue, DatabaseError> {
+    Ok(match value {
+        ValueRef::Null => JsonValue::Null,
+        ValueRef::Integer(i) => JsonValue::from(i),
+        ValueRef::Real(f) => JsonValue::from(f),
+        ValueRef::Text(t) => JsonValue::from(String::from_utf8_lossy(t).into_owned()),
+        ValueRef::Blob(_) => {
+            return Err(DatabaseError::InvalidExport(format!(
+                "Binärdaten in {}.{} werden nicht unterstützt", table, column
+            )))
+        }
+    })
+}
+
+fn from_json(value: &JsonValue) -> Result<Value, DatabaseError> {
+    match value {
+        JsonValue::Null => Ok(Value::Null),
+        JsonValue::Bool(b) => Ok(Value::Integer(*b as i64)),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => Ok(Value::Integer(i)),
+            None => Ok(Value::Real(n.as_f64().unwrap_or_default())),
+        },
+        JsonValue::String(s) => Ok(Value::Text(s.clone())),
+        other => Err(DatabaseError::InvalidExport(format!("Ungültiger Wert: {}", other))),
+    }
+}
+
+/// Seiten pro Kopierschritt der Backup-API
+const BACKUP_PAGES_PER_STEP: i32 = 256;
+
+/// Pause zwischen zwei Kopierschritten
+const BACKUP_STEP_PAUSE: Duration = Duration::from_millis(10);
+
+/// Erstellt mit der SQLite-Backup-API eine konsistente Kopie der Datenbank,
+/// auch während die Bridge läuft. Kopiert wird in Schritten, zwischen denen
+/// die Sperren freigegeben werden; schreibt die Bridge währenddessen, startet
+/// SQLite die Kopie automatisch neu. Die Quelle wird nur lesend geöffnet:
+/// keine Migrationen, und sie darf auf einem schreibgeschützten Mount liegen.
+pub fn backup<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> Result<(), DatabaseError> {
+    let source = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut copy = Connection::open(&target)?;
+
+    let backup = Backup::new(&source, &mut copy)?;
+    backup.run_to_completion(BACKUP_PAGES_PER_STEP, BACKUP_STEP_PAUSE, None)?;
+
+    info!("💾 Backup erstellt: {}", target.as_ref().display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{MessageDirection, MessageMapping};
+    use crate::storage::Storage;
+    use rusqlite::OptionalExtension;
+    use std::io::Cursor;
+
+    /// Leitet eine Telegram-Nachricht wie die Bridge weiter (Claim, Abschluss)
+    async fn bridge_message(db: &Database, event: &str, message_id: i64) {
+        let timestamp = 1000 + message_id;
+        let claim_id = db.claim_telegram_message(-100, message_id, timestamp).await.unwrap().unwrap();
+        let mapping = MessageMapping {
+            id: None,
+            telegram_chat_id: -100,
+            telegram_message_id: message_id,
+            telegram_thread_id: None,
+            nostr_event_id: event.to_string(),
+            nostr_recipient_pubkey: "npub1test".to_string(),
+            direction: MessageDirection::TelegramToNostr,
+            timestamp,
+        };
+        db.complete_claim(claim_id, &mapping).await.unwrap();
+    }
+
+    /// Telegram-Nachricht des Mappings zu `event`
+    async fn message_of(db: &Database, event: &'static str) -> Option<i64> {
+        db.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT telegram_message_id FROM message_mapping WHERE nostr_event_id = ?1",
+                    [event],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_import_roundtrip_with_conflicts() {
+        let source = Database::new(":memory:").unwrap();
+        bridge_message(&source, "event-a", 1).await;
+        bridge_message(&source, "event-b", 2).await;
+        source.prune_mappings(None, Some(1), 2000).await.unwrap();
+
+        let (rows, archive) = export(&source, Vec::new()).await.unwrap();
+        // 1 Mapping, 1 gelöschtes Event, 1 Zähler in bridge_state
+        assert_eq!(rows, 3);
+
+        let text = String::from_utf8(archive.clone()).unwrap();
+        let header: ExportHeader = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+        assert_eq!(header.format, EXPORT_FORMAT);
+        assert_eq!(header.schema_version, source.schema_version().await.unwrap());
+
+        // Ziel enthält bereits event-b unter anderer Telegram-ID
+        let target = Database::new(":memory:").unwrap();
+        bridge_message(&target, "event-b", 99).await;
+
+        let stats = import(&target, Cursor::new(archive.clone()), ConflictPolicy::Skip).await.unwrap();
+        assert_eq!(stats, ImportStats { imported: 2, skipped: 1 });
+        assert_eq!(message_of(&target, "event-b").await, Some(99));
+        assert!(target.nostr_event_exists("event-a", 2000).await.unwrap());
+        assert_eq!(target.get_pruned_count().await.unwrap(), 1);
+
+        let stats = import(&target, Cursor::new(archive), ConflictPolicy::Replace).await.unwrap();
+        assert_eq!(stats.imported, 2);
+        assert_eq!(message_of(&target, "event-b").await, Some(2));
+        assert_eq!(target.get_stats().await.unwrap().0, 1);
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_invalid_archives() {
+        let db = Database::new(":memory:").unwrap();
+
+        let wrong_format = r#"{"format":"other","version":1,"schema_version":1,"exported_at":0}"#;
+        assert!(import(&db, Cursor::new(wrong_format), ConflictPolicy::Skip).await.is_err());
+
+        let newer_schema = r#"{"format":"nostr-telegram-bridge-export","version":1,"schema_version":9999,"exported_at":0}"#;
+        assert!(matches!(
+            import(&db, Cursor::new(newer_schema), ConflictPolicy::Skip).await,
+            Err(DatabaseError::SchemaTooNew { .. })
+        ));
+
+        // Unbekannte Spalte: nichts wird übernommen, auch nicht die gültige erste Zeile
+        let bad_column = format!(
+            "{}\n{}\n{}",
+            r#"{"format":"nostr-telegram-bridge-export","version":1,"schema_version":1,"exported_at":0}"#,
+            r#"{"table":"pruned_events","row":{"hash":42}}"#,
+            r#"{"table":"pruned_events","row":{"hash":43,"evil":"1); DROP TABLE x; --"}}"#,
+        );
+        assert!(import(&db, Cursor::new(bad_column), ConflictPolicy::Skip).await.is_err());
+        let pruned: i64 = db
+            .call(|conn| Ok(conn.query_row("SELECT COUNT(*) FROM pruned_events", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(pruned, 0);
+    }
+
+    #[tokio::test]
+    async fn test_backup_creates_readable_copy() {
+        let dir = std::env::temp_dir();
+        let source = dir.join(format!("bridge-backup-source-{}.db", std::process::id()));
+        let path = dir.join(format!("bridge-backup-test-{}.db", std::process::id()));
+        let missing = dir.join(format!("bridge-backup-missing-{}.db", std::process::id()));
+        for file in [&source, &path, &missing] {
+            let _ = std::fs::remove_file(file);
+        }
+
+        // Die Quelle bleibt wie bei laufender Bridge geöffnet
+        let db = Database::new(&source).unwrap();
+        bridge_message(&db, "event-backup", 1).await;
+        backup(&source, &path).unwrap();
+
+        let copy = Database::new(&path).unwrap();
+        assert!(copy.nostr_event_exists("event-backup", 2000).await.unwrap());
+        assert_eq!(copy.schema_version().await.unwrap(), db.schema_version().await.unwrap());
+
+        // Eine fehlende Quelle wird nicht als leere Datenbank angelegt
+        drop(copy);
+        std::fs::remove_file(&path).unwrap();
+        assert!(backup(&missing, &path).is_err());
+        assert!(!missing.exists());
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_file(&source);
+    }
+}
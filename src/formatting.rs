@@ -0,0 +1,1214 @@
+use std::ops::Range;
+use teloxide::types::{MessageEntity, MessageEntityKind, MessageEntityRef};
+
+/// Formatierter Text als Baum – Zwischenformat zwischen Telegram-Entities,
+/// Markdown (Nostr) und Telegram-HTML
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    Bold(Vec<Node>),
+    Italic(Vec<Node>),
+    Strike(Vec<Node>),
+    Spoiler(Vec<Node>),
+    Link { url: String, children: Vec<Node> },
+    Code(String),
+    Pre { language: Option<String>, code: String },
+}
+
+/// Wandelt Telegram-Text mit Entities in Markdown für Nostr um:
+/// `**fett**`, `*kursiv*`, `~~durchgestrichen~~`, `||Spoiler||`, `[Text](URL)`,
+/// `` `Code` `` und Codeblöcke mit ```` ``` ````. `replacements` ersetzt Byte-Bereiche
+/// des Textes (z.B. Erwähnungen) unverändert. Ohne Formatierung bleibt der Text
+/// unverändert, sonst werden Markdown-Sonderzeichen mit `\` maskiert.
+pub fn telegram_to_markdown(text: &str, entities: &[MessageEntity], replacements: &[(Range<usize>, String)]) -> String {
+    to_markdown(&from_telegram(text, entities, replacements))
+}
+
+/// Wandelt Markdown aus Nostr-Nachrichten in Telegram-HTML (`ParseMode::Html`) um,
+/// ohne Aufteilung (im Betrieb über `split_markdown_for_telegram`)
+#[cfg(test)]
+pub fn markdown_to_telegram_html(markdown: &str) -> String {
+    let mut html = String::with_capacity(markdown.len());
+    render_html(&parse_markdown(markdown), &mut html, false);
+    html
+}
+
+/// Maskiert Text, der in fertiges Markdown eingefügt wird (z.B. Profilnamen),
+/// damit er keine Formatierung oder Links erzeugt
+pub fn escape_markdown_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    escape_markdown(text, &mut escaped);
+    escaped
+}
+
+/// Maskiert Text für Telegram-HTML
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// ---------------------------------------------------------------------------
+// Telegram-Entities → Baum
+// ---------------------------------------------------------------------------
+
+/// Baut den Baum aus Telegram-Entities. Unterstrichen und automatisch erkannte
+/// Links, Hashtags usw. haben in Markdown keine Entsprechung und bleiben Text.
+pub fn from_telegram(text: &str, entities: &[MessageEntity], replacements: &[(Range<usize>, String)]) -> Vec<Node> {
+    let mut spans: Vec<(Range<usize>, &MessageEntityKind)> = MessageEntityRef::parse(text, entities)
+        .into_iter()
+        .filter(|entity| is_formatting(entity.kind()))
+        .map(|entity| (entity.range(), entity.kind()))
+        .collect();
+    // Äußere Entities vor inneren, damit verschachtelte Bereiche direkt folgen;
+    // Code kann nichts enthalten und kommt bei gleichem Bereich zuletzt
+    spans.sort_by_key(|(range, kind)| {
+        let is_code = matches!(kind, MessageEntityKind::Code | MessageEntityKind::Pre { .. });
+        (range.start, std::cmp::Reverse(range.end), is_code)
+    });
+
+    let mut replacements = replacements.to_vec();
+    replacements.sort_by_key(|(range, _)| range.start);
+
+    let mut builder = TreeBuilder { text, replacements: &replacements, skip_until: 0 };
+    builder.build(0..text.len(), &spans)
+}
+
+fn is_formatting(kind: &MessageEntityKind) -> bool {
+    matches!(
+        kind,
+        MessageEntityKind::Bold
+            | MessageEntityKind::Italic
+            | MessageEntityKind::Strikethrough
+            | MessageEntityKind::Spoiler
+            | MessageEntityKind::TextLink { .. }
+            | MessageEntityKind::Code
+            | MessageEntityKind::Pre { .. }
+    )
+}
+
+struct TreeBuilder<'a> {
+    text: &'a str,
+    replacements: &'a [(Range<usize>, String)],
+    /// Ende der zuletzt eingesetzten Ersetzung
+    skip_until: usize,
+}
+
+impl TreeBuilder<'_> {
+    fn build(&mut self, range: Range<usize>, spans: &[(Range<usize>, &MessageEntityKind)]) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut pos = range.start;
+        let mut i = 0;
+
+        while i < spans.len() {
+            let (span, kind) = &spans[i];
+            // Überlappende Entities werden am Rand des äußeren Bereichs abgeschnitten
+            let start = span.start.max(pos);
+            let end = span.end.min(range.end);
+            let mut nested_end = i + 1;
+            while nested_end < spans.len() && spans[nested_end].0.start < end {
+                nested_end += 1;
+            }
+
+            if start < end {
+                self.push_text(&mut nodes, pos..start);
+                let nested = &spans[i + 1..nested_end];
+                match kind {
+                    MessageEntityKind::Code => nodes.push(Node::Code(self.text[start..end].to_string())),
+                    MessageEntityKind::Pre { language } => nodes.push(Node::Pre {
+                        language: language.clone().filter(|l| !l.is_empty()),
+                        code: self.text[start..end].to_string(),
+                    }),
+                    MessageEntityKind::Bold => nodes.push(Node::Bold(self.build(start..end, nested))),
+                    MessageEntityKind::Italic => nodes.push(Node::Italic(self.build(start..end, nested))),
+                    MessageEntityKind::Strikethrough => nodes.push(Node::Strike(self.build(start..end, nested))),
+                    MessageEntityKind::Spoiler => nodes.push(Node::Spoiler(self.build(start..end, nested))),
+                    MessageEntityKind::TextLink { url } => nodes.push(Node::Link {
+                        url: url.to_string(),
+                        children: self.build(start..end, nested),
+                    }),
+                    _ => nodes.extend(self.build(start..end, nested)),
+                }
+                pos = end;
+            }
+            i = nested_end;
+        }
+
+        self.push_text(&mut nodes, pos..range.end);
+        nodes
+    }
+
+    fn push_text(&mut self, nodes: &mut Vec<Node>, range: Range<usize>) {
+        let mut pos = range.start.max(self.skip_until);
+        for (replaced, replacement) in self.replacements {
+            if replaced.start < pos || replaced.start >= range.end {
+                continue;
+            }
+            push_text(nodes, &self.text[pos..replaced.start]);
+            push_text(nodes, replacement);
+            pos = replaced.end;
+            self.skip_until = replaced.end;
+        }
+        if pos < range.end {
+            push_text(nodes, &self.text[pos..range.end]);
+        }
+    }
+}
+
+/// Hängt Text an und verschmilzt ihn mit einem direkt vorangehenden Textknoten
+fn push_text(nodes: &mut Vec<Node>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match nodes.last_mut() {
+        Some(Node::Text(last)) => last.push_str(text),
+        _ => nodes.push(Node::Text(text.to_string())),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Baum → Markdown
+// ---------------------------------------------------------------------------
+
+/// Aktive Formatierungen beim Normalisieren; doppelt verschachtelte werden aufgelöst
+#[derive(Debug, Clone, Copy, Default)]
+struct Active {
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    spoiler: bool,
+    link: bool,
+}
+
+impl Active {
+    fn is_inline(&self) -> bool {
+        self.bold || self.italic || self.strike || self.spoiler || self.link
+    }
+}
+
+/// Höchstzahl an Hervorhebungen pro Knoten, für die alle `*`/`_`-Varianten probiert werden
+const MAX_MARKER_VARIANTS_BITS: u32 = 6;
+
+/// Rendert den Baum als Markdown. Hervorhebungen mit `*` und `_` sind nicht immer
+/// eindeutig (`***a**b*`); jeder Knoten der obersten Ebene wird deshalb gegen den
+/// Parser geprüft und notfalls mit anderen Markern bzw. ohne Hervorhebung gerendert.
+pub fn to_markdown(nodes: &[Node]) -> String {
+    let nodes = normalize(nodes, Active::default());
+    let mut out = String::new();
+    if nodes.iter().all(|node| matches!(node, Node::Text(_))) {
+        for node in &nodes {
+            if let Node::Text(text) = node {
+                out.push_str(text);
+            }
+        }
+        return out;
+    }
+
+    for (i, node) in nodes.iter().enumerate() {
+        let next = next_of(nodes.get(i + 1), Next::Other);
+        let expected = styled_chars(&nodes[..=i]);
+        let emphasis_count = count_emphasis(std::slice::from_ref(node));
+
+        let mut variants = vec![Vec::new()];
+        if emphasis_count > 0 && emphasis_count <= MAX_MARKER_VARIANTS_BITS {
+            variants.extend((0..1u32 << emphasis_count).map(|bits| {
+                (0..emphasis_count).map(|bit| if bits & (1 << bit) == 0 { '*' } else { '_' }).collect()
+            }));
+        }
+
+        let rendered = variants.iter().find_map(|forced| {
+            let mut candidate = out.clone();
+            let mut choice = MarkerChoice { forced, index: 0 };
+            render_markdown(std::slice::from_ref(node), &mut candidate, next, None, &mut choice);
+            (styled_chars(&parse_markdown(&candidate)) == expected).then_some(candidate)
+        });
+        match rendered {
+            Some(rendered) => out = rendered,
+            None => escape_markdown(&plain_text(std::slice::from_ref(node)), &mut out),
+        }
+    }
+    out
+}
+
+/// Zieht führende und folgende Leerzeichen aus Formatierungen heraus (`** a**`
+/// wäre kein gültiges Markdown), löst doppelt verschachtelte Formatierungen auf,
+/// entfernt leere Knoten und verschmilzt benachbarten Text und Code
+fn normalize(nodes: &[Node], active: Active) -> Vec<Node> {
+    let mut result = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => push_text(&mut result, text),
+            Node::Code(code) => push_code(&mut result, code),
+            // Codeblöcke innerhalb von Formatierungen werden zu Inline-Code
+            Node::Pre { code, .. } if active.is_inline() => push_code(&mut result, code),
+            // Codeblöcke beginnen in Markdown auf einer eigenen Zeile
+            Node::Pre { .. } => {
+                if result.last().is_some_and(|last| !matches!(last, Node::Text(text) if text.ends_with('\n'))) {
+                    push_text(&mut result, "\n");
+                }
+                result.push(node.clone());
+            }
+            Node::Link { url, children } => {
+                let children = normalize(children, Active { link: true, ..active });
+                if active.link {
+                    children.into_iter().for_each(|child| push_normalized(&mut result, child));
+                } else if !children.is_empty() {
+                    push_normalized(&mut result, Node::Link { url: url.clone(), children });
+                }
+            }
+            Node::Bold(children) | Node::Italic(children) | Node::Strike(children) | Node::Spoiler(children) => {
+                let (nested, already_active) = match node {
+                    Node::Bold(_) => (Active { bold: true, ..active }, active.bold),
+                    Node::Italic(_) => (Active { italic: true, ..active }, active.italic),
+                    Node::Strike(_) => (Active { strike: true, ..active }, active.strike),
+                    _ => (Active { spoiler: true, ..active }, active.spoiler),
+                };
+                let mut children = normalize(children, nested);
+                if already_active {
+                    children.into_iter().for_each(|child| push_normalized(&mut result, child));
+                    continue;
+                }
+                let leading = take_leading_whitespace(&mut children);
+                let trailing = take_trailing_whitespace(&mut children);
+                push_text(&mut result, &leading);
+                if !children.is_empty() {
+                    push_normalized(&mut result, with_children(node, children));
+                }
+                push_text(&mut result, &trailing);
+            }
+        }
+    }
+    result
+}
+
+/// Hängt einen normalisierten Knoten an; gleich formatierte Nachbarn werden
+/// verschmolzen, da `*a**b*` anders gelesen würde
+fn push_normalized(nodes: &mut Vec<Node>, node: Node) {
+    match (nodes.last_mut(), node) {
+        (_, Node::Text(text)) => push_text(nodes, &text),
+        (_, Node::Code(code)) => push_code(nodes, &code),
+        (Some(Node::Bold(last)), Node::Bold(children))
+        | (Some(Node::Italic(last)), Node::Italic(children))
+        | (Some(Node::Strike(last)), Node::Strike(children))
+        | (Some(Node::Spoiler(last)), Node::Spoiler(children)) => {
+            children.into_iter().for_each(|child| push_normalized(last, child));
+        }
+        (Some(Node::Link { url: last_url, children: last }), Node::Link { url, children }) if *last_url == url => {
+            children.into_iter().for_each(|child| push_normalized(last, child));
+        }
+        (_, node) => nodes.push(node),
+    }
+}
+
+/// Benachbarter Inline-Code wird verschmolzen, da `` `a``b` `` anders gelesen würde
+fn push_code(nodes: &mut Vec<Node>, code: &str) {
+    if code.is_empty() {
+        return;
+    }
+    match nodes.last_mut() {
+        Some(Node::Code(last)) => last.push_str(code),
+        _ => nodes.push(Node::Code(code.to_string())),
+    }
+}
+
+fn with_children(node: &Node, children: Vec<Node>) -> Node {
+    match node {
+        Node::Bold(_) => Node::Bold(children),
+        Node::Italic(_) => Node::Italic(children),
+        Node::Strike(_) => Node::Strike(children),
+        Node::Spoiler(_) => Node::Spoiler(children),
+        _ => unreachable!("nur für Formatierungsknoten"),
+    }
+}
+
+fn take_leading_whitespace(children: &mut Vec<Node>) -> String {
+    let Some(Node::Text(text)) = children.first_mut() else {
+        return String::new();
+    };
+    let trimmed = text.trim_start().len();
+    let leading = text[..text.len() - trimmed].to_string();
+    text.drain(..leading.len());
+    if text.is_empty() {
+        children.remove(0);
+    }
+    leading
+}
+
+fn take_trailing_whitespace(children: &mut Vec<Node>) -> String {
+    let Some(Node::Text(text)) = children.last_mut() else {
+        return String::new();
+    };
+    let trimmed = text.trim_end().len();
+    let trailing = text.split_off(trimmed);
+    if text.is_empty() {
+        children.pop();
+    }
+    trailing
+}
+
+/// Was im Markdown direkt auf einen Knoten folgt
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Next {
+    /// Buchstabe oder Ziffer
+    Word,
+    /// Fett oder kursiv
+    Emphasis,
+    Other,
+}
+
+fn next_of(node: Option<&Node>, fallback: Next) -> Next {
+    match node {
+        Some(Node::Text(text)) if text.starts_with(char::is_alphanumeric) => Next::Word,
+        Some(Node::Bold(_) | Node::Italic(_)) => Next::Emphasis,
+        Some(_) => Next::Other,
+        None => fallback,
+    }
+}
+
+/// Vorgegebene Marker für fett/kursiv in Dokumentreihenfolge, sonst Heuristik
+struct MarkerChoice<'a> {
+    forced: &'a [char],
+    index: usize,
+}
+
+/// `next`: was auf `nodes` folgt; `emphasis`: Zeichen (`*` oder `_`) einer
+/// umgebenden Hervorhebung
+fn render_markdown(nodes: &[Node], out: &mut String, next: Next, emphasis: Option<char>, choice: &mut MarkerChoice) {
+    for (i, node) in nodes.iter().enumerate() {
+        let next = next_of(nodes.get(i + 1), next);
+        match node {
+            Node::Text(text) => escape_markdown(text, out),
+            Node::Bold(children) | Node::Italic(children) => {
+                let marker = match choice.forced.get(choice.index) {
+                    Some(&forced) => forced,
+                    None => emphasis_marker(out, next, emphasis, count_emphasis(children) > 0),
+                };
+                choice.index += 1;
+                let length = if matches!(node, Node::Bold(_)) { 2 } else { 1 };
+                let marker = marker.to_string().repeat(length);
+                out.push_str(&marker);
+                render_markdown(children, out, Next::Other, Some(marker.chars().next().unwrap_or('*')), choice);
+                out.push_str(&marker);
+            }
+            Node::Strike(children) => wrap_markdown("~~", children, out, emphasis, choice),
+            Node::Spoiler(children) => wrap_markdown("||", children, out, emphasis, choice),
+            Node::Link { url, children } => {
+                out.push('[');
+                render_markdown(children, out, Next::Other, emphasis, choice);
+                out.push_str("](");
+                for c in url.chars() {
+                    if matches!(c, '\\' | '(' | ')') {
+                        out.push('\\');
+                    }
+                    out.push(c);
+                }
+                out.push(')');
+            }
+            Node::Code(code) => render_code_span(code, out),
+            Node::Pre { language, code } => {
+                let fence = "`".repeat((longest_backtick_run(code) + 1).max(3));
+                out.push_str(&fence);
+                out.push_str(language.as_deref().unwrap_or_default());
+                out.push('\n');
+                out.push_str(code);
+                out.push('\n');
+                out.push_str(&fence);
+                out.push('\n');
+            }
+        }
+    }
+}
+
+/// Wählt `*` oder `_` für fett/kursiv. `_` wird genommen, wo `*` mehrdeutig
+/// wäre (direkt nach einem `*`, innerhalb einer `*`-Hervorhebung oder um eine
+/// verschachtelte Hervorhebung), sofern es nicht mitten in einem Wort oder
+/// direkt vor der nächsten Hervorhebung steht.
+fn emphasis_marker(out: &str, next: Next, emphasis: Option<char>, nested: bool) -> char {
+    let prev = out.chars().next_back();
+    let underscore_possible = !prev.is_some_and(|c| c.is_alphanumeric() || c == '_') && next == Next::Other;
+    let avoid_star = prev == Some('*') || emphasis == Some('*') || nested;
+    if avoid_star && underscore_possible && emphasis != Some('_') {
+        '_'
+    } else {
+        '*'
+    }
+}
+
+fn count_emphasis(nodes: &[Node]) -> u32 {
+    nodes.iter().map(|node| match node {
+        Node::Bold(children) | Node::Italic(children) => 1 + count_emphasis(children),
+        Node::Strike(children) | Node::Spoiler(children) | Node::Link { children, .. } => count_emphasis(children),
+        _ => 0,
+    }).sum()
+}
+
+fn plain_text(nodes: &[Node]) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(t) | Node::Code(t) | Node::Pre { code: t, .. } => text.push_str(t),
+            Node::Bold(children) | Node::Italic(children) | Node::Strike(children)
+            | Node::Spoiler(children) | Node::Link { children, .. } => text.push_str(&plain_text(children)),
+        }
+    }
+    text
+}
+
+/// Formatierung eines einzelnen Zeichens, zum Vergleich von Bäumen unabhängig
+/// von ihrer Verschachtelung. Leerzeichen tragen keine Hervorhebung, da sie beim
+/// Rendern aus Formatierungen herausgezogen werden.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    spoiler: bool,
+    code: bool,
+    link: Option<String>,
+}
+
+fn styled_chars(nodes: &[Node]) -> Vec<(char, Style)> {
+    fn walk(nodes: &[Node], style: &Style, out: &mut Vec<(char, Style)>) {
+        for node in nodes {
+            let mut inner = style.clone();
+            let children = match node {
+                Node::Text(text) => {
+                    for c in text.chars() {
+                        let style = if c.is_whitespace() {
+                            Style { code: style.code, link: style.link.clone(), ..Style::default() }
+                        } else {
+                            style.clone()
+                        };
+                        out.push((c, style));
+                    }
+                    continue;
+                }
+                Node::Code(code) | Node::Pre { code, .. } => {
+                    inner.code = true;
+                    walk(&[Node::Text(code.clone())], &inner, out);
+                    continue;
+                }
+                Node::Bold(children) => {
+                    inner.bold = true;
+                    children
+                }
+                Node::Italic(children) => {
+                    inner.italic = true;
+                    children
+                }
+                Node::Strike(children) => {
+                    inner.strike = true;
+                    children
+                }
+                Node::Spoiler(children) => {
+                    inner.spoiler = true;
+                    children
+                }
+                Node::Link { url, children } => {
+                    inner.link.get_or_insert_with(|| url.clone());
+                    children
+                }
+            };
+            walk(children, &inner, out);
+        }
+    }
+    let mut out = Vec::new();
+    walk(nodes, &Style::default(), &mut out);
+    out
+}
+
+fn wrap_markdown(marker: &str, children: &[Node], out: &mut String, emphasis: Option<char>, choice: &mut MarkerChoice) {
+    out.push_str(marker);
+    render_markdown(children, out, Next::Other, emphasis, choice);
+    out.push_str(marker);
+}
+
+/// Maskiert Zeichen, die der Markdown-Parser sonst als Formatierung lesen würde.
+/// `_` zwischen zwei Buchstaben (snake_case, URLs) bleibt unmaskiert, da es dort
+/// keine Formatierung beginnen oder beenden kann.
+fn escape_markdown(text: &str, out: &mut String) {
+    let chars: Vec<char> = text.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        let next = chars.get(i + 1).copied();
+        match c {
+            '*' | '~' | '|' | '`' | '[' | ']' => out.push('\\'),
+            '\\' if next.is_none_or(|n| n.is_ascii_punctuation()) => out.push('\\'),
+            '_' => {
+                let prev = out.chars().next_back();
+                let inside_word = prev.is_some_and(char::is_alphanumeric) && next.is_some_and(char::is_alphanumeric);
+                if !inside_word {
+                    out.push('\\');
+                }
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+}
+
+fn render_code_span(code: &str, out: &mut String) {
+    let fence = "`".repeat(longest_backtick_run(code) + 1);
+    // Ein Leerzeichen am Rand wird beim Parsen wieder entfernt
+    let pad = code.starts_with('`')
+        || code.ends_with('`')
+        || (code.starts_with(' ') && code.ends_with(' ') && !code.chars().all(|c| c == ' '));
+
+    out.push_str(&fence);
+    if pad {
+        out.push(' ');
+    }
+    out.push_str(code);
+    if pad {
+        out.push(' ');
+    }
+    out.push_str(&fence);
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+// ---------------------------------------------------------------------------
+// Markdown → Baum
+// ---------------------------------------------------------------------------
+
+/// Parst die Markdown-Teilmenge, die Nostr-Clients üblicherweise darstellen:
+/// Codeblöcke, Inline-Code, `**`/`__` fett, `*`/`_` kursiv, `~~`, `||`, Links
+/// und `\`-Maskierung. Alles andere bleibt Text.
+pub fn parse_markdown(input: &str) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut rest = input;
+
+    while let Some(block) = find_code_block(rest) {
+        for node in parse_inline(&rest[..block.start]) {
+            push_node(&mut nodes, node);
+        }
+        nodes.push(Node::Pre { language: block.language, code: block.code });
+        rest = &rest[block.end..];
+    }
+    for node in parse_inline(rest) {
+        push_node(&mut nodes, node);
+    }
+    nodes
+}
+
+struct CodeBlock {
+    start: usize,
+    end: usize,
+    language: Option<String>,
+    code: String,
+}
+
+/// Sucht einen Codeblock (```` ``` ```` am Zeilenanfang bis zu einer Zeile mit
+/// mindestens ebenso vielen Backticks)
+fn find_code_block(input: &str) -> Option<CodeBlock> {
+    let mut line_start = 0;
+    while line_start < input.len() {
+        let line_end = input[line_start..].find('\n').map_or(input.len(), |i| line_start + i);
+        let line = &input[line_start..line_end];
+        let fence_len = line.len() - line.trim_start_matches('`').len();
+        let info = line[fence_len..].trim();
+
+        if fence_len >= 3 && !info.contains('`') && line_end < input.len() {
+            let code_start = line_end + 1;
+            let mut pos = code_start;
+            loop {
+                let end = input[pos..].find('\n').map_or(input.len(), |i| pos + i);
+                let candidate = input[pos..end].trim_end();
+                if candidate.len() >= fence_len && candidate.chars().all(|c| c == '`') {
+                    let code = if pos > code_start { &input[code_start..pos - 1] } else { "" };
+                    return Some(CodeBlock {
+                        start: line_start,
+                        end: (end + 1).min(input.len()),
+                        language: info.split_whitespace().next().map(str::to_string),
+                        code: code.to_string(),
+                    });
+                }
+                if end >= input.len() {
+                    break;
+                }
+                pos = end + 1;
+            }
+        }
+        line_start = line_end + 1;
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Delimiter {
+    Star,
+    Underscore,
+    Tilde,
+    Pipe,
+    Bracket,
+}
+
+impl Delimiter {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '*' => Some(Delimiter::Star),
+            '_' => Some(Delimiter::Underscore),
+            '~' => Some(Delimiter::Tilde),
+            '|' => Some(Delimiter::Pipe),
+            _ => None,
+        }
+    }
+
+    fn as_char(self) -> char {
+        match self {
+            Delimiter::Star => '*',
+            Delimiter::Underscore => '_',
+            Delimiter::Tilde => '~',
+            Delimiter::Pipe => '|',
+            Delimiter::Bracket => '[',
+        }
+    }
+
+    /// `~~` und `||` wirken nur paarweise
+    fn is_paired(self) -> bool {
+        matches!(self, Delimiter::Tilde | Delimiter::Pipe)
+    }
+}
+
+/// Offene Formatierung auf dem Stack des Inline-Parsers
+struct Frame {
+    delimiter: Option<Delimiter>,
+    /// Noch nicht geschlossene Zeichen des öffnenden Laufs
+    count: usize,
+    /// Länge des ursprünglichen Laufs (für die Dreier-Regel aus CommonMark)
+    run_length: usize,
+    can_close: bool,
+    children: Vec<Node>,
+}
+
+impl Frame {
+    fn root() -> Self {
+        Frame { delimiter: None, count: 0, run_length: 0, can_close: false, children: Vec::new() }
+    }
+}
+
+fn parse_inline(input: &str) -> Vec<Node> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut stack = vec![Frame::root()];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let top = stack.len() - 1;
+
+        if c == '\\' && chars.get(i + 1).is_some_and(|n| n.is_ascii_punctuation()) {
+            push_char(&mut stack[top].children, chars[i + 1]);
+            i += 2;
+            continue;
+        }
+
+        if c == '`' {
+            let run = run_length(&chars, i);
+            match find_closing_backticks(&chars, i + run, run) {
+                Some(close) => {
+                    let mut code: String = chars[i + run..close].iter().collect();
+                    if code.len() >= 2 && code.starts_with(' ') && code.ends_with(' ') && !code.chars().all(|c| c == ' ') {
+                        code = code[1..code.len() - 1].to_string();
+                    }
+                    push_node(&mut stack[top].children, Node::Code(code));
+                    i = close + run;
+                }
+                None => {
+                    push_text(&mut stack[top].children, &"`".repeat(run));
+                    i += run;
+                }
+            }
+            continue;
+        }
+
+        if let Some(delimiter) = Delimiter::from_char(c) {
+            let run = run_length(&chars, i);
+            let prev = i.checked_sub(1).map(|p| chars[p]);
+            let next = chars.get(i + run).copied();
+            handle_delimiter_run(&mut stack, delimiter, run, prev, next);
+            i += run;
+            continue;
+        }
+
+        if c == '[' {
+            stack.push(Frame { delimiter: Some(Delimiter::Bracket), count: 1, run_length: 1, can_close: false, children: Vec::new() });
+            i += 1;
+            continue;
+        }
+
+        if c == ']' && chars.get(i + 1) == Some(&'(') {
+            let bracket = stack.iter().rposition(|frame| frame.delimiter == Some(Delimiter::Bracket));
+            if let (Some(bracket), Some((url, end))) = (bracket, parse_link_destination(&chars, i + 2)) {
+                revert_above(&mut stack, bracket);
+                let frame = stack.pop().expect("Klammer-Frame vorhanden");
+                let top = stack.len() - 1;
+                push_node(&mut stack[top].children, Node::Link { url, children: frame.children });
+                i = end;
+                continue;
+            }
+        }
+
+        push_char(&mut stack[top].children, c);
+        i += 1;
+    }
+
+    revert_above(&mut stack, 0);
+    stack.pop().map(|root| root.children).unwrap_or_default()
+}
+
+fn handle_delimiter_run(stack: &mut Vec<Frame>, delimiter: Delimiter, run: usize, prev: Option<char>, next: Option<char>) {
+    let prev_is_space = prev.is_none_or(char::is_whitespace);
+    let next_is_space = next.is_none_or(char::is_whitespace);
+    let (can_open, can_close) = match delimiter {
+        // `_` innerhalb von Wörtern (snake_case) ist keine Formatierung
+        Delimiter::Underscore => (
+            !next_is_space && !prev.is_some_and(char::is_alphanumeric),
+            !prev_is_space && !next.is_some_and(char::is_alphanumeric),
+        ),
+        _ => (!next_is_space, !prev_is_space),
+    };
+
+    let mut remaining = run;
+    if can_close {
+        while remaining > 0 {
+            let opener = stack.iter().enumerate().skip(1).rev()
+                .take_while(|(_, frame)| frame.delimiter != Some(Delimiter::Bracket))
+                .find(|(_, frame)| {
+                    frame.delimiter == Some(delimiter)
+                        && (delimiter.is_paired() || !violates_rule_of_three(frame, run, can_open))
+                })
+                .map(|(index, _)| index);
+            let Some(opener) = opener else { break };
+
+            let used = if delimiter.is_paired() {
+                if remaining < 2 {
+                    break;
+                }
+                2
+            } else if remaining >= 2 && stack[opener].count >= 2 {
+                2
+            } else {
+                1
+            };
+
+            revert_above(stack, opener);
+            let frame = stack.last_mut().expect("Opener-Frame vorhanden");
+            let children = std::mem::take(&mut frame.children);
+            let node = match (delimiter, used) {
+                (Delimiter::Tilde, _) => Node::Strike(children),
+                (Delimiter::Pipe, _) => Node::Spoiler(children),
+                (_, 2) => Node::Bold(children),
+                _ => Node::Italic(children),
+            };
+            frame.count -= used;
+            if frame.count == 0 {
+                stack.pop();
+                let top = stack.len() - 1;
+                push_node(&mut stack[top].children, node);
+            } else {
+                frame.children.push(node);
+            }
+            remaining -= used;
+        }
+    }
+
+    let top = stack.len() - 1;
+    if remaining > 0 && can_open {
+        let count = if delimiter.is_paired() { remaining / 2 * 2 } else { remaining };
+        if count > 0 {
+            push_text(&mut stack[top].children, &delimiter.as_char().to_string().repeat(remaining - count));
+            stack.push(Frame { delimiter: Some(delimiter), count, run_length: run, can_close, children: Vec::new() });
+            return;
+        }
+    }
+    if remaining > 0 {
+        push_text(&mut stack[top].children, &delimiter.as_char().to_string().repeat(remaining));
+    }
+}
+
+/// CommonMark: Kann einer der beiden Läufe öffnen und schließen, dürfen sich
+/// ihre Längen nicht zu einem Vielfachen von 3 addieren (`**a*b***`)
+fn violates_rule_of_three(opener: &Frame, closer_run: usize, closer_can_open: bool) -> bool {
+    (opener.can_close || closer_can_open)
+        && (opener.run_length + closer_run).is_multiple_of(3)
+        && !(opener.run_length.is_multiple_of(3) && closer_run.is_multiple_of(3))
+}
+
+/// Löst alle Frames oberhalb von `index` wieder in Text auf
+fn revert_above(stack: &mut Vec<Frame>, index: usize) {
+    while stack.len() > index + 1 {
+        let frame = stack.pop().expect("Frame vorhanden");
+        let parent = &mut stack.last_mut().expect("Frame vorhanden").children;
+        if let Some(delimiter) = frame.delimiter {
+            push_text(parent, &delimiter.as_char().to_string().repeat(frame.count));
+        }
+        for node in frame.children {
+            push_node(parent, node);
+        }
+    }
+}
+
+/// Liest `url)` ab `start`; `\` maskiert, Klammern müssen ausgeglichen sein
+fn parse_link_destination(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut url = String::new();
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if chars.get(i + 1).is_some_and(|n| n.is_ascii_punctuation()) => {
+                url.push(chars[i + 1]);
+                i += 1;
+            }
+            '(' => {
+                depth += 1;
+                url.push('(');
+            }
+            ')' if depth == 0 => return (!url.is_empty()).then_some((url, i + 1)),
+            ')' => {
+                depth -= 1;
+                url.push(')');
+            }
+            c if c.is_whitespace() => return None,
+            c => url.push(c),
+        }
+        i += 1;
+    }
+    None
+}
+
+fn run_length(chars: &[char], start: usize) -> usize {
+    chars[start..].iter().take_while(|&&c| c == chars[start]).count()
+}
+
+fn find_closing_backticks(chars: &[char], from: usize, length: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        if chars[i] == '`' {
+            let run = run_length(chars, i);
+            if run == length {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+fn push_char(nodes: &mut Vec<Node>, c: char) {
+    match nodes.last_mut() {
+        Some(Node::Text(last)) => last.push(c),
+        _ => nodes.push(Node::Text(c.to_string())),
+    }
+}
+
+fn push_node(nodes: &mut Vec<Node>, node: Node) {
+    match node {
+        Node::Text(text) => push_text(nodes, &text),
+        node => nodes.push(node),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Aufteilen langer Nachrichten
+// ---------------------------------------------------------------------------
+
+/// Ein Teil einer aufgeteilten Nachricht als Telegram-HTML und als Markdown
+/// (zum Senden als Text, falls Telegram das HTML ablehnt)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelegramPart {
+    pub html: String,
+    pub markdown: String,
+}
+
+/// Teilt Markdown in Telegram-Nachrichten mit höchstens `first_limit` (erster
+/// Teil) bzw. `limit` sichtbaren Zeichen, gezählt in UTF-16 wie von Telegram.
+/// Getrennt wird bevorzugt an Absätzen, dann an Zeilenumbrüchen, dann an
+/// Leerzeichen. Formatierungen über eine Grenze hinweg werden geschlossen und
+/// im nächsten Teil wieder geöffnet.
+pub fn split_markdown_for_telegram(markdown: &str, first_limit: usize, limit: usize) -> Vec<TelegramPart> {
+    let nodes = parse_markdown(markdown);
+    let text: Vec<char> = plain_text(&nodes).chars().collect();
+    split_ranges(&text, first_limit, limit)
+        .into_iter()
+        .map(|range| {
+            let part = slice_nodes(&nodes, &range, &mut 0);
+            let mut html = String::new();
+            render_html(&part, &mut html, false);
+            TelegramPart { html, markdown: to_markdown(&part) }
+        })
+        .collect()
+}
+
+/// Zeichenbereiche der Teile; die Trennzeichen selbst fallen weg
+fn split_ranges(text: &[char], first_limit: usize, limit: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    loop {
+        let max = if ranges.is_empty() { first_limit } else { limit };
+        // Längster Bereich ab `start`, der in das Limit passt (mindestens ein Zeichen)
+        let mut end = start;
+        let mut units = 0;
+        while end < text.len() && (end == start || units + text[end].len_utf16() <= max) {
+            units += text[end].len_utf16();
+            end += 1;
+        }
+        if end == text.len() {
+            ranges.push(start..end);
+            return ranges;
+        }
+
+        // Trennstellen in der ersten Hälfte ergäben unnötig kurze Teile
+        let from = (start + (end - start) / 2).max(start + 1);
+        let (cut, resume) = find_break(text, from, end).unwrap_or((end, end));
+        ranges.push(start..cut);
+
+        start = resume;
+        while start < text.len() && text[start] == '\n' {
+            start += 1;
+        }
+        if start == text.len() {
+            return ranges;
+        }
+    }
+}
+
+/// Letzte Trennstelle in `from..=to` als (Ende des Teils, Beginn des nächsten)
+fn find_break(text: &[char], from: usize, to: usize) -> Option<(usize, usize)> {
+    let candidates = || (from..=to.min(text.len() - 1)).rev();
+    candidates()
+        .find(|&i| text[i] == '\n' && text.get(i + 1) == Some(&'\n'))
+        .map(|i| (i, i + 2))
+        .or_else(|| candidates().find(|&i| text[i] == '\n').map(|i| (i, i + 1)))
+        .or_else(|| candidates().find(|&i| text[i].is_whitespace()).map(|i| (i, i + 1)))
+}
+
+/// Schneidet den Zeichenbereich `range` (bezogen auf `plain_text`) aus dem
+/// Baum; angeschnittene Formatierungen bleiben um ihren Ausschnitt erhalten
+fn slice_nodes(nodes: &[Node], range: &Range<usize>, offset: &mut usize) -> Vec<Node> {
+    let mut sliced = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Text(t) | Node::Code(t) | Node::Pre { code: t, .. } => {
+                let start = *offset;
+                *offset += t.chars().count();
+                let (from, to) = (range.start.max(start), range.end.min(*offset));
+                if from >= to {
+                    continue;
+                }
+                let piece: String = t.chars().skip(from - start).take(to - from).collect();
+                sliced.push(match node {
+                    Node::Text(_) => Node::Text(piece),
+                    Node::Code(_) => Node::Code(piece),
+                    Node::Pre { language, .. } => Node::Pre { language: language.clone(), code: piece },
+                    _ => unreachable!(),
+                });
+            }
+            Node::Link { url, children } => {
+                let children = slice_nodes(children, range, offset);
+                if !children.is_empty() {
+                    sliced.push(Node::Link { url: url.clone(), children });
+                }
+            }
+            Node::Bold(children) | Node::Italic(children) | Node::Strike(children) | Node::Spoiler(children) => {
+                let children = slice_nodes(children, range, offset);
+                if !children.is_empty() {
+                    sliced.push(with_children(node, children));
+                }
+            }
+        }
+    }
+    sliced
+}
+
+// ---------------------------------------------------------------------------
+// Baum → Telegram-HTML
+// ---------------------------------------------------------------------------
+
+fn render_html(nodes: &[Node], out: &mut String, in_link: bool) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(&escape_html(text)),
+            Node::Bold(children) => wrap_html("b", children, out, in_link),
+            Node::Italic(children) => wrap_html("i", children, out, in_link),
+            Node::Strike(children) => wrap_html("s", children, out, in_link),
+            Node::Spoiler(children) => wrap_html("tg-spoiler", children, out, in_link),
+            // Telegram erlaubt keine verschachtelten Links
+            Node::Link { children, .. } if in_link => render_html(children, out, true),
+            Node::Link { url, children } => {
+                out.push_str(&format!("<a href=\"{}\">", escape_html(url)));
+                render_html(children, out, true);
+                out.push_str("</a>");
+            }
+            Node::Code(code) => out.push_str(&format!("<code>{}</code>", escape_html(code))),
+            Node::Pre { language: Some(language), code } => out.push_str(&format!(
+                "<pre><code class=\"language-{}\">{}</code></pre>",
+                escape_html(language),
+                escape_html(code)
+            )),
+            Node::Pre { language: None, code } => out.push_str(&format!("<pre>{}</pre>", escape_html(code))),
+        }
+    }
+}
+
+fn wrap_html(tag: &str, children: &[Node], out: &mut String, in_link: bool) {
+    out.push_str(&format!("<{}>", tag));
+    render_html(children, out, in_link);
+    out.push_str(&format!("</{}>", tag));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Umkehrung von `from_telegram`: Baum → Text mit UTF-16-Entities.
+    /// Telegram kennt keine verschachtelten Links, es zählt der äußere.
+    fn to_telegram(nodes: &[Node]) -> (String, Vec<MessageEntity>) {
+        fn walk(nodes: &[Node], in_link: bool, text: &mut String, entities: &mut Vec<MessageEntity>) {
+            for node in nodes {
+                let offset = text.encode_utf16().count();
+                let kind = match node {
+                    Node::Text(t) => {
+                        text.push_str(t);
+                        continue;
+                    }
+                    Node::Code(code) => {
+                        text.push_str(code);
+                        MessageEntityKind::Code
+                    }
+                    Node::Pre { language, code } => {
+                        text.push_str(code);
+                        MessageEntityKind::Pre { language: language.clone() }
+                    }
+                    Node::Link { children, .. } if in_link => {
+                        walk(children, true, text, entities);
+                        continue;
+                    }
+                    Node::Bold(children) | Node::Italic(children) | Node::Strike(children)
+                    | Node::Spoiler(children) | Node::Link { children, .. } => {
+                        let is_link = matches!(node, Node::Link { .. });
+                        walk(children, in_link || is_link, text, entities);
+                        match node {
+                            Node::Bold(_) => MessageEntityKind::Bold,
+                            Node::Italic(_) => MessageEntityKind::Italic,
+                            Node::Strike(_) => MessageEntityKind::Strikethrough,
+                            Node::Spoiler(_) => MessageEntityKind::Spoiler,
+                            Node::Link { url, .. } => MessageEntityKind::TextLink { url: url.parse().unwrap() },
+                            _ => unreachable!(),
+                        }
+                    }
+                };
+                let length = text.encode_utf16().count() - offset;
+                if length > 0 {
+                    entities.push(MessageEntity::new(kind, offset, length));
+                }
+            }
+        }
+        let mut text = String::new();
+        let mut entities = Vec::new();
+        walk(nodes, false, &mut text, &mut entities);
+        (text, entities)
+    }
+
+    fn arb_nodes() -> impl Strategy<Value = Vec<Node>> {
+        let text = "[a-z äö_*~|`\\[\\]\\\\()#\n🙂]{1,8}".prop_map(Node::Text);
+        let code = "[a-z `*_\\\\]{1,6}".prop_map(Node::Code);
+        let url = prop_oneof![
+            Just("https://example.com/".to_string()),
+            Just("https://example.com/snake_case_(x)".to_string()),
+            Just("https://example.com/a?b=c&d=%5C".to_string()),
+        ];
+        let node = prop_oneof![3 => text, 1 => code].prop_recursive(4, 24, 4, move |inner| {
+            let children = prop::collection::vec(inner, 1..4);
+            prop_oneof![
+                children.clone().prop_map(Node::Bold),
+                children.clone().prop_map(Node::Italic),
+                children.clone().prop_map(Node::Strike),
+                children.clone().prop_map(Node::Spoiler),
+                (url.clone(), children).prop_map(|(url, children)| Node::Link { url, children }),
+            ]
+        });
+        prop::collection::vec(node, 1..5)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_telegram_formatting_round_trips_through_markdown(nodes in arb_nodes()) {
+            let (text, entities) = to_telegram(&nodes);
+            let tree = from_telegram(&text, &entities, &[]);
+            prop_assert_eq!(styled_chars(&tree), styled_chars(&nodes));
+
+            let markdown = telegram_to_markdown(&text, &entities, &[]);
+            prop_assume!(normalize(&tree, Active::default()).iter().any(|node| !matches!(node, Node::Text(_))));
+            prop_assert_eq!(styled_chars(&parse_markdown(&markdown)), styled_chars(&nodes), "Markdown: {:?}", markdown);
+        }
+    }
+
+    #[test]
+    fn test_plain_text_stays_unchanged() {
+        let text = "Preis: 5 * 3 = 15, snake_case und [Klammern] bleiben so";
+        assert_eq!(telegram_to_markdown(text, &[], &[]), text);
+        assert_eq!(parse_markdown("snake_case_name und https://example.com/a_b_c"), vec![
+            Node::Text("snake_case_name und https://example.com/a_b_c".to_string())
+        ]);
+    }
+
+    #[test]
+    fn test_telegram_entities_to_markdown() {
+        // "fett Link Spoiler code" mit Text-Link, Spoiler und Inline-Code
+        let text = "fett Link Spoiler a*b undfn main() {}";
+        let entities = vec![
+            MessageEntity::new(MessageEntityKind::Bold, 0, 4),
+            MessageEntity::new(MessageEntityKind::TextLink { url: "https://example.com/".parse().unwrap() }, 5, 4),
+            MessageEntity::new(MessageEntityKind::Spoiler, 10, 7),
+            MessageEntity::new(MessageEntityKind::Code, 18, 3),
+            MessageEntity::new(MessageEntityKind::Pre { language: Some("rust".to_string()) }, 25, 12),
+        ];
+
+        let markdown = telegram_to_markdown(text, &entities, &[]);
+        assert_eq!(markdown, "**fett** [Link](https://example.com/) ||Spoiler|| `a*b` und\n```rust\nfn main() {}\n```\n");
+    }
+
+    #[test]
+    fn test_markdown_to_telegram_html() {
+        let markdown = "**<fett>** & _kursiv_ ~~weg~~ ||geheim|| [Link](https://example.com/?a=1&b=\"2\") `a<b`\n```rust\nlet x = 1 < 2;\n```\nEnde \\*ohne\\*";
+        assert_eq!(
+            markdown_to_telegram_html(markdown),
+            "<b>&lt;fett&gt;</b> &amp; <i>kursiv</i> <s>weg</s> <tg-spoiler>geheim</tg-spoiler> \
+             <a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\">Link</a> <code>a&lt;b</code>\n\
+             <pre><code class=\"language-rust\">let x = 1 &lt; 2;</code></pre>Ende *ohne*"
+        );
+    }
+
+    #[test]
+    fn test_split_for_telegram() {
+        let parts = |markdown: &str, first_limit: usize, limit: usize| -> Vec<String> {
+            split_markdown_for_telegram(markdown, first_limit, limit).into_iter().map(|part| part.html).collect()
+        };
+
+        // Kurze Nachrichten bleiben ganz
+        assert_eq!(parts("**eins zwei drei**", 20, 20), vec!["<b>eins zwei drei</b>"]);
+        // Formatierung wird an der Grenze geschlossen und wieder geöffnet
+        assert_eq!(parts("**eins zwei drei**", 10, 10), vec!["<b>eins zwei</b>", "<b>drei</b>"]);
+        // Absätze vor Leerzeichen, der erste Teil hat ein eigenes Limit
+        assert_eq!(
+            parts("Erster Absatz hier.\n\nZweiter Absatz mit mehr Text", 30, 30),
+            vec!["Erster Absatz hier.", "Zweiter Absatz mit mehr Text"]
+        );
+        assert_eq!(parts("aaaa bbbb", 4, 100), vec!["aaaa", "bbbb"]);
+        // Ohne Trennstelle wird hart getrennt, Emojis zählen doppelt (UTF-16)
+        assert_eq!(parts("aaaaaaaaaa", 4, 4), vec!["aaaa", "aaaa", "aa"]);
+        assert_eq!(parts("🙂🙂🙂", 4, 4), vec!["🙂🙂", "🙂"]);
+    }
+}
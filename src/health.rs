@@ -0,0 +1,226 @@
+use tracing::{debug, info, warn};
+use nostr_sdk::prelude::*;
+use sd_notify::NotifyState;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide::prelude::*;
+
+use crate::database::{Database, MessageDirection};
+use crate::metrics::{self, Metrics};
+use crate::storage::Storage;
+use crate::unix_now;
+
+/// Komponente: Telegram-Empfang (jede `getUpdates`-Runde bzw. jede Abfrage
+/// des Webhook-Listeners durch den Dispatcher)
+pub const TELEGRAM: &str = "telegram";
+/// Erreichbarkeit der Telegram-Bot-API (regelmäßiger `getMe`-Aufruf), nur zur Diagnose
+pub const TELEGRAM_API: &str = "telegram_api";
+/// Komponente: Nostr-Listener-Schleife
+pub const NOSTR_LISTENER: &str = "nostr_listener";
+
+/// Intervall, in dem die Nostr-Listener-Schleife ein Lebenszeichen gibt
+pub const LISTENER_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// Intervall der Telegram-Erreichbarkeitsprüfung
+const TELEGRAM_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Ohne Lebenszeichen seit dieser Zeit gilt der Nostr-Listener als hängend
+const LISTENER_STALE_SECS: i64 = 4 * LISTENER_HEARTBEAT_INTERVAL.as_secs() as i64;
+/// Ohne Lebenszeichen des Telegram-Empfangs seit dieser Zeit ist die Bridge
+/// nicht bereit (mehrere Long-Polling-Runden)
+const TELEGRAM_STALE_SECS: i64 = 6 * crate::polling::POLL_TIMEOUT.as_secs() as i64;
+/// Ohne erfolgreiches `getMe` seit dieser Zeit gilt die Bot-API als nicht erreichbar
+const TELEGRAM_API_STALE_SECS: i64 = 3 * TELEGRAM_PROBE_INTERVAL.as_secs() as i64;
+
+/// Zustand einer Komponente mit Zeitpunkt des letzten Lebenszeichens
+#[derive(Debug, Serialize)]
+pub struct ComponentStatus {
+    pub ok: bool,
+    pub last_seen: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelayCounts {
+    pub connected: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseStatus {
+    pub writable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LastForward {
+    pub telegram_to_nostr: Option<i64>,
+    pub nostr_to_telegram: Option<i64>,
+}
+
+/// Antwort von `/healthz` und `/readyz`
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub alive: bool,
+    pub ready: bool,
+    pub telegram: ComponentStatus,
+    pub telegram_api: ComponentStatus,
+    pub nostr_listener: ComponentStatus,
+    pub relays: RelayCounts,
+    pub database: DatabaseStatus,
+    pub last_forward: LastForward,
+}
+
+/// Lebendigkeit (läuft die Nostr-Listener-Schleife?) und Bereitschaft
+/// (Telegram-Empfang läuft, mindestens ein Relay verbunden, Datenbank beschreibbar)
+pub struct Health {
+    metrics: Arc<Metrics>,
+    client: Arc<Client>,
+    db: Arc<Database>,
+    storage: Arc<dyn Storage>,
+}
+
+impl Health {
+    pub fn new(metrics: Arc<Metrics>, client: Arc<Client>, db: Arc<Database>, storage: Arc<dyn Storage>) -> Self {
+        Health { metrics, client, db, storage }
+    }
+
+    /// Lebendig, solange die Nostr-Listener-Schleife regelmäßig ein Lebenszeichen gibt
+    pub fn is_alive(&self) -> bool {
+        self.component(NOSTR_LISTENER, LISTENER_STALE_SECS).ok
+    }
+
+    pub async fn report(&self) -> HealthReport {
+        let telegram = self.component(TELEGRAM, TELEGRAM_STALE_SECS);
+        let telegram_api = self.component(TELEGRAM_API, TELEGRAM_API_STALE_SECS);
+        let nostr_listener = self.component(NOSTR_LISTENER, LISTENER_STALE_SECS);
+
+        let relays = self.client.relays().await;
+        let mut connected = 0;
+        for relay in relays.values() {
+            if relay.status().await == RelayStatus::Connected {
+                connected += 1;
+            }
+        }
+        let relays = RelayCounts { connected, total: relays.len() };
+
+        // SQLite ist immer im Einsatz; mit PostgreSQL wird zusätzlich dieses geprüft
+        let now = unix_now();
+        let check = match Storage::check_writable(&*self.db, now).await {
+            Ok(()) => self.storage.check_writable(now).await,
+            Err(e) => Err(e),
+        };
+        let database = match check {
+            Ok(()) => DatabaseStatus { writable: true, error: None },
+            Err(e) => DatabaseStatus { writable: false, error: Some(e.to_string()) },
+        };
+
+        let last_forward = LastForward {
+            telegram_to_nostr: self.metrics.last_forward(MessageDirection::TelegramToNostr),
+            nostr_to_telegram: self.metrics.last_forward(MessageDirection::NostrToTelegram),
+        };
+
+        let alive = nostr_listener.ok;
+        let ready = alive && telegram.ok && relays.connected > 0 && database.writable;
+        HealthReport { alive, ready, telegram, telegram_api, nostr_listener, relays, database, last_forward }
+    }
+
+    fn component(&self, component: &str, stale_after_secs: i64) -> ComponentStatus {
+        let last_seen = self.metrics.last_heartbeat(component);
+        let ok = last_seen.is_some_and(|seen| unix_now() - seen <= stale_after_secs);
+        ComponentStatus { ok, last_seen }
+    }
+}
+
+impl fmt::Debug for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Health").finish_non_exhaustive()
+    }
+}
+
+/// Hintergrund-Task: prüft regelmäßig per `getMe`, ob die Bot-API erreichbar
+/// ist. Nur ein Detail im Bericht; ob Updates ankommen, zeigt `TELEGRAM`.
+pub async fn run_telegram_probe(bot: Bot, metrics: Arc<Metrics>) {
+    let mut interval = tokio::time::interval(TELEGRAM_PROBE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match bot.get_me().await {
+            Ok(_) => metrics.heartbeat(TELEGRAM_API),
+            Err(e) => warn!("Telegram nicht erreichbar: {} (Ursache: {})", e, metrics::telegram_failure_cause(&e)),
+        }
+    }
+}
+
+/// Meldet systemd (Type=notify), dass die Bridge bereit ist. Ohne systemd ein No-Op.
+pub fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("sd_notify READY fehlgeschlagen: {}", e);
+    }
+}
+
+/// Meldet systemd, dass die Bridge herunterfährt
+pub fn notify_stopping() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Stopping]) {
+        debug!("sd_notify STOPPING fehlgeschlagen: {}", e);
+    }
+}
+
+/// Hintergrund-Task für den systemd-Watchdog (WatchdogSec). Solange die
+/// Bridge lebendig ist, wird WATCHDOG=1 im halben Watchdog-Intervall gesendet;
+/// hängt oder endet die Nostr-Listener-Schleife, bleibt das Signal aus und
+/// systemd startet den Dienst neu.
+pub async fn run_systemd_watchdog(health: Arc<Health>) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let period = Duration::from_micros(usec) / 2;
+    info!("🐕 systemd-Watchdog aktiv (Intervall {:?})", period);
+
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if health.is_alive() {
+            if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                warn!("sd_notify WATCHDOG fehlgeschlagen: {}", e);
+            }
+        } else {
+            warn!("Nostr-Listener ohne Lebenszeichen, systemd-Watchdog wird nicht bedient");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_reflects_heartbeats_and_database() {
+        let metrics = Arc::new(Metrics::new());
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let client = Arc::new(Client::new(Keys::generate()));
+        let health = Health::new(metrics.clone(), client, db.clone(), db);
+
+        let report = health.report().await;
+        assert!(!report.alive);
+        assert!(!report.ready);
+        assert!(report.database.writable);
+        assert_eq!(report.relays.total, 0);
+        assert_eq!(report.last_forward.telegram_to_nostr, None);
+
+        metrics.heartbeat(NOSTR_LISTENER);
+        metrics.heartbeat(TELEGRAM_API);
+        let report = health.report().await;
+        assert!(report.alive);
+        // getMe allein heißt nicht, dass Updates ankommen
+        assert!(report.telegram_api.ok);
+        assert!(!report.telegram.ok);
+
+        metrics.heartbeat(TELEGRAM);
+        let report = health.report().await;
+        assert!(report.telegram.ok);
+        // Ohne verbundenes Relay nicht bereit
+        assert!(!report.ready);
+    }
+}
@@ -0,0 +1,211 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use tracing::info;
+use nostr_sdk::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::health::Health;
+use crate::metrics::Metrics;
+
+#[derive(Error, Debug)]
+pub enum HttpError {
+    #[error("HTTP_LISTEN_ADDR {addr} nicht verfügbar: {source}")]
+    Bind { addr: SocketAddr, source: std::io::Error },
+    #[error("HTTP-Server-Fehler: {0}")]
+    Server(#[from] hyper::Error),
+}
+
+/// NIP-05-Identität, die unter `/.well-known/nostr.json` ausgeliefert wird
+#[derive(Debug, Clone)]
+pub struct Nip05Identity {
+    /// Lokaler Teil des Identifiers (z.B. "bridge")
+    pub name: String,
+    pub public_key: PublicKey,
+    pub relays: Vec<String>,
+}
+
+/// Gemeinsamer Zustand des eingebauten HTTP-Servers
+#[derive(Debug, Clone, Default)]
+pub struct HttpState {
+    pub nip05: Option<Nip05Identity>,
+    pub metrics: Option<Arc<Metrics>>,
+    pub health: Option<Arc<Health>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Nip05Query {
+    name: Option<String>,
+}
+
+/// Erstellt den Router mit allen HTTP-Endpunkten der Bridge
+pub fn router(state: Arc<HttpState>) -> Router {
+    Router::new()
+        .route("/.well-known/nostr.json", get(nostr_json))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state)
+}
+
+/// Öffnet den Port des HTTP-Servers. Getrennt von `serve`, damit ein belegter
+/// Port den Start der Bridge abbricht, statt den Server-Task abstürzen zu lassen.
+pub fn bind(addr: SocketAddr) -> Result<TcpListener, HttpError> {
+    let socket = TcpListener::bind(addr)
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+        .map_err(|source| HttpError::Bind { addr, source })?;
+    info!("🌐 HTTP-Server lauscht auf {}", addr);
+    Ok(socket)
+}
+
+/// Startet den HTTP-Server und läuft bis zum Ende des Prozesses
+pub async fn serve(socket: TcpListener, router: Router) -> Result<(), HttpError> {
+    axum::Server::from_tcp(socket)?
+        .serve(router.into_make_service())
+        .await?;
+    Ok(())
+}
+
+/// NIP-05: `GET /.well-known/nostr.json?name=<name>`
+async fn nostr_json(
+    State(state): State<Arc<HttpState>>,
+    Query(query): Query<Nip05Query>,
+) -> impl IntoResponse {
+    let Some(ref identity) = state.nip05 else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Ohne Namen oder mit passendem Namen liefern wir die Identität aus
+    let matches = query
+        .name
+        .as_deref()
+        .is_none_or(|name| name.eq_ignore_ascii_case(&identity.name));
+
+    let body = if matches {
+        let hex = identity.public_key.to_hex();
+        json!({
+            "names": { identity.name.clone(): hex.clone() },
+            "relays": { hex: identity.relays },
+        })
+    } else {
+        json!({ "names": {} })
+    };
+
+    // NIP-05 verlangt CORS, damit Web-Clients die Datei abrufen können
+    (
+        [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Json(body),
+    )
+        .into_response()
+}
+
+/// Prometheus: `GET /metrics`
+async fn metrics(State(state): State<Arc<HttpState>>) -> impl IntoResponse {
+    match state.metrics {
+        Some(ref metrics) => (
+            [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            metrics.render(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Lebendigkeit: `GET /healthz` (503, wenn die Nostr-Listener-Schleife hängt)
+async fn healthz(State(state): State<Arc<HttpState>>) -> impl IntoResponse {
+    let Some(ref health) = state.health else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let report = health.report().await;
+    let status = if report.alive { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report)).into_response()
+}
+
+/// Bereitschaft: `GET /readyz` (503, solange Telegram, Relays oder Datenbank fehlen)
+async fn readyz(State(state): State<Arc<HttpState>>) -> impl IntoResponse {
+    let Some(ref health) = state.health else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let report = health.report().await;
+    let status = if report.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{HeaderMap, Request};
+    use tower::ServiceExt;
+
+    fn nip05_state(nip05: Option<Nip05Identity>) -> Arc<HttpState> {
+        Arc::new(HttpState { nip05, ..Default::default() })
+    }
+
+    async fn get(state: Arc<HttpState>, uri: &str) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let response = router(state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, headers, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_nostr_json() {
+        let public_key = Keys::generate().public_key();
+        let hex = public_key.to_hex();
+        let state = nip05_state(Some(Nip05Identity {
+            name: "bridge".to_string(),
+            public_key,
+            relays: vec!["wss://relay.example.org".to_string()],
+        }));
+        let identity = json!({
+            "names": { "bridge": hex },
+            "relays": { hex.clone(): ["wss://relay.example.org"] },
+        });
+
+        let cases = [
+            ("/.well-known/nostr.json?name=bridge", identity.clone()),
+            // Groß-/Kleinschreibung des Namens spielt keine Rolle
+            ("/.well-known/nostr.json?name=Bridge", identity.clone()),
+            ("/.well-known/nostr.json", identity),
+            ("/.well-known/nostr.json?name=alice", json!({ "names": {} })),
+        ];
+
+        for (uri, expected) in cases {
+            let (status, headers, body) = get(state.clone(), uri).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*", "{}", uri);
+            assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), expected, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nostr_json_without_nip05() {
+        let (status, headers, _) = get(nip05_state(None), "/.well-known/nostr.json?name=bridge").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[test]
+    fn test_bind_reports_taken_port() {
+        let first = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = first.local_addr().unwrap();
+
+        match bind(addr) {
+            Err(HttpError::Bind { addr: failed, .. }) => assert_eq!(failed, addr),
+            other => panic!("Bind-Fehler erwartet, erhalten: {:?}", other.map(|_| ())),
+        }
+    }
+}
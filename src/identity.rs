@@ -0,0 +1,107 @@
+use nostr_sdk::prelude::*;
+use tracing::{info, warn};
+use teloxide::types::User;
+
+use crate::database::{Database, DbResult, IdentityLink};
+use crate::unix_now;
+
+/// Gültigkeit eines /link-Bestätigungscodes in Sekunden
+pub const LINK_CHALLENGE_TTL_SECS: i64 = 30 * 60;
+
+/// Präfix der Bestätigungscodes, damit sie in einer DM eindeutig erkennbar sind
+const CHALLENGE_PREFIX: &str = "tg-link-";
+
+/// Erzeugt einen zufälligen Bestätigungscode, z.B. "tg-link-04812736"
+pub fn generate_challenge() -> String {
+    format!("{}{:08}", CHALLENGE_PREFIX, rand::random::<u32>() % 100_000_000)
+}
+
+/// Sucht einen Bestätigungscode im Inhalt einer DM. Groß- und Kleinschreibung
+/// wird ignoriert, da mobile Tastaturen das erste Wort oft großschreiben.
+pub fn extract_challenge(content: &str) -> Option<String> {
+    content
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_ascii_alphanumeric() && c != '-'))
+        .map(|word| word.to_ascii_lowercase())
+        .find(|word| word.starts_with(CHALLENGE_PREFIX))
+}
+
+/// Startet die Verknüpfung eines Telegram-Users mit einem npub und gibt den
+/// Bestätigungscode zurück, den der User per DM vom npub senden muss
+pub async fn start_link(db: &Database, user: &User, nostr_pubkey: &PublicKey) -> DbResult<String> {
+    let challenge = generate_challenge();
+    let pending = IdentityLink {
+        telegram_user_id: user.id.0 as i64,
+        telegram_username: user.username.clone(),
+        telegram_name: user.full_name(),
+        nostr_pubkey: nostr_pubkey.to_hex(),
+        linked_at: unix_now(),
+    };
+    db.create_pending_link(&pending, &challenge).await?;
+    info!("🔗 Verknüpfung angefragt: Telegram-User {} -> {}", pending.telegram_user_id, pending.nostr_pubkey);
+    Ok(challenge)
+}
+
+/// Prüft ob eine DM einen gültigen Bestätigungscode des Absenders enthält
+/// und schließt in diesem Fall die Verknüpfung ab
+pub async fn confirm_link(db: &Database, sender: &PublicKey, content: &str) -> Option<IdentityLink> {
+    let challenge = extract_challenge(content)?;
+    let now = unix_now();
+
+    match db.confirm_pending_link(&sender.to_hex(), &challenge, now - LINK_CHALLENGE_TTL_SECS, now).await {
+        Ok(Some(link)) => {
+            info!("🔗 Verknüpfung bestätigt: Telegram-User {} -> {}", link.telegram_user_id, link.nostr_pubkey);
+            Some(link)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Fehler beim Bestätigen der Verknüpfung: {}", e);
+            None
+        }
+    }
+}
+
+/// Gibt den verknüpften Nostr-Pubkey eines Telegram-Users zurück
+pub async fn linked_pubkey(db: &Database, telegram_user_id: i64) -> Option<PublicKey> {
+    match db.find_link_by_telegram_user(telegram_user_id).await {
+        Ok(link) => link.and_then(|link| PublicKey::from_hex(&link.nostr_pubkey).ok()),
+        Err(e) => {
+            warn!("Fehler beim Lesen der Verknüpfung: {}", e);
+            None
+        }
+    }
+}
+
+/// Telegram-Erwähnung eines verknüpften Users (@username oder Name)
+pub fn telegram_mention(link: &IdentityLink) -> String {
+    match link.telegram_username {
+        Some(ref username) => format!("@{}", username),
+        None => link.telegram_name.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_challenge() {
+        let cases = [
+            ("tg-link-04812736", Some("tg-link-04812736")),
+            ("  tg-link-04812736\n", Some("tg-link-04812736")),
+            ("Tg-link-04812736", Some("tg-link-04812736")),
+            ("TG-LINK-04812736", Some("tg-link-04812736")),
+            ("Hallo! Mein Code: tg-link-04812736. Danke", Some("tg-link-04812736")),
+            ("„tg-link-04812736“", Some("tg-link-04812736")),
+            ("(tg-link-04812736)\ttg-link-11111111", Some("tg-link-04812736")),
+            ("xtg-link-04812736", None),
+            ("tg link 04812736", None),
+            ("kein Code", None),
+            ("", None),
+        ];
+
+        for (content, expected) in cases {
+            assert_eq!(extract_challenge(content).as_deref(), expected, "{:?}", content);
+        }
+    }
+}
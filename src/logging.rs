@@ -0,0 +1,74 @@
+use std::env;
+use std::fmt;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::ConfigError;
+
+/// Ausgabeformat der Logs (LOG_FORMAT)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Menschenlesbare Zeilen (Standard)
+    Text,
+    /// Ein JSON-Objekt pro Zeile inkl. Span-Feldern, z.B. für journald oder Loki
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_str(s: &str) -> Result<Self, ConfigError> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(ConfigError::InvalidValue {
+                var: "LOG_FORMAT".to_string(),
+                msg: "Muss 'text' oder 'json' sein".to_string(),
+            }),
+        }
+    }
+}
+
+/// Initialisiert `tracing` mit RUST_LOG als Filter (Standard: info) und LOG_FORMAT.
+/// Log-Ausgaben von Abhängigkeiten, die noch `log` verwenden, werden übernommen.
+pub fn init() -> Result<(), ConfigError> {
+    let format = match env::var("LOG_FORMAT") {
+        Ok(value) if !value.is_empty() => LogFormat::from_str(&value)?,
+        _ => LogFormat::Text,
+    };
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(false).init(),
+    }
+    Ok(())
+}
+
+/// Nachrichteninhalte erscheinen oberhalb von TRACE nur als Länge:
+/// `info!("Nachricht: {}", Redacted(text))` ergibt "Nachricht: [42 Zeichen]".
+/// Der Inhalt selbst wird ausschließlich per `trace!` protokolliert.
+pub struct Redacted<'a>(pub &'a str);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} Zeichen]", self.0.chars().count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted_hides_content() {
+        let shown = Redacted("Geheimer Inhalt äöü").to_string();
+        assert_eq!(shown, "[19 Zeichen]");
+        assert!(!shown.contains("Geheim"));
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!(LogFormat::from_str("JSON").unwrap(), LogFormat::Json);
+        assert_eq!(LogFormat::from_str("text").unwrap(), LogFormat::Text);
+        assert!(LogFormat::from_str("yaml").is_err());
+    }
+}
@@ -1,17 +1,16 @@
 use teloxide::prelude::*;
-use teloxide::types::Message;
+use teloxide::types::{InputFile, Message, MessageId, MessageKind, ParseMode};
 use dotenv::dotenv;
 use nostr_sdk::prelude::*;
 use nostr_sdk::Kind;
-use tokio::signal;
+use tokio::sync::broadcast::error::RecvError;
 use std::sync::Arc;
 use thiserror::Error;
-use log::{info, warn, error, debug};
+use tracing::{field, info, instrument, trace, warn, error, debug, Span};
 use chrono::{NaiveDateTime, TimeZone};
 use chrono_tz::Tz;
 use std::env;
-use std::time::{SystemTime, UNIX_EPOCH};
-use serde_json;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 mod config;
 use crate::config::{Config, ConfigError, EncryptionType};
@@ -19,6 +18,67 @@ use crate::config::{Config, ConfigError, EncryptionType};
 mod database;
 use crate::database::{Database, MessageMapping, MessageDirection};
 
+mod storage;
+use crate::storage::Storage;
+
+#[cfg(feature = "postgres")]
+mod postgres;
+
+mod http;
+use crate::http::{HttpState, Nip05Identity};
+
+mod metrics;
+use crate::metrics::Metrics;
+
+mod health;
+use crate::health::Health;
+
+mod profile;
+use crate::profile::ProfileCache;
+
+mod identity;
+
+mod commands;
+
+mod puppet;
+
+mod mentions;
+mod formatting;
+
+mod content;
+use crate::content::TelegramContent;
+
+mod preview;
+
+mod topics;
+use crate::topics::TopicDestination;
+
+mod sources;
+use crate::sources::ChannelFormat;
+
+mod templates;
+use crate::templates::{Language, MessageContext, Template};
+
+mod retention;
+use crate::retention::{resync_floor, RESYNC_SLACK_SECS};
+
+mod archive;
+
+mod cli;
+use crate::cli::CliCommand;
+
+mod export;
+
+mod supervisor;
+use crate::supervisor::Shutdown;
+
+mod logging;
+
+mod polling;
+
+mod webhook;
+use crate::logging::Redacted;
+
 #[derive(Error, Debug)]
 pub enum BridgeError {
     #[error("Konfigurationsfehler: {0}")]
@@ -29,6 +89,14 @@ pub enum BridgeError {
     KeyParsing(String),
     #[error("Event-Build-Fehler: {0}")]
     EventBuild(String),
+    #[error("Datenbank-Fehler: {0}")]
+    Database(#[from] database::DatabaseError),
+    #[error("Archiv-Fehler: {0}")]
+    Archive(String),
+    #[error("Task unerwartet beendet: {0}")]
+    TaskEnded(String),
+    #[error("{0}")]
+    Http(#[from] http::HttpError),
 }
 
 type Result<T> = std::result::Result<T, BridgeError>;
@@ -58,17 +126,60 @@ async fn init_nostr_client(keys: &Keys, relays: &[String], config: &Config) -> R
     Ok(client)
 }
 
-/// Sendet eine Nachricht an Nostr mit flexibler Verschlüsselung
+/// Sendeversuche Richtung Nostr. Telegram hat das Update schon bestätigt, ein
+/// freigegebener Claim allein führt also zu keinem neuen Versuch.
+const NOSTR_SEND_ATTEMPTS: u32 = 3;
+
+/// Wartezeit vor dem zweiten Versuch, danach jeweils verdoppelt (2 s + 4 s,
+/// bleibt unter der Shutdown-Frist)
+const NOSTR_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Wiederholt `send` mit exponentiellem Backoff, bis es klappt oder die
+/// Versuche aufgebraucht sind
+async fn send_with_retry<F, Fut>(mut send: F) -> Result<EventId>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<EventId>>,
+{
+    let mut delay = NOSTR_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match send().await {
+            Err(e) if attempt < NOSTR_SEND_ATTEMPTS => {
+                warn!("Senden an Nostr fehlgeschlagen (Versuch {}/{}): {} – neuer Versuch in {:?}",
+                    attempt, NOSTR_SEND_ATTEMPTS, e, delay);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Sendet eine Nachricht an Nostr mit flexibler Verschlüsselung.
+///
+/// `extra_tags` (z.B. `p`-Tags verknüpfter User) werden nur an öffentliche und
+/// Gruppen-Nachrichten gehängt: DM-Clients würden weitere `p`-Tags als
+/// zusätzliche Empfänger interpretieren. `topic` ist das Ziel des Forum-Themas
+/// (Gruppe oder Hashtag); DM-Partner eines Themas kommen als `recipient_pubkey`.
+/// `route` ist meist ENCRYPTION_TYPE, bei Privatchats das DM-Format.
+#[allow(clippy::too_many_arguments)]
 async fn send_to_nostr(
     client: &Client,
     keys: &Keys,
+    route: &EncryptionType,
     recipient_pubkey: Option<&PublicKey>,
     text: &str,
     config: &Config,
+    mut extra_tags: Vec<Tag>,
+    poll: Option<&teloxide::types::Poll>,
+    topic: Option<&TopicDestination>,
 ) -> Result<EventId> {
-    debug!("Sende {:?} Nachricht: {}", config.encryption_type, &text[..text.len().min(50)]);
+    debug!("Sende {:?} Nachricht {}", route, Redacted(text));
+    trace!(content = text, "Ausgehender Nostr-Inhalt");
 
-    let event_builder = match config.encryption_type {
+    let event_builder = match route {
         EncryptionType::Nip04 => {
             info!("Sende NIP-04 verschlüsselte Nachricht...");
             let recipient = recipient_pubkey.ok_or_else(|| 
@@ -94,16 +205,29 @@ async fn send_to_nostr(
         },
         EncryptionType::Public => {
             info!("Sende öffentliche Nachricht...");
-            let public_text = format!("📱 Telegram-Weiterleitung:\n{}", text);
-            EventBuilder::text_note(public_text, Vec::new())
+            let mut public_text = format!("📱 Telegram-Weiterleitung:\n{}", text);
+            // Forum-Thema als Hashtag, im Text und als t-Tag (NIP-24)
+            if let Some(TopicDestination::Hashtag(hashtag)) = topic {
+                public_text.push_str(&format!("\n\n#{}", hashtag));
+                extra_tags.push(Tag::Hashtag(hashtag.clone()));
+            }
+            match poll {
+                // NIP-88: Umfrage als eigenes Event, der Text dient als Beschriftung
+                Some(poll) => content::poll_event(poll, &public_text, extra_tags),
+                None => EventBuilder::text_note(public_text, extra_tags),
+            }
         },
         EncryptionType::Group => {
             // NIP-29 Gruppen-Modus (Legacy-Unterstützung)
             // Hinweis: Dieser Modus ist für Nostr-Gruppen gedacht, nicht für DM-Bridge
             // Für DM-Bridge verwenden Sie EncryptionType::Nip04 oder Nip17
             info!("Sende Gruppen-Nachricht (NIP-29)...");
+            let topic_group = match topic {
+                Some(TopicDestination::Group(group_id)) => Some(group_id.as_str()),
+                _ => None,
+            };
             let group_event_id = EventId::from_hex(
-                config.get_group_event_id().ok_or_else(|| 
+                topic_group.or(config.get_group_event_id()).ok_or_else(|| 
                     BridgeError::Config(ConfigError::InvalidValue {
                         var: "NOSTR_GROUP_EVENT_ID".to_string(),
                         msg: "Gruppen-Event-ID fehlt".to_string(),
@@ -113,17 +237,15 @@ async fn send_to_nostr(
         
             
             // NIP-29 Gruppen-Nachricht (Kind 9) - KORRIGIERT
-            EventBuilder::new(
-                Kind::Custom(9),
-                text,
-                vec![
-                    Tag::event(group_event_id), // KORRIGIERT: Tag::event statt Tag::Event
-                    Tag::Generic(
-                        TagKind::Custom("h".to_string().into()), // KORRIGIERT: .into() für Cow<str>
-                        vec![hex::encode(group_event_id.as_bytes())],
-                    ),
-                ],
-            )
+            let mut tags = vec![
+                Tag::event(group_event_id), // KORRIGIERT: Tag::event statt Tag::Event
+                Tag::Generic(
+                    TagKind::Custom("h".to_string()),
+                    vec![hex::encode(group_event_id.as_bytes())],
+                ),
+            ];
+            tags.extend(extra_tags);
+            EventBuilder::new(Kind::Custom(9), text, tags)
         }
     };
     
@@ -131,93 +253,178 @@ async fn send_to_nostr(
         .map_err(|e| BridgeError::EventBuild(e.to_string()))?;
     
     let event_id = client.send_event(event).await?;
-    info!("Nachricht ({:?}) an Nostr gesendet! Event-ID: {}", config.encryption_type, event_id);
+    info!("Nachricht ({:?}) an Nostr gesendet! Event-ID: {}", route, event_id);
     Ok(event_id)
 }
 
+/// Aktuelle Unix-Zeit in Sekunden
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
 /// Behandelt eingehende Telegram-Nachrichten
+#[allow(clippy::too_many_arguments)]
+#[instrument(name = "forward", skip_all, fields(
+    direction = MessageDirection::TelegramToNostr.to_string(),
+    route = config.encryption_type.as_str(),
+    telegram_chat_id = message.chat.id.0,
+    telegram_message_id = message.id.0,
+    nostr_event_id = field::Empty,
+))]
 async fn handle_telegram_message(
+    bot: Bot,
     message: Message,
     client: Arc<Client>,
     config: Arc<Config>,
     keys: Arc<Keys>,
     recipient_pubkey: Option<PublicKey>,
     db: Arc<Database>,
+    storage: Arc<dyn Storage>,
+    metrics: Arc<Metrics>,
 ) -> Result<()> {
+    let received = Instant::now();
     debug!("Nachricht empfangen von Chat-ID: {}", message.chat.id.0);
 
-    // Nur Nachrichten aus der gewünschten Gruppe weiterleiten
-    if message.chat.id.0 != config.telegram_group_id {
-        debug!("Nachricht ignoriert - falsche Gruppe");
+    // Bot-Befehle (/link, /unlink) funktionieren in der Gruppe und im Privatchat
+    let is_command_chat = message.chat.id.0 == config.telegram_group_id || message.chat.is_private();
+    if is_command_chat && commands::handle_command(&bot, &message, &db, &keys, &config).await {
         return Ok(());
     }
 
-    // Loop-Schutz: Prüfen ob Nachricht bereits verarbeitet wurde
-    let telegram_msg_id = message.id.0 as i64;
-    if db.telegram_message_exists(message.chat.id.0, telegram_msg_id)
-        .unwrap_or(false) {
-        debug!("Nachricht bereits verarbeitet (Loop-Schutz): {}", telegram_msg_id);
+    // Nur Nachrichten aus der gewünschten Gruppe und aus eingerichteten
+    // Privatchats (TELEGRAM_PRIVATE_CHATS) weiterleiten
+    let private_partner = message.chat.is_private()
+        .then(|| config.sources.private_chat_partner(message.chat.id.0))
+        .flatten();
+    if message.chat.id.0 != config.telegram_group_id && private_partner.is_none() {
+        debug!("Nachricht ignoriert - falsche Gruppe");
         return Ok(());
     }
 
-    if let Some(text) = message.text() {
+    let telegram_msg_id = message.id.0 as i64;
+
+    // Privatchats sind eigene DM-Bridges mit ihrem Partner
+    let route = match private_partner {
+        Some(_) => sources::private_chat_route(&config.encryption_type),
+        None => config.encryption_type.clone(),
+    };
+    let recipient_pubkey = private_partner.or(recipient_pubkey);
+    Span::current().record("route", route.as_str());
+
+    // Forum-Thema: eigenes Nostr-Ziel, DMs gehen an den Partner des Themas
+    let is_topic_message = matches!(&message.kind, MessageKind::Common(common) if common.is_topic_message);
+    let thread_id = message.thread_id.filter(|_| is_topic_message);
+    let topic = thread_id.and_then(|thread_id| config.topics.destination(thread_id));
+    let recipient_pubkey = match topic {
+        Some(TopicDestination::DirectMessage(partner)) => Some(*partner),
+        _ => recipient_pubkey,
+    };
+
+    // Text, Standort, Umfrage oder Kontakt; Umfragen gehen öffentlich als NIP-88-Event
+    let nip88_polls = route == EncryptionType::Public;
+    if let Some(content) = TelegramContent::from_message(&message, config.templates.language, nip88_polls) {
+        // Loop-Schutz: Nachricht vor dem Senden atomar reservieren, damit doppelte
+        // Updates oder mehrere Bridge-Instanzen sie nur einmal weiterleiten
+        let claim_id = match storage.claim_telegram_message(message.chat.id.0, telegram_msg_id, unix_now()).await? {
+            Some(claim_id) => claim_id,
+            None => {
+                debug!("Nachricht bereits verarbeitet (Loop-Schutz): {}", telegram_msg_id);
+                return Ok(());
+            }
+        };
+
         let sender_name = message.from()
             .map(|u| u.full_name())
             .unwrap_or_else(|| "Unbekannt".to_string());
 
         info!("Verarbeite Nachricht von: {}", sender_name);
 
-        // HIER Logging ergänzen:
-        info!("Nachricht von {}: {}", sender_name, text);
+        // Verknüpfte User werden mit npub angezeigt (und in öffentlichen Modi per p-Tag markiert)
+        let linked_pubkey = match message.from() {
+            Some(user) => identity::linked_pubkey(&db, user.id.0 as i64).await,
+            None => None,
+        };
+        let sender_name = match linked_pubkey {
+            Some(pubkey) => format!("{} ({})", sender_name, pubkey.to_bech32().unwrap_or_default()),
+            None => sender_name,
+        };
+
+        let mut extra_tags: Vec<Tag> = linked_pubkey.into_iter().map(Tag::public_key).collect();
+        let (text, poll) = match &content {
+            TelegramContent::Text(text, entities) => {
+                // @Erwähnungen verknüpfter User (bzw. Puppets) werden zu nostr:npub1… mit p-Tag,
+                // Formatierungen (fett, kursiv, Code, Links, …) zu Markdown
+                let (replacements, mentioned) = mentions::telegram_mention_replacements(
+                    text,
+                    entities,
+                    &db,
+                    &keys,
+                    config.uses_puppet_keys(),
+                ).await;
+                extra_tags.extend(
+                    mentioned.into_iter()
+                        .filter(|pk| Some(*pk) != linked_pubkey)
+                        .map(Tag::public_key)
+                );
+                (formatting::telegram_to_markdown(text, entities, &replacements), None)
+            }
+            TelegramContent::Rendered { text, tags, poll } => {
+                extra_tags.extend(tags.iter().cloned());
+                (text.clone(), *poll)
+            }
+        };
+
+        // In öffentlichen Modi signiert auf Wunsch der Puppet-Key des Users statt des Bridge-Keys
+        let signing_keys = match message.from() {
+            Some(user) if config.uses_puppet_keys() && private_partner.is_none() => {
+                match puppet::puppet_keys_for(&client, &db, &keys, user).await {
+                    Ok(puppet_keys) => puppet_keys,
+                    Err(e) => {
+                        warn!("Puppet-Key nicht verfügbar, signiere mit Bridge-Key: {}", e);
+                        (*keys).clone()
+                    }
+                }
+            }
+            _ => (*keys).clone(),
+        };
 
-        // Telegram-Datum (Unix-Timestamp) in lesbares Format umwandeln
-        let tz: Tz = env::var("TIMEZONE")
-            .unwrap_or_else(|_| "Europe/Berlin".to_string())
-            .parse()
-            .unwrap_or(chrono_tz::Europe::Berlin);
+        info!("Nachricht von {} {}", sender_name, Redacted(&text));
+        trace!(content = %text, "Telegram-Inhalt");
 
+        // Telegram-Datum (Unix-Timestamp) in TIMEZONE umrechnen
         #[allow(deprecated)]
         let dt = NaiveDateTime::from_timestamp(message.date.timestamp(), 0);
-        let local_dt = tz.from_utc_datetime(&dt);
-        let time_str = local_dt.format("%Y-%m-%d %H:%M:%S").to_string();
-        let time_short = local_dt.format("%H:%M").to_string();
-
-        // Formatiere die Nachricht mit Metadaten
-        let formatted_message = match config.encryption_type {
-            EncryptionType::Public => {
-                format!(
-                    "Von: {} ({})\n\n{}",
-                    sender_name,
-                    time_short,
-                    text
-                )
-            },
-            EncryptionType::Group => {
-                format!(
-                    "📱 Telegram → Nostr Gruppe\n👤 Von: {} ({})\n\n{}",
-                    sender_name,
-                    time_short,
-                    text
-                )
-            },
-            _ => {
-                format!(
-                    "📱 Telegram-Nachricht\n👤 Von: {}\n📅 Zeit: {}\n\n{}",
-                    sender_name,
-                    time_str,
-                    text
-                )
-            }
+        // In Forum-Themen antwortet jede Nachricht formal auf die Eröffnung des Themas
+        let reply = message.reply_to_message()
+            .filter(|replied| Some(replied.id.0) != thread_id)
+            .map(reply_context);
+        let forwarded = content::forwarded_from(&message);
+
+        // Formatiere die Nachricht mit Metadaten (Vorlage je Modus, TEMPLATE_TELEGRAM_TO_NOSTR*)
+        let context = MessageContext {
+            sender: &sender_name,
+            time: timezone().from_utc_datetime(&dt),
+            chat_title: message.chat.title(),
+            reply: reply.as_deref(),
+            forwarded: forwarded.as_deref(),
+            route: &route,
+            language: config.templates.language,
         };
+        let formatted_message = config.templates.telegram_to_nostr.render(&context, &text, str::to_string);
 
-        match send_to_nostr(&client, &keys, recipient_pubkey.as_ref(), &formatted_message, &config).await {
+        let sent = send_with_retry(|| {
+            send_to_nostr(&client, &signing_keys, &route, recipient_pubkey.as_ref(), &formatted_message, &config, extra_tags.clone(), poll, topic)
+        })
+        .await;
+        match sent {
             Ok(event_id) => {
-                // Erfolgreich gesendet - in Datenbank speichern
-                let timestamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() as i64;
+                Span::current().record("nostr_event_id", field::display(event_id));
+                metrics.record_forward(MessageDirection::TelegramToNostr, route.as_str(), received);
 
+                // Erfolgreich gesendet - Claim mit der Event-ID abschließen
                 let recipient_pubkey_str = recipient_pubkey
                     .map(|pk| pk.to_bech32().unwrap_or_else(|_| "unknown".to_string()))
                     .unwrap_or_else(|| "public".to_string());
@@ -226,25 +433,30 @@ async fn handle_telegram_message(
                     id: None,
                     telegram_chat_id: message.chat.id.0,
                     telegram_message_id: telegram_msg_id,
+                    telegram_thread_id: thread_id.map(i64::from),
                     nostr_event_id: event_id.to_hex(),
                     nostr_recipient_pubkey: recipient_pubkey_str,
                     direction: MessageDirection::TelegramToNostr,
-                    timestamp,
+                    timestamp: unix_now(),
                 };
 
-                // Prüfen ob Event-ID bereits existiert (kann passieren wenn wir unser eigenes Event empfangen)
-                if !db.nostr_event_exists(&event_id.to_hex()).unwrap_or(false) {
-                    if let Err(e) = db.save_mapping(&mapping) {
-                        error!("Fehler beim Speichern des Mappings: {}", e);
-                    } else {
-                        debug!("Mapping gespeichert: Telegram {} -> Nostr {}", telegram_msg_id, event_id);
-                    }
+                if let Err(e) = storage.complete_claim(claim_id, &mapping).await {
+                    error!("Fehler beim Speichern des Mappings: {}", e);
                 } else {
-                    debug!("Event-ID bereits in Datenbank, überspringe Speicherung");
+                    debug!("Mapping gespeichert: Telegram {} -> Nostr {}", telegram_msg_id, event_id);
+                    if config.archive_enabled {
+                        archive::store(&db, &keys, &mapping, &sender_name, content.archive_text()).await;
+                    }
                 }
             }
             Err(e) => {
-                error!("Fehler beim Senden an Nostr: {}", e);
+                error!("Nachricht {} nach {} Versuchen nicht an Nostr gesendet und verloren: {}",
+                    telegram_msg_id, NOSTR_SEND_ATTEMPTS, e);
+                metrics.record_send_failure(MessageDirection::TelegramToNostr, metrics::nostr_failure_cause(&e));
+                // Claim freigeben, damit die Nachricht nicht als weitergeleitet gilt
+                if let Err(e) = storage.release_claim(claim_id).await {
+                    error!("Fehler beim Freigeben des Claims: {}", e);
+                }
             }
         }
     }
@@ -252,6 +464,120 @@ async fn handle_telegram_message(
     Ok(())
 }
 
+/// Veröffentlicht Beiträge eingerichteter Kanäle (TELEGRAM_CHANNELS) mit dem
+/// Bridge-Key als Notiz oder NIP-23-Artikel. Kanäle sind reine Quellen, es gibt
+/// keinen Rückweg von Nostr.
+#[allow(clippy::too_many_arguments)]
+#[instrument(name = "forward", skip_all, fields(
+    direction = MessageDirection::TelegramToNostr.to_string(),
+    route = "channel",
+    telegram_chat_id = message.chat.id.0,
+    telegram_message_id = message.id.0,
+    nostr_event_id = field::Empty,
+))]
+async fn handle_channel_post(
+    message: Message,
+    client: Arc<Client>,
+    config: Arc<Config>,
+    keys: Arc<Keys>,
+    db: Arc<Database>,
+    storage: Arc<dyn Storage>,
+    metrics: Arc<Metrics>,
+) -> Result<()> {
+    let received = Instant::now();
+
+    let Some(format) = config.sources.channel(message.chat.id.0) else {
+        debug!("Kanalbeitrag ignoriert - Kanal nicht eingerichtet: {}", message.chat.id.0);
+        return Ok(());
+    };
+    // Standorte, Umfragen und Kontakte als Text; Beiträge ohne Text (z.B. Fotos) entfallen
+    let Some(content) = TelegramContent::from_message(&message, config.templates.language, false) else {
+        return Ok(());
+    };
+
+    let telegram_msg_id = message.id.0 as i64;
+    let claim_id = match storage.claim_telegram_message(message.chat.id.0, telegram_msg_id, unix_now()).await? {
+        Some(claim_id) => claim_id,
+        None => {
+            debug!("Kanalbeitrag bereits verarbeitet (Loop-Schutz): {}", telegram_msg_id);
+            return Ok(());
+        }
+    };
+
+    let (markdown, tags) = match &content {
+        TelegramContent::Text(text, entities) => (formatting::telegram_to_markdown(text, entities, &[]), Vec::new()),
+        TelegramContent::Rendered { text, tags, .. } => (text.clone(), tags.clone()),
+    };
+    let channel_name = message.chat.title().unwrap_or("Kanal").to_string();
+    info!("Kanalbeitrag aus {} ({:?}) {}", channel_name, format, Redacted(&markdown));
+
+    let builder = sources::channel_post_event(format, &markdown, message.chat.id.0, message.id.0, message.date.timestamp(), tags);
+    let published = match builder.to_event(&keys) {
+        Ok(event) => send_with_retry(|| async { Ok(client.send_event(event.clone()).await?) }).await,
+        Err(e) => Err(BridgeError::EventBuild(e.to_string())),
+    };
+
+    match published {
+        Ok(event_id) => {
+            Span::current().record("nostr_event_id", field::display(event_id));
+            let kind = match format {
+                ChannelFormat::Note => "note",
+                ChannelFormat::Article => "article",
+            };
+            info!("Kanalbeitrag als {} veröffentlicht! Event-ID: {}", kind, event_id);
+            metrics.record_forward(MessageDirection::TelegramToNostr, "channel", received);
+
+            let mapping = MessageMapping {
+                id: None,
+                telegram_chat_id: message.chat.id.0,
+                telegram_message_id: telegram_msg_id,
+                telegram_thread_id: None,
+                nostr_event_id: event_id.to_hex(),
+                nostr_recipient_pubkey: "public".to_string(),
+                direction: MessageDirection::TelegramToNostr,
+                timestamp: unix_now(),
+            };
+            if let Err(e) = storage.complete_claim(claim_id, &mapping).await {
+                error!("Fehler beim Speichern des Mappings: {}", e);
+            } else if config.archive_enabled {
+                archive::store(&db, &keys, &mapping, &channel_name, content.archive_text()).await;
+            }
+        }
+        Err(e) => {
+            error!("Kanalbeitrag {} nach {} Versuchen nicht veröffentlicht und verloren: {}",
+                telegram_msg_id, NOSTR_SEND_ATTEMPTS, e);
+            metrics.record_send_failure(MessageDirection::TelegramToNostr, metrics::nostr_failure_cause(&e));
+            if let Err(e) = storage.release_claim(claim_id).await {
+                error!("Fehler beim Freigeben des Claims: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Zeitzone für Zeitangaben in Nachrichten (TIMEZONE, Standard: Europe/Berlin)
+fn timezone() -> Tz {
+    env::var("TIMEZONE")
+        .unwrap_or_else(|_| "Europe/Berlin".to_string())
+        .parse()
+        .unwrap_or(chrono_tz::Europe::Berlin)
+}
+
+/// Länge des Nachrichtenanfangs im Antwort-Kontext
+const REPLY_SNIPPET_CHARS: usize = 50;
+
+/// Antwort-Kontext für Vorlagen: "Absender: Anfang der Nachricht…"
+fn reply_context(replied: &Message) -> String {
+    let sender = replied.from().map(|u| u.full_name()).unwrap_or_else(|| "Unbekannt".to_string());
+    let text = replied.text().or(replied.caption()).unwrap_or_default();
+    let mut snippet: String = text.chars().take(REPLY_SNIPPET_CHARS).collect();
+    if text.chars().count() > REPLY_SNIPPET_CHARS {
+        snippet.push('…');
+    }
+    format!("{}: {}", sender, snippet.replace('\n', " "))
+}
+
 /// Sendet eine Nachricht an Telegram
 async fn send_to_telegram(
     bot: &Bot,
@@ -262,265 +588,523 @@ async fn send_to_telegram(
     Ok(msg)
 }
 
-// Hilfsfunktion: Hole Profil-Name von Nostr (NIP-01 Metadata)
-async fn get_nostr_display_name(client: &Client, pubkey: &PublicKey) -> String {
-    // Versuche Metadata vom Relay zu holen
-    let filter = Filter::new()
-        .kind(Kind::Metadata)
-        .author(*pubkey)
-        .limit(1);
-    
-    match client.get_events_of(vec![filter], Some(std::time::Duration::from_secs(3))).await {
-        Ok(events) => {
-            if let Some(event) = events.first() {
-                // Parse Metadata JSON
-                if let Ok(metadata) = serde_json::from_str::<serde_json::Value>(&event.content) {
-                    // Versuche verschiedene Name-Felder
-                    if let Some(display_name) = metadata.get("display_name").and_then(|v| v.as_str()) {
-                        if !display_name.is_empty() {
-                            return format!("{} ({})", display_name, pubkey.to_bech32().unwrap_or_default());
-                        }
-                    }
-                    if let Some(name) = metadata.get("name").and_then(|v| v.as_str()) {
-                        if !name.is_empty() {
-                            return format!("{} ({})", name, pubkey.to_bech32().unwrap_or_default());
-                        }
-                    }
-                }
+/// Sendet eine HTML-formatierte Nachricht an Telegram, optional in das
+/// Forum-Thema `thread` und als Antwort auf `reply_to`. Lehnt Telegram das HTML
+/// ab (z.B. ungültige Link-URL), wird `fallback` als reiner Text gesendet.
+async fn send_html_to_telegram(
+    bot: &Bot,
+    chat_id: i64,
+    html: &str,
+    fallback: &str,
+    thread: Option<i32>,
+    reply_to: Option<MessageId>,
+) -> std::result::Result<teloxide::types::Message, teloxide::RequestError> {
+    let mut request = bot.send_message(ChatId(chat_id), html).parse_mode(ParseMode::Html);
+    if let Some(thread) = thread {
+        request = request.message_thread_id(thread);
+    }
+    if let Some(reply_to) = reply_to {
+        request = request.reply_to_message_id(reply_to);
+    }
+    match request.await {
+        Err(teloxide::RequestError::Api(e)) => {
+            warn!("Telegram lehnt formatierte Nachricht ab, sende als Text: {}", e);
+            let mut request = bot.send_message(ChatId(chat_id), fallback);
+            if let Some(thread) = thread {
+                request = request.message_thread_id(thread);
             }
-        },
-        Err(e) => {
-            debug!("Konnte Metadata nicht abrufen: {}", e);
+            if let Some(reply_to) = reply_to {
+                request = request.reply_to_message_id(reply_to);
+            }
+            request.await
         }
+        result => result,
+    }
+}
+
+/// Telegram-Limit für den sichtbaren Text einer Nachricht (in UTF-16-Einheiten)
+const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+/// Telegram-Limit für die Beschriftung einer Datei
+const TELEGRAM_CAPTION_LIMIT: usize = 1024;
+
+/// Sendet Markdown-Inhalt im Rahmen von `template` an Telegram. Zu lange Inhalte
+/// werden aufgeteilt und als Serie gesendet, in der jeder Teil auf den
+/// vorherigen antwortet; ab `document_threshold` Zeichen geht der Inhalt
+/// stattdessen als `.txt`-Datei. `thread` ist das Forum-Thema (`None`: Chat
+/// ohne Thema). Gibt die IDs aller gesendeten Nachrichten zurück – scheitert ein
+/// späterer Teil, die bis dahin gesendeten.
+async fn send_content_to_telegram(
+    bot: &Bot,
+    chat_id: i64,
+    thread: Option<i32>,
+    template: &Template,
+    context: &MessageContext<'_>,
+    content: &str,
+    document_threshold: Option<usize>,
+) -> std::result::Result<Vec<MessageId>, teloxide::RequestError> {
+    let frame = template.render(context, "", str::to_string);
+
+    if document_threshold.is_some_and(|threshold| content.chars().count() > threshold) {
+        let file_name = match context.language {
+            Language::De => "nachricht.txt",
+            Language::En => "message.txt",
+        };
+        let document = InputFile::memory(template.render(context, content, str::to_string).into_bytes()).file_name(file_name);
+        let caption: String = frame.trim().chars().take(TELEGRAM_CAPTION_LIMIT).collect();
+        let mut request = bot.send_document(ChatId(chat_id), document).caption(caption);
+        if let Some(thread) = thread {
+            request = request.message_thread_id(thread);
+        }
+        let msg = request.await?;
+        return Ok(vec![msg.id]);
+    }
+
+    // Der Rahmen der Vorlage umschließt den ersten Teil und zählt zu dessen Länge
+    let first_limit = TELEGRAM_MESSAGE_LIMIT.saturating_sub(frame.encode_utf16().count());
+    let parts = formatting::split_markdown_for_telegram(content, first_limit, TELEGRAM_MESSAGE_LIMIT);
+    if parts.len() > 1 {
+        info!("Nachricht zu lang für Telegram, sende {} Teile", parts.len());
+    }
+
+    let mut sent: Vec<MessageId> = Vec::with_capacity(parts.len());
+    for (index, part) in parts.iter().enumerate() {
+        let (html, fallback) = if index == 0 {
+            (
+                template.render(context, &part.html, formatting::escape_html),
+                template.render(context, &part.markdown, str::to_string),
+            )
+        } else {
+            (part.html.clone(), part.markdown.clone())
+        };
+        match send_html_to_telegram(bot, chat_id, &html, &fallback, thread, sent.last().copied()).await {
+            Ok(msg) => sent.push(msg.id),
+            Err(e) if sent.is_empty() => return Err(e),
+            Err(e) => {
+                error!("Teil {}/{} konnte nicht an Telegram gesendet werden: {}", index + 1, parts.len(), e);
+                break;
+            }
+        }
+    }
+    Ok(sent)
+}
+
+/// Entschlüsselt eine an die Bridge gerichtete DM (NIP-04 oder NIP-17 Gift Wrap)
+/// und gibt Absender und Inhalt zurück
+fn decrypt_direct_message(keys: &Keys, event: &Event) -> std::result::Result<(PublicKey, String), String> {
+    let secret_key = keys.secret_key().map_err(|e| e.to_string())?;
+
+    match event.kind {
+        Kind::EncryptedDirectMessage => {
+            // NIP-04: Entschlüsseln mit nip04
+            debug!("Verarbeite NIP-04 DM (Kind 4)");
+            let content = nip04::decrypt(secret_key, &event.pubkey, &event.content)
+                .map_err(|e| format!("NIP-04 Entschlüsselung fehlgeschlagen: {}", e))?;
+            Ok((event.pubkey, content))
+        },
+        Kind::GiftWrap => {
+            // NIP-17: Gift Wrap entschlüsseln
+            debug!("Verarbeite NIP-17 Gift Wrap (Kind 1059)");
+
+            // Gift Wrap ist AN uns (bridge_pubkey), entschlüsseln mit unserem Secret Key
+            let unwrapped_json = nip44::decrypt(secret_key, &event.pubkey, &event.content)
+                .map_err(|e| format!("Gift Wrap Entschlüsselung fehlgeschlagen: {}", e))?;
+            debug!("Gift Wrap entschlüsselt, parse Seal Event...");
+
+            // Parse das Seal Event (Kind 13)
+            let seal_event = Event::from_json(&unwrapped_json)
+                .map_err(|e| format!("Seal Event parse Fehler: {}", e))?;
+            debug!("Seal Event geparst, Sender: {}", seal_event.pubkey.to_bech32().unwrap_or_default());
+
+            // Entschlüssele das Seal (enthält das Rumor)
+            let rumor_json = nip44::decrypt(secret_key, &seal_event.pubkey, &seal_event.content)
+                .map_err(|e| format!("Seal Entschlüsselung fehlgeschlagen: {}", e))?;
+            debug!("Seal entschlüsselt, parse Rumor...");
+
+            // Parse das Rumor (die eigentliche Nachricht)
+            let rumor = serde_json::from_str::<serde_json::Value>(&rumor_json)
+                .map_err(|e| format!("Rumor JSON parse Fehler: {}", e))?;
+            let content = rumor.get("content")
+                .and_then(|c| c.as_str())
+                .ok_or_else(|| "Rumor enthält kein 'content' Feld".to_string())?;
+            debug!("Rumor erfolgreich entschlüsselt!");
+
+            Ok((seal_event.pubkey, content.to_string()))
+        },
+        _ => Err(format!("Unbekannter Event-Kind: {:?}", event.kind)),
+    }
+}
+
+/// Event-Kind, in dem DMs des Formats `route` ankommen (`None` ohne DMs)
+fn dm_kind(route: &EncryptionType) -> Option<Kind> {
+    match route {
+        EncryptionType::Nip04 => Some(Kind::EncryptedDirectMessage),
+        EncryptionType::Nip17 => Some(Kind::GiftWrap),
+        _ => None,
+    }
+}
+
+/// Filter für DMs an die Bridge - NIP-04 (Kind 4) und NIP-17 (Kind 1059 - Gift Wrap).
+/// Mit Since-Cursor werden alle seitdem verpassten Events abgefragt, sonst die letzten 50.
+fn dm_filters(bridge_pubkey: PublicKey, since: Option<i64>) -> Vec<Filter> {
+    let nip04 = Filter::new()
+        .kind(Kind::EncryptedDirectMessage) // Kind 4
+        .pubkey(bridge_pubkey);
+    let nip17 = Filter::new()
+        .kind(Kind::GiftWrap) // Kind 1059 für NIP-17
+        .pubkey(bridge_pubkey); // AN den Bridge-Bot
+
+    match since {
+        Some(since) => vec![
+            nip04.since(Timestamp::from((since - RESYNC_SLACK_SECS).max(0) as u64)),
+            nip17.since(Timestamp::from(resync_floor(since).max(0) as u64)),
+        ],
+        None => vec![nip04.limit(50), nip17.limit(50)],
     }
-    
-    // Fallback: Nur npub
-    pubkey.to_bech32().unwrap_or_else(|_| "unknown".to_string())
 }
 
 /// Hört auf Nostr-Events und leitet sie an Telegram weiter
+#[allow(clippy::too_many_arguments)]
 async fn listen_nostr_events(
     client: Arc<Client>,
     keys: Arc<Keys>,
     config: Arc<Config>,
     bot: Bot,
     db: Arc<Database>,
+    storage: Arc<dyn Storage>,
     recipient_pubkey: Option<PublicKey>,
+    profiles: ProfileCache,
+    metrics: Arc<Metrics>,
+    shutdown: Shutdown,
 ) -> Result<()> {
     info!("Starte Nostr-Event-Listener...");
 
-    // Nur für DM-Modi (NIP-04/NIP-17)
-    if !config.needs_encryption() {
-        info!("Nostr-Listener nur für DM-Modi aktiv");
-        return Ok(());
+    // Weitergeleitet werden nur DMs des Empfängers im konfigurierten Format (NIP-04/NIP-17)
+    // und DMs der Privatchat-Partner. In allen Modi nimmt der Listener außerdem
+    // Bestätigungscodes für /link entgegen.
+    let forward_kind = dm_kind(&config.encryption_type);
+    if forward_kind.is_none() {
+        if config.sources.has_private_chats() {
+            info!("Nostr-Listener leitet in {:?} nur DMs der Privatchat-Partner weiter", config.encryption_type);
+        } else {
+            info!("Nostr-Listener leitet nur in DM-Modi weiter ({:?}: nur /link-Bestätigungen)", config.encryption_type);
+        }
     }
 
-    let recipient = match recipient_pubkey {
-        Some(pk) => pk,
-        None => {
-            warn!("Kein Empfänger-Pubkey konfiguriert, Nostr-Listener wird nicht gestartet");
-            return Ok(());
-        }
-    };
+    let recipient = forward_kind.and(recipient_pubkey);
+    if forward_kind.is_some() && recipient.is_none() {
+        warn!("Kein Empfänger-Pubkey konfiguriert, es werden keine DMs weitergeleitet");
+    }
 
-    // Filter für DMs - unterstützt sowohl NIP-04 als auch NIP-17
     let bridge_pubkey = keys.public_key();
-    
-    // Filter basierend auf Encryption-Type
-    let filters = match config.encryption_type {
-        EncryptionType::Nip04 => {
-            info!("Konfiguriere Filter für NIP-04 (Kind 4)");
-            vec![
-                Filter::new()
-                    .kind(Kind::EncryptedDirectMessage) // Kind 4
-                    .author(recipient)
-                    .pubkey(bridge_pubkey)
-                    .limit(50)
-            ]
-        },
-        EncryptionType::Nip17 => {
-            info!("Konfiguriere Filter für NIP-17 (Kind 1059 - Gift Wrap)");
-            vec![
-                Filter::new()
-                    .kind(Kind::GiftWrap) // Kind 1059 für NIP-17
-                    .pubkey(bridge_pubkey) // AN den Bridge-Bot
-                    .limit(50)
-            ]
-        },
-        _ => {
-            warn!("Encryption-Type {:?} wird für Nostr-Listener nicht unterstützt", config.encryption_type);
-            return Ok(());
-        }
-    };
+    let since = db.nostr_since().await.unwrap_or_else(|e| {
+        warn!("Since-Cursor nicht lesbar, starte ohne: {}", e);
+        None
+    });
 
     info!("Subscribing mit Filter:");
     info!("  - Encryption-Type: {:?}", config.encryption_type);
     info!("  - Bridge-Bot Pubkey: {}", bridge_pubkey.to_bech32().unwrap_or_default());
-    info!("  - Erwarteter Sender: {}", recipient.to_bech32().unwrap_or_default());
+    if let Some(recipient) = recipient {
+        info!("  - Erwarteter Sender: {}", recipient.to_bech32().unwrap_or_default());
+    }
+    if forward_kind.is_some() && !config.topics.is_empty() {
+        info!("  - Forum-Themen mit eigenem DM-Partner: {}", config.topics.len());
+    }
+    if config.sources.has_private_chats() {
+        info!("  - Privatchats mit eigenem DM-Partner: {}", config.sources.private_chat_count());
+    }
+
+    if let Some(since) = since {
+        info!("  - Since-Cursor: {}", since);
+    }
 
-    let subscription_id = client.subscribe(filters.clone(), None).await;
+    let mut subscription_id = client.subscribe(dm_filters(bridge_pubkey, since), None).await;
     info!("Nostr-Subscription aktiv mit ID: {:?}", subscription_id);
-    info!("Bridge-Bot Pubkey: {}", bridge_pubkey.to_bech32().unwrap_or_default());
-    info!("Erwarteter Sender: {}", recipient.to_bech32().unwrap_or_default());
 
     // Event-Stream verarbeiten
     let mut notifications = client.notifications();
     info!("Warte auf Notifications vom Relay-Pool...");
     
-    while let Ok(notification) = notifications.recv().await {
-        info!(">>> Notification empfangen: {:?}", notification);
+    // Lebenszeichen für /healthz und den systemd-Watchdog, auch wenn keine Events eintreffen
+    let mut heartbeat = tokio::time::interval(health::LISTENER_HEARTBEAT_INTERVAL);
+
+    loop {
+        let notification = tokio::select! {
+            result = notifications.recv() => match result {
+                Ok(notification) => notification,
+                Err(RecvError::Lagged(skipped)) => {
+                    // Verpasste Events erneut von den Relays holen; Doubletten fängt der Loop-Schutz ab
+                    let since = db.nostr_since().await.unwrap_or_default();
+                    warn!("{} Notifications verpasst, abonniere neu ab Since-Cursor {:?}", skipped, since);
+                    client.unsubscribe(subscription_id).await;
+                    subscription_id = client.subscribe(dm_filters(bridge_pubkey, since), None).await;
+                    continue;
+                }
+                Err(RecvError::Closed) => {
+                    return Err(BridgeError::TaskEnded("Notification-Stream geschlossen".to_string()));
+                }
+            },
+            _ = heartbeat.tick() => {
+                metrics.heartbeat(health::NOSTR_LISTENER);
+                continue;
+            }
+            // Erst zwischen zwei Events: ein laufendes wird noch vollständig weitergeleitet
+            _ = shutdown.triggered() => {
+                client.unsubscribe(subscription_id).await;
+                info!("Nostr-Listener beendet");
+                return Ok(());
+            }
+        };
+        trace!(?notification, "Notification empfangen");
         
-        if let RelayPoolNotification::Event { event, .. } = notification {
-            info!("Event empfangen! Kind: {:?}, Author: {}", event.kind, event.pubkey.to_bech32().unwrap_or_default());
-            
-            // Loop-Schutz: Prüfen ob Event bereits verarbeitet wurde
-            let event_id_hex = event.id.to_hex();
-            if db.nostr_event_exists(&event_id_hex).unwrap_or(false) {
-                debug!("Nostr-Event bereits verarbeitet (Loop-Schutz): {}", event_id_hex);
+        if let RelayPoolNotification::Event { subscription_id: sub_id, event, .. } = notification {
+            let received = Instant::now();
+
+            // Events anderer Subscriptions (z.B. Profil-Cache) gehören nicht zum DM-Listener
+            if sub_id != subscription_id {
                 continue;
             }
 
-            // Entschlüsseln basierend auf Event-Kind
-            let secret_key = keys.secret_key().expect("Failed to get secret key");
-            let decrypted_content_result = match event.kind {
-                Kind::EncryptedDirectMessage => {
-                    // NIP-04: Entschlüsseln mit nip04
-                    info!("Verarbeite NIP-04 DM (Kind 4)");
-                    
-                    // Prüfe ob vom erwarteten Sender
-                    if event.pubkey != recipient {
-                        warn!("NIP-04 Event von anderem Pubkey ignoriert: {} (erwartet: {})",
-                            event.pubkey.to_bech32().unwrap_or_default(),
-                            recipient.to_bech32().unwrap_or_default());
-                        continue;
-                    }
-                    
-                    nip04::decrypt(secret_key, &event.pubkey, &event.content)
-                        .map_err(|e| format!("NIP-04 Entschlüsselung fehlgeschlagen: {}", e))
-                },
-                Kind::GiftWrap => {
-                    // NIP-17: Gift Wrap entschlüsseln
-                    info!("Verarbeite NIP-17 Gift Wrap (Kind 1059)");
-                    
-                    // Gift Wrap ist AN uns (bridge_pubkey), entschlüsseln mit unserem Secret Key
-                    match nip44::decrypt(secret_key, &event.pubkey, &event.content) {
-                        Ok(unwrapped_json) => {
-                            info!("Gift Wrap entschlüsselt, parse Seal Event...");
-                            
-                            // Parse das Seal Event (Kind 13)
-                            match Event::from_json(&unwrapped_json) {
-                                Ok(seal_event) => {
-                                    info!("Seal Event geparst, Sender: {}", seal_event.pubkey.to_bech32().unwrap_or_default());
-                                    
-                                    // Prüfe ob vom erwarteten Sender
-                                    if seal_event.pubkey != recipient {
-                                        warn!("Seal Event von anderem Pubkey ignoriert: {} (erwartet: {})",
-                                            seal_event.pubkey.to_bech32().unwrap_or_default(),
-                                            recipient.to_bech32().unwrap_or_default());
-                                        continue;
-                                    }
-                                    
-                                    // Entschlüssele das Seal (enthält das Rumor)
-                                    match nip44::decrypt(secret_key, &seal_event.pubkey, &seal_event.content) {
-                                        Ok(rumor_json) => {
-                                            info!("Seal entschlüsselt, parse Rumor...");
-                                            
-                                            // Parse das Rumor (die eigentliche Nachricht)
-                                            match serde_json::from_str::<serde_json::Value>(&rumor_json) {
-                                                Ok(rumor) => {
-                                                    if let Some(content) = rumor.get("content").and_then(|c| c.as_str()) {
-                                                        info!("Rumor erfolgreich entschlüsselt!");
-                                                        Ok(content.to_string())
-                                                    } else {
-                                                        Err("Rumor enthält kein 'content' Feld".to_string())
-                                                    }
-                                                },
-                                                Err(e) => Err(format!("Rumor JSON parse Fehler: {}", e))
-                                            }
-                                        },
-                                        Err(e) => Err(format!("Seal Entschlüsselung fehlgeschlagen: {}", e))
-                                    }
-                                },
-                                Err(e) => Err(format!("Seal Event parse Fehler: {}", e))
-                            }
-                        },
-                        Err(e) => Err(format!("Gift Wrap Entschlüsselung fehlgeschlagen: {}", e))
-                    }
-                },
-                _ => {
-                    warn!("Unbekannter Event-Kind: {:?}", event.kind);
-                    continue;
-                }
+            // Cursor sofort vorrücken: beim Neu-Abonnieren wird mit Puffer ab dem Cursor
+            // abgefragt, dieses Event also auch nach einem Absturz erneut geliefert.
+            // Zeitstempel aus der Zukunft (falsche Uhr des Absenders) werden gekappt.
+            let created_at = (event.created_at.as_u64() as i64).min(unix_now());
+            if let Err(e) = db.advance_nostr_since(created_at).await {
+                warn!("Since-Cursor nicht gespeichert: {}", e);
+            }
+
+            handle_nostr_event(
+                &event,
+                received,
+                &client,
+                &keys,
+                &config,
+                &bot,
+                &db,
+                &storage,
+                forward_kind,
+                recipient,
+                &profiles,
+                &metrics,
+            ).await;
+            // Vorschau-Abrufe können dauern; ein verarbeitetes Event ist selbst ein Lebenszeichen
+            metrics.heartbeat(health::NOSTR_LISTENER);
+        }
+    }
+}
+
+/// Verarbeitet ein Event der DM-Subscription: Loop-Schutz, Entschlüsseln,
+/// /link-Bestätigungen und Weiterleitung an Telegram
+#[allow(clippy::too_many_arguments)]
+#[instrument(name = "forward", skip_all, fields(
+    direction = MessageDirection::NostrToTelegram.to_string(),
+    route = config.encryption_type.as_str(),
+    nostr_event_id = %event.id,
+    telegram_message_id = field::Empty,
+))]
+async fn handle_nostr_event(
+    event: &Event,
+    received: Instant,
+    client: &Client,
+    keys: &Keys,
+    config: &Config,
+    bot: &Bot,
+    db: &Arc<Database>,
+    storage: &Arc<dyn Storage>,
+    forward_kind: Option<Kind>,
+    recipient: Option<PublicKey>,
+    profiles: &ProfileCache,
+    metrics: &Metrics,
+) {
+    info!("Event empfangen! Kind: {:?}, Author: {}", event.kind, event.pubkey.to_bech32().unwrap_or_default());
+    
+    // Schneller Loop-Schutz vor dem Entschlüsseln (z.B. dasselbe Event von
+    // mehreren Relays); maßgeblich ist der Claim vor dem Weiterleiten
+    let event_id_hex = event.id.to_hex();
+    match storage.nostr_event_exists(&event_id_hex, unix_now()).await {
+        Ok(false) => {}
+        Ok(true) => {
+            debug!("Nostr-Event bereits verarbeitet (Loop-Schutz): {}", event_id_hex);
+            return;
+        }
+        Err(e) => {
+            // Im Zweifel nicht weiterleiten, statt eine Doublette zu riskieren
+            error!("Loop-Schutz nicht prüfbar, Event {} übersprungen: {}", event_id_hex, e);
+            return;
+        }
+    }
+
+    // Entschlüsseln basierend auf Event-Kind
+    let (sender, decrypted_content) = match decrypt_direct_message(keys, event) {
+        Ok(dm) => dm,
+        Err(e) => {
+            error!("Fehler beim Entschlüsseln der Nostr-DM: {}", e);
+            metrics.record_decrypt_failure(event.kind);
+            return;
+        }
+    };
+
+    // Bestätigungscode für eine /link-Anfrage?
+    if let Some(link) = identity::confirm_link(db, &sender, &decrypted_content).await {
+        let notice = format!(
+            "🔗 {} ist jetzt mit {} verknüpft",
+            link.telegram_name,
+            sender.to_bech32().unwrap_or_default()
+        );
+        if let Err(e) = send_to_telegram(bot, config.telegram_group_id, &notice).await {
+            warn!("Fehler beim Senden der Verknüpfungsbestätigung: {}", e);
+        }
+        return;
+    }
+
+    // DMs eines Privatchat-Partners (TELEGRAM_PRIVATE_CHATS) gehen in dessen
+    // Chat, sonst nur vom erwarteten Sender im konfigurierten DM-Format in die
+    // Gruppe; DMs der Partner von Forum-Themen gehen in ihr Thema
+    let private_route = sources::private_chat_route(&config.encryption_type);
+    let private_chat = config.sources.private_chat_for_partner(&sender)
+        .filter(|_| Some(event.kind) == dm_kind(&private_route));
+    let topic_thread = config.topics.thread_for_partner(&sender);
+    let (chat_id, topic_thread, route) = match private_chat {
+        Some(chat_id) => (chat_id, None, &private_route),
+        None if Some(event.kind) == forward_kind && (Some(sender) == recipient || topic_thread.is_some()) => {
+            (config.telegram_group_id, topic_thread, &config.encryption_type)
+        }
+        None => {
+            warn!("DM von anderem Pubkey ignoriert: {} (erwartet: {})",
+                sender.to_bech32().unwrap_or_default(),
+                recipient.and_then(|pk| pk.to_bech32().ok()).unwrap_or_default());
+            return;
+        }
+    };
+    Span::current().record("route", route.as_str());
+
+    let claim_id = match storage.claim_nostr_event(&event_id_hex, chat_id, unix_now()).await {
+        Ok(Some(claim_id)) => claim_id,
+        Ok(None) => {
+            debug!("Nostr-Event bereits verarbeitet (Loop-Schutz): {}", event_id_hex);
+            return;
+        }
+        Err(e) => {
+            error!("Loop-Schutz nicht prüfbar, Event {} übersprungen: {}", event_id_hex, e);
+            return;
+        }
+    };
+
+    info!("Nachricht erfolgreich entschlüsselt {}", Redacted(&decrypted_content));
+    trace!(content = %decrypted_content, "Nostr-Inhalt");
+
+    // Hole Display-Name des Absenders
+    let sender_name = profiles.display_name(&sender).await;
+
+    // NIP-27-Referenzen werden zu Namen/Links, verknüpfte User zu Telegram-@Erwähnungen
+    let mut content = mentions::render_nostr_references(
+        &decrypted_content,
+        db,
+        profiles,
+        &config.nostr_web_client_url,
+    ).await;
+
+    // Referenzierte Notizen und Artikel als Vorschau anhängen
+    if config.nostr_event_previews {
+        for preview in preview::render_previews(client, &decrypted_content, profiles, &config.nostr_web_client_url).await {
+            content.push_str("\n\n");
+            content.push_str(&preview);
+        }
+    }
+
+    // Formatiere Nachricht für Telegram (TEMPLATE_NOSTR_TO_TELEGRAM*), Markdown im Inhalt wird zu Telegram-HTML
+    let context = MessageContext {
+        sender: &sender_name,
+        // Gift Wraps tragen einen verschleierten Zeitstempel, daher zählt der Empfang
+        time: chrono::Utc::now().with_timezone(&timezone()),
+        chat_title: None,
+        reply: None,
+        forwarded: None,
+        route,
+        language: config.templates.language,
+    };
+    let template = &config.templates.nostr_to_telegram;
+
+    // An Telegram senden (lange Inhalte als Serie oder Datei)
+    match send_content_to_telegram(
+        bot,
+        chat_id,
+        topic_thread,
+        template,
+        &context,
+        &content,
+        config.telegram_document_threshold,
+    ).await {
+        Ok(message_ids) => {
+            let telegram_msg_id = message_ids[0];
+            Span::current().record("telegram_message_id", telegram_msg_id.0);
+            info!("Nachricht an Telegram gesendet");
+            metrics.record_forward(MessageDirection::NostrToTelegram, route.as_str(), received);
+            
+            // Claim mit der Telegram-Nachricht abschließen
+            let mapping = MessageMapping {
+                id: None,
+                telegram_chat_id: chat_id,
+                telegram_message_id: telegram_msg_id.0 as i64,
+                telegram_thread_id: topic_thread.map(i64::from),
+                nostr_event_id: event_id_hex.clone(),
+                nostr_recipient_pubkey: sender.to_bech32().unwrap_or_else(|_| "unknown".to_string()),
+                direction: MessageDirection::NostrToTelegram,
+                timestamp: unix_now(),
             };
 
-            // Verarbeite entschlüsselten Inhalt
-            match decrypted_content_result {
-                Ok(decrypted_content) => {
-                    info!("Nachricht erfolgreich entschlüsselt!");
-                    info!("Inhalt: {}", decrypted_content);
-                    
-                    // Hole Display-Name des Absenders
-                    let sender_name = get_nostr_display_name(&client, &recipient).await;
-                    
-                    // Formatiere Nachricht für Telegram
-                    let formatted_message = format!(
-                        "📨 Nostr-DM\n👤 Von: {}\n\n{}",
-                        sender_name,
-                        decrypted_content
-                    );
-
-                    // An Telegram senden
-                    match send_to_telegram(&bot, config.telegram_group_id, &formatted_message).await {
-                        Ok(telegram_msg) => {
-                            info!("Nachricht an Telegram gesendet");
-                            
-                            // In Datenbank speichern
-                            let timestamp = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs() as i64;
-
-                            let mapping = MessageMapping {
-                                id: None,
-                                telegram_chat_id: config.telegram_group_id,
-                                telegram_message_id: telegram_msg.id.0 as i64,
-                                nostr_event_id: event_id_hex.clone(),
-                                nostr_recipient_pubkey: recipient.to_bech32().unwrap_or_else(|_| "unknown".to_string()),
-                                direction: MessageDirection::NostrToTelegram,
-                                timestamp,
-                            };
-
-                            if let Err(e) = db.save_mapping(&mapping) {
-                                error!("Fehler beim Speichern des Mappings: {}", e);
-                            } else {
-                                debug!("Mapping gespeichert: Nostr {} -> Telegram {}", event_id_hex, telegram_msg.id.0);
-                            }
-                        }
-                        Err(e) => {
-                            error!("Fehler beim Senden an Telegram: {}", e);
-                        }
+            if let Err(e) = storage.complete_claim(claim_id, &mapping).await {
+                error!("Fehler beim Speichern des Mappings: {}", e);
+            } else {
+                debug!("Mapping gespeichert: Nostr {} -> Telegram {}", event_id_hex, telegram_msg_id.0);
+                let part_ids: Vec<i64> = message_ids[1..].iter().map(|id| id.0 as i64).collect();
+                if !part_ids.is_empty() {
+                    if let Err(e) = storage.save_message_parts(chat_id, &event_id_hex, &part_ids).await {
+                        error!("Fehler beim Speichern der Nachrichtenteile: {}", e);
                     }
                 }
-                Err(e) => {
-                    error!("Fehler beim Entschlüsseln der Nostr-DM: {}", e);
+                if config.archive_enabled {
+                    archive::store(db, keys, &mapping, &sender_name, &decrypted_content).await;
                 }
             }
         }
+        Err(e) => {
+            error!("Fehler beim Senden an Telegram: {}", e);
+            metrics.record_send_failure(MessageDirection::NostrToTelegram, metrics::telegram_failure_cause(&e));
+            if let Err(e) = storage.release_claim(claim_id).await {
+                error!("Fehler beim Freigeben des Claims: {}", e);
+            }
+        }
     }
-
-    Ok(())
 }
 
+/// Maximale Wartezeit auf laufende Weiterleitungen beim Herunterfahren
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Hauptfunktion: Telegram-Nachrichten empfangen und an Nostr weiterleiten
 #[tokio::main]
 async fn main() -> Result<()> {
+    // .env laden (auch RUST_LOG und LOG_FORMAT)
+    dotenv().ok();
+
     // Logging initialisieren
-    env_logger::init();
+    logging::init()?;
+
+    let command = match cli::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let CliCommand::Help = command {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
     info!("Bridge startet...");
-    
-    // .env laden
-    dotenv().ok();
-    
+
     // Konfiguration laden
     let config = Arc::new(Config::from_env()?);
     info!("Konfiguration geladen");
@@ -531,6 +1115,11 @@ async fn main() -> Result<()> {
         Keys::parse(&config.nostr_private_key)
             .map_err(|e| BridgeError::KeyParsing(e.to_string()))?
     );
+
+    // Kommandozeilen-Befehle arbeiten nur auf der Datenbank
+    if command != CliCommand::Run {
+        return cli::run(command, &config, &keys).await;
+    }
     
     // Empfänger-Pubkey nur für verschlüsselte Modi
     let recipient_pubkey = if config.needs_encryption() {
@@ -555,15 +1144,54 @@ async fn main() -> Result<()> {
                 msg: format!("Fehler beim Öffnen der Datenbank: {}", e),
             }))?
     );
-    info!("📊 Datenbank initialisiert: {}", config.database_path);
+    info!("📊 Datenbank initialisiert: {} (Schema-Version {})",
+        config.database_path,
+        db.schema_version().await.unwrap_or_default());
+
+    // Mapping-Speicher: SQLite (Standard) oder zentral in PostgreSQL
+    let storage: Arc<dyn Storage> = match config.postgres_url {
+        #[cfg(feature = "postgres")]
+        Some(ref url) => {
+            let storage = postgres::PostgresStorage::connect(url).await?;
+            info!("🐘 Mappings werden in PostgreSQL gespeichert");
+            Arc::new(storage)
+        }
+        _ => db.clone(),
+    };
+
+    // Prometheus-Metriken (ausgeliefert unter /metrics, falls HTTP_LISTEN_ADDR gesetzt ist)
+    let metrics = Arc::new(Metrics::new());
+    tokio::spawn(metrics.clone().run_sampler(client.clone(), db.clone()));
 
     // Statistiken anzeigen
-    if let Ok((total, t_to_n, n_to_t)) = db.get_stats() {
-        info!("📈 Datenbank-Statistiken: {} Nachrichten ({} T→N, {} N→T)", total, t_to_n, n_to_t);
+    if let Ok((total, t_to_n, n_to_t)) = storage.get_stats().await {
+        let pruned = storage.get_pruned_count().await.unwrap_or_default();
+        info!("📈 Datenbank-Statistiken: {} Nachrichten ({} T→N, {} N→T), {} durch Retention gelöscht",
+            total, t_to_n, n_to_t, pruned);
+    }
+
+    // Retention für message_mapping (optional)
+    if let Some(ref retention) = config.retention {
+        info!("🧹 Retention aktiv: max. Alter {:?}s, max. {:?} Mappings",
+            retention.max_age_secs, retention.max_rows);
+        tokio::spawn(retention::run_retention(storage.clone(), db.clone(), retention.clone()));
     }
 
+    let shutdown = Shutdown::new();
+
+    // Profil-Cache für Anzeigenamen (Kind 0) mit Hintergrund-Aktualisierung
+    let profiles = ProfileCache::new(client.clone(), db.clone(), config.profile_cache_ttl_secs, metrics.clone());
+    let profile_cache = profiles.clone();
+    let profile_shutdown = shutdown.clone();
+    tokio::spawn(supervisor::supervise(profile::PROFILE_CACHE, metrics.clone(), shutdown.clone(), move || {
+        profile_cache.clone().run(profile_shutdown.clone())
+    }));
+
     info!("🚀 Bridge läuft ({:?})", config.encryption_type);
     info!("📱 Telegram-Gruppe: {}", config.telegram_group_id);
+    if config.sources.channel_count() > 0 {
+        info!("📢 Telegram-Kanäle als Quelle: {}", config.sources.channel_count());
+    }
     
     match config.encryption_type {
         EncryptionType::Public => {
@@ -587,11 +1215,40 @@ async fn main() -> Result<()> {
 
     let bot = Bot::new(&config.telegram_bot_token);
 
-    // Graceful shutdown Handler
-    let shutdown_signal = async {
-        signal::ctrl_c().await.expect("Fehler beim Installieren des Shutdown-Handlers");
-        info!("🛑 Shutdown-Signal erhalten, Bridge wird beendet...");
-    };
+    // Health-Checks (/healthz, /readyz) und systemd-Watchdog
+    let health = Arc::new(Health::new(metrics.clone(), client.clone(), db.clone(), storage.clone()));
+    tokio::spawn(health::run_telegram_probe(bot.clone(), metrics.clone()));
+    tokio::spawn(health::run_systemd_watchdog(health.clone()));
+
+    // Bridge-Profil (Kind 0) veröffentlichen und periodisch erneuern
+    if let Some(ref profile) = config.bridge_profile {
+        tokio::spawn(profile::run_profile_refresh(client.clone(), profile.clone()));
+    }
+
+    // Eingebauter HTTP-Server (NIP-05, Metriken)
+    if let Some(addr) = config.http_listen_addr {
+        let nip05 = config.bridge_profile.as_ref()
+            .and_then(|profile| profile.nip05_name())
+            .map(|name| Nip05Identity {
+                name: name.to_string(),
+                public_key: keys.public_key(),
+                relays: config.nostr_relays.clone(),
+            });
+        if let Some(ref identity) = nip05 {
+            info!("🪪 NIP-05 aktiv: /.well-known/nostr.json?name={}", identity.name);
+        }
+        let router = http::router(Arc::new(HttpState {
+            nip05,
+            metrics: Some(metrics.clone()),
+            health: Some(health.clone()),
+        }));
+        let socket = http::bind(addr)?;
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(socket, router).await {
+                error!("HTTP-Server beendet: {}", e);
+            }
+        });
+    }
 
     // Telegram-Handler (Task 1: Telegram → Nostr)
     let telegram_bot = bot.clone();
@@ -599,24 +1256,112 @@ async fn main() -> Result<()> {
     let telegram_config = config.clone();
     let telegram_keys = keys.clone();
     let telegram_db = db.clone();
+    let telegram_storage = storage.clone();
+    let telegram_metrics = metrics.clone();
     let telegram_recipient = recipient_pubkey;
-    
-    let telegram_task = tokio::spawn(async move {
-        teloxide::repl(telegram_bot, move |message: Message| {
-            let client = telegram_client.clone();
-            let config = telegram_config.clone();
-            let keys = telegram_keys.clone();
-            let db = telegram_db.clone();
-            let recipient_pubkey = telegram_recipient;
-            
+    let telegram_shutdown = shutdown.clone();
+
+    // Beide Richtungen laufen unter Aufsicht und werden nach einem Fehler neu gestartet
+    let telegram_task = tokio::spawn(supervisor::supervise(health::TELEGRAM, metrics.clone(), shutdown.clone(), move || {
+        let client = telegram_client.clone();
+        let config = telegram_config.clone();
+        let keys = telegram_keys.clone();
+        let db = telegram_db.clone();
+        let storage = telegram_storage.clone();
+        let metrics = telegram_metrics.clone();
+        let recipient_pubkey = telegram_recipient;
+        let shutdown = telegram_shutdown.clone();
+        let webhook_config = config.webhook.clone();
+
+        let channel_client = client.clone();
+        let channel_config = config.clone();
+        let channel_keys = keys.clone();
+        let channel_db = db.clone();
+        let channel_storage = storage.clone();
+        let channel_metrics = metrics.clone();
+
+        let messages = Update::filter_message().endpoint(move |bot: Bot, message: Message| {
+            let client = client.clone();
+            let config = config.clone();
+            let keys = keys.clone();
+            let db = db.clone();
+            let storage = storage.clone();
+            let metrics = metrics.clone();
+
             async move {
-                if let Err(e) = handle_telegram_message(message, client, config, keys, recipient_pubkey, db).await {
+                if let Err(e) = handle_telegram_message(bot, message, client, config, keys, recipient_pubkey, db, storage, metrics).await {
                     error!("Fehler beim Verarbeiten der Telegram-Nachricht: {}", e);
                 }
-                Ok(())
+                respond(())
             }
-        }).await;
-    });
+        });
+
+        // Kanalbeiträge (TELEGRAM_CHANNELS) kommen als eigene Update-Art
+        let channel_posts = Update::filter_channel_post().endpoint(move |message: Message| {
+            let client = channel_client.clone();
+            let config = channel_config.clone();
+            let keys = channel_keys.clone();
+            let db = channel_db.clone();
+            let storage = channel_storage.clone();
+            let metrics = channel_metrics.clone();
+
+            async move {
+                if let Err(e) = handle_channel_post(message, client, config, keys, db, storage, metrics).await {
+                    error!("Fehler beim Verarbeiten des Kanalbeitrags: {}", e);
+                }
+                respond(())
+            }
+        });
+
+        let handler = dptree::entry().branch(messages).branch(channel_posts);
+
+        // Ohne eigenen Ctrl-C-Handler: beendet wird nur über das Shutdown-Signal in main
+        let mut dispatcher = Dispatcher::builder(telegram_bot.clone(), handler)
+            .default_handler(|_| async {})
+            .build();
+        let token = dispatcher.shutdown_token();
+        let webhook_bot = telegram_bot.clone();
+        let receiver_metrics = telegram_metrics.clone();
+        async move {
+            // Webhook (TELEGRAM_WEBHOOK_URL), bei Fehlern beim Einrichten Long Polling
+            let webhook = match webhook_config {
+                Some(ref webhook_config) => match webhook::listen(webhook_bot.clone(), webhook_config, receiver_metrics.clone()).await {
+                    Ok(listener) => Some(listener),
+                    Err(e) => {
+                        warn!("Telegram-Webhook nicht verfügbar, nutze Long Polling: {}", e);
+                        None
+                    }
+                },
+                None => None,
+            };
+            let dispatch = async {
+                match webhook {
+                    Some(listener) => {
+                        let error_handler = LoggingErrorHandler::with_custom_text("Fehler im Telegram-Webhook");
+                        dispatcher.dispatch_with_listener(listener, error_handler).await
+                    }
+                    None => {
+                        let listener = polling::listen(webhook_bot, receiver_metrics).await;
+                        let error_handler = LoggingErrorHandler::with_custom_text("Fehler beim Telegram-Polling");
+                        dispatcher.dispatch_with_listener(listener, error_handler).await
+                    }
+                }
+            };
+            tokio::pin!(dispatch);
+            tokio::select! {
+                // z.B. Webhook-Server beendet: der Supervisor startet neu (notfalls mit Polling)
+                _ = &mut dispatch => return Err(BridgeError::TaskEnded("Telegram-Empfang beendet".to_string())),
+                _ = shutdown.triggered() => {}
+            }
+
+            // Polling bzw. Webhook beenden; bereits empfangene Updates werden noch abgearbeitet
+            if token.shutdown().is_ok() {
+                dispatch.await;
+            }
+            info!("Telegram-Empfang beendet");
+            Ok(())
+        }
+    }));
 
     // Nostr-Listener (Task 2: Nostr → Telegram)
     let nostr_client = client.clone();
@@ -624,34 +1369,64 @@ async fn main() -> Result<()> {
     let nostr_config = config.clone();
     let nostr_bot = bot.clone();
     let nostr_db = db.clone();
+    let nostr_storage = storage.clone();
     let nostr_recipient = recipient_pubkey;
-    
-    let nostr_task = tokio::spawn(async move {
-        if let Err(e) = listen_nostr_events(
-            nostr_client,
-            nostr_keys,
-            nostr_config,
-            nostr_bot,
-            nostr_db,
+    let nostr_profiles = profiles.clone();
+    let nostr_metrics = metrics.clone();
+    let nostr_shutdown = shutdown.clone();
+
+    let nostr_task = tokio::spawn(supervisor::supervise(health::NOSTR_LISTENER, metrics.clone(), shutdown.clone(), move || {
+        listen_nostr_events(
+            nostr_client.clone(),
+            nostr_keys.clone(),
+            nostr_config.clone(),
+            nostr_bot.clone(),
+            nostr_db.clone(),
+            nostr_storage.clone(),
             nostr_recipient,
-        ).await {
-            error!("Fehler im Nostr-Listener: {}", e);
-        }
-    });
+            nostr_profiles.clone(),
+            nostr_metrics.clone(),
+            nostr_shutdown.clone(),
+        )
+    }));
+
+    health::notify_ready();
+
+    // Nur ein Shutdown-Signal (SIGINT/SIGTERM) beendet die Bridge
+    supervisor::shutdown_signal().await;
+    health::notify_stopping();
 
-    // Auf Shutdown-Signal oder Task-Completion warten
+    // Geordnet herunterfahren: keine neuen Updates annehmen, laufende Weiterleitungen
+    // abschließen, damit nach einem Neustart keine Doubletten entstehen
+    info!("⏳ Warte bis zu {:?} auf laufende Weiterleitungen...", SHUTDOWN_DRAIN_TIMEOUT);
+    shutdown.trigger();
+    let drain = async {
+        let _ = telegram_task.await;
+        let _ = nostr_task.await;
+    };
     tokio::select! {
-        _ = shutdown_signal => {
-            info!("Bridge beendet.");
-            Ok(())
+        result = tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, drain) => {
+            if result.is_err() {
+                // Offene Claims verfallen nach CLAIM_TIMEOUT_SECS und sind dann wieder frei
+                warn!("Nicht alle Weiterleitungen rechtzeitig abgeschlossen");
+            }
         }
-        _ = telegram_task => {
-            info!("Telegram-Task beendet");
-            Ok(())
-        }
-        _ = nostr_task => {
-            info!("Nostr-Task beendet");
-            Ok(())
+        _ = supervisor::shutdown_signal() => {
+            warn!("Zweites Shutdown-Signal, beende sofort");
         }
     }
+
+    // Subscriptions schließen und Relays sauber trennen
+    client.unsubscribe_all().await;
+    if let Err(e) = client.disconnect().await {
+        warn!("Fehler beim Trennen der Relays: {}", e);
+    }
+
+    // Ausstehende Datenbank-Aufträge abarbeiten und WAL übernehmen
+    if let Err(e) = db.checkpoint().await {
+        warn!("WAL-Checkpoint fehlgeschlagen: {}", e);
+    }
+
+    info!("Bridge beendet.");
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,428 @@
+use nostr_sdk::prelude::*;
+use tracing::warn;
+use std::ops::Range;
+use teloxide::types::{MessageEntity, MessageEntityKind, MessageEntityRef};
+
+use crate::database::Database;
+use crate::formatting::escape_markdown_text;
+use crate::identity;
+use crate::profile::ProfileCache;
+use crate::puppet;
+
+/// Bech32-Präfixe, die als NIP-27-Referenz erkannt werden
+const REFERENCE_PREFIXES: [&str; 5] = ["npub1", "nprofile1", "note1", "nevent1", "naddr1"];
+
+/// NIP-27-URI-Schema
+const NOSTR_URI_SCHEME: &str = "nostr:";
+
+/// Eine Nostr-Referenz (NIP-27) im Nachrichtentext
+#[derive(Debug, Clone)]
+pub struct NostrReference {
+    /// Byte-Bereich im Text, inklusive eines eventuellen `nostr:`-Präfixes
+    pub range: Range<usize>,
+    /// Bech32-Kodierung ohne `nostr:`-Präfix
+    pub bech32: String,
+    pub entity: Nip19,
+}
+
+/// Findet `nostr:npub1…`, `nostr:nprofile1…`, `nostr:note1…`, `nostr:nevent1…` und
+/// `nostr:naddr1…` im Text. Ohne `nostr:`-Präfix werden Referenzen nur am
+/// Wortanfang erkannt, damit z.B. Pfade in URLs unangetastet bleiben.
+pub fn find_nostr_references(content: &str) -> Vec<NostrReference> {
+    let mut references = Vec::new();
+    let mut pos = 0;
+
+    while pos < content.len() {
+        let rest = &content[pos..];
+        let Some(prefix_offset) = REFERENCE_PREFIXES.iter().filter_map(|p| rest.find(p)).min() else {
+            break;
+        };
+
+        let start = pos + prefix_offset;
+        let end = start + content[start..]
+            .find(|c: char| !is_bech32_char(c))
+            .unwrap_or(content.len() - start);
+
+        let before = &content[..start];
+        let range_start = if before.ends_with(NOSTR_URI_SCHEME) {
+            Some(start - NOSTR_URI_SCHEME.len())
+        } else if before.chars().next_back().is_none_or(is_reference_boundary) {
+            Some(start)
+        } else {
+            None
+        };
+
+        if let Some(range_start) = range_start {
+            let bech32 = &content[start..end];
+            if let Ok(entity) = Nip19::from_bech32(bech32) {
+                references.push(NostrReference {
+                    range: range_start..end,
+                    bech32: bech32.to_string(),
+                    entity,
+                });
+            }
+        }
+
+        pos = end.max(start + 1);
+    }
+
+    references
+}
+
+/// Baut den Link zu einem Web-Client aus der URL-Vorlage (`{id}` = Bech32)
+pub fn web_client_link(template: &str, bech32: &str) -> String {
+    template.replace("{id}", bech32)
+}
+
+/// Ersetzt NIP-27-Referenzen in eingehenden Nostr-Nachrichten durch lesbare
+/// Namen und Links für Telegram. Verknüpfte User und Puppets werden zu ihrer
+/// Telegram-@Erwähnung.
+pub async fn render_nostr_references(
+    content: &str,
+    db: &Database,
+    profiles: &ProfileCache,
+    web_client_url: &str,
+) -> String {
+    let references = find_nostr_references(content);
+    if references.is_empty() {
+        return content.to_string();
+    }
+
+    let mut result = String::with_capacity(content.len());
+    let mut last = 0;
+
+    for reference in references {
+        result.push_str(&content[last..reference.range.start]);
+        let link = web_client_link(web_client_url, &reference.bech32);
+
+        let rendered = match reference.entity {
+            Nip19::Pubkey(pubkey) => render_pubkey(&pubkey, db, profiles, &link).await,
+            Nip19::Profile(ref profile) => render_pubkey(&profile.public_key, db, profiles, &link).await,
+            Nip19::EventId(_) | Nip19::Event(_) | Nip19::Coordinate(_) => format!("📝 {}", link),
+            _ => content[reference.range.clone()].to_string(),
+        };
+
+        result.push_str(&rendered);
+        last = reference.range.end;
+    }
+
+    result.push_str(&content[last..]);
+    result
+}
+
+/// Namen stammen aus Telegram bzw. fremden Kind-0-Profilen und werden vor dem
+/// Einfügen maskiert, damit sie keine Formatierung oder Links einschleusen
+async fn render_pubkey(pubkey: &PublicKey, db: &Database, profiles: &ProfileCache, link: &str) -> String {
+    let hex = pubkey.to_hex();
+
+    match db.find_link_by_nostr_pubkey(&hex).await {
+        Ok(Some(link)) => return escape_markdown_text(&identity::telegram_mention(&link)),
+        Ok(None) => {}
+        Err(e) => warn!("Fehler beim Lesen der Verknüpfung: {}", e),
+    }
+
+    match db.find_puppet_by_nostr_pubkey(&hex).await {
+        Ok(Some(puppet)) => {
+            return match puppet.telegram_username {
+                Some(username) => escape_markdown_text(&format!("@{}", username)),
+                None => escape_markdown_text(&puppet.display_name),
+            };
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Fehler beim Lesen des Puppets: {}", e),
+    }
+
+    match profiles.name(pubkey).await {
+        Some(name) => format!("{} ({})", escape_markdown_text(&name), link),
+        None => link.to_string(),
+    }
+}
+
+/// Ersetzungen für Telegram-Erwähnungen (`@username` und Text-Mentions ohne
+/// Username) verknüpfter User bzw. Puppets durch `nostr:npub1…` als Byte-Bereiche
+/// im Text, dazu die erwähnten Pubkeys für `p`-Tags
+pub async fn telegram_mention_replacements(
+    text: &str,
+    entities: &[MessageEntity],
+    db: &Database,
+    bridge_keys: &Keys,
+    use_puppets: bool,
+) -> (Vec<(Range<usize>, String)>, Vec<PublicKey>) {
+    let mut replacements = Vec::new();
+    let mut mentioned = Vec::new();
+    let mut last = 0;
+
+    let mut refs = MessageEntityRef::parse(text, entities);
+    refs.sort_by_key(|entity| entity.start());
+
+    for entity in refs {
+        let pubkey = match entity.kind() {
+            MessageEntityKind::Mention => {
+                resolve_username(entity.text().trim_start_matches('@'), db, use_puppets).await
+            }
+            MessageEntityKind::TextMention { user } => {
+                let user_id = user.id.0 as i64;
+                identity::linked_pubkey(db, user_id).await.or_else(|| {
+                    use_puppets
+                        .then(|| puppet::derive_puppet_keys(bridge_keys, user_id).ok())
+                        .flatten()
+                        .map(|keys| keys.public_key())
+                })
+            }
+            _ => None,
+        };
+
+        let Some(pubkey) = pubkey else {
+            continue;
+        };
+        if entity.start() < last {
+            continue;
+        }
+
+        replacements.push((
+            entity.range(),
+            format!("{}{}", NOSTR_URI_SCHEME, pubkey.to_bech32().unwrap_or_default()),
+        ));
+        last = entity.end();
+
+        if !mentioned.contains(&pubkey) {
+            mentioned.push(pubkey);
+        }
+    }
+
+    (replacements, mentioned)
+}
+
+async fn resolve_username(username: &str, db: &Database, use_puppets: bool) -> Option<PublicKey> {
+    let linked = db.find_link_by_telegram_username(username).await.unwrap_or_else(|e| {
+        warn!("Fehler beim Lesen der Verknüpfung: {}", e);
+        None
+    });
+    if let Some(link) = linked {
+        return PublicKey::from_hex(&link.nostr_pubkey).ok();
+    }
+
+    if !use_puppets {
+        return None;
+    }
+
+    db.find_puppet_by_telegram_username(username).await
+        .unwrap_or_else(|e| {
+            warn!("Fehler beim Lesen des Puppets: {}", e);
+            None
+        })
+        .and_then(|puppet| PublicKey::from_hex(&puppet.nostr_pubkey).ok())
+}
+
+fn is_bech32_char(c: char) -> bool {
+    c.is_ascii_digit() || c.is_ascii_lowercase()
+}
+
+fn is_reference_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | '[' | '"' | '\'' | '@' | ',' | ';')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_find_nostr_references() {
+        let keys = Keys::generate();
+        let npub = keys.public_key().to_bech32().unwrap();
+        let note = EventId::all_zeros().to_bech32().unwrap();
+
+        let content = format!(
+            "Hallo nostr:{npub}, siehe {note} und https://njump.me/{npub} sowie nostr:nsec1ungueltig"
+        );
+        let references = find_nostr_references(&content);
+
+        assert_eq!(references.len(), 2);
+        assert_eq!(&content[references[0].range.clone()], format!("nostr:{npub}"));
+        assert!(matches!(references[0].entity, Nip19::Pubkey(pk) if pk == keys.public_key()));
+        assert_eq!(references[1].bech32, note);
+        assert!(matches!(references[1].entity, Nip19::EventId(_)));
+    }
+
+    #[tokio::test]
+    async fn test_telegram_mention_replacements() {
+        let db = Database::new(":memory:").unwrap();
+        let bridge_keys = Keys::generate();
+        let alice = Keys::generate().public_key();
+
+        db.create_pending_link(
+            &crate::database::IdentityLink {
+                telegram_user_id: 7,
+                telegram_username: Some("Alice".to_string()),
+                telegram_name: "Alice".to_string(),
+                nostr_pubkey: alice.to_hex(),
+                linked_at: 100,
+            },
+            "tg-link-00000001",
+        ).await.unwrap();
+        db.confirm_pending_link(&alice.to_hex(), "tg-link-00000001", 0, 100).await.unwrap();
+
+        // "👋 @alice und @bob" – Offsets in UTF-16 (👋 zählt doppelt)
+        let text = "👋 @alice und @bob";
+        let entities = vec![
+            MessageEntity::new(MessageEntityKind::Mention, 3, 6),
+            MessageEntity::new(MessageEntityKind::Mention, 14, 4),
+        ];
+
+        let (replacements, mentioned) = telegram_mention_replacements(text, &entities, &db, &bridge_keys, false).await;
+        let translated = crate::formatting::telegram_to_markdown(text, &entities, &replacements);
+        assert_eq!(translated, format!("👋 nostr:{} und @bob", alice.to_bech32().unwrap()));
+        assert_eq!(mentioned, vec![alice]);
+    }
+
+    #[tokio::test]
+    async fn test_telegram_mention_replacements_offsets() {
+        let db = Database::new(":memory:").unwrap();
+        let bridge_keys = Keys::generate();
+        let alice = Keys::generate().public_key();
+        let carol = Keys::generate().public_key();
+
+        for (user_id, username, pubkey) in [(7, Some("Alice"), alice), (8, None, carol)] {
+            let challenge = format!("tg-link-0000000{}", user_id);
+            db.create_pending_link(
+                &crate::database::IdentityLink {
+                    telegram_user_id: user_id,
+                    telegram_username: username.map(str::to_string),
+                    telegram_name: "Name".to_string(),
+                    nostr_pubkey: pubkey.to_hex(),
+                    linked_at: 100,
+                },
+                &challenge,
+            ).await.unwrap();
+            db.confirm_pending_link(&pubkey.to_hex(), &challenge, 0, 100).await.unwrap();
+        }
+
+        let carol_user: teloxide::types::User =
+            serde_json::from_value(serde_json::json!({"id": 8, "is_bot": false, "first_name": "Carol"})).unwrap();
+        let mention = |offset, length| MessageEntity::new(MessageEntityKind::Mention, offset, length);
+        let text_mention =
+            |offset, length| MessageEntity::new(MessageEntityKind::TextMention { user: carol_user.clone() }, offset, length);
+        let a = format!("nostr:{}", alice.to_bech32().unwrap());
+        let c = format!("nostr:{}", carol.to_bech32().unwrap());
+
+        // (Text, Entities mit UTF-16-Offsets, erwarteter Text, erwähnte Pubkeys)
+        let cases = [
+            ("@alice", vec![mention(0, 6)], a.clone(), vec![alice]),
+            ("@ALICE!", vec![mention(0, 6)], format!("{a}!"), vec![alice]),
+            // 🇩🇪 besteht aus zwei Codepoints außerhalb der BMP: 4 UTF-16-Einheiten, 8 Bytes
+            ("🇩🇪 @alice", vec![mention(5, 6)], format!("🇩🇪 {a}"), vec![alice]),
+            ("ä👋 @alice 👋 @alice", vec![mention(4, 6), mention(14, 6)], format!("ä👋 {a} 👋 {a}"), vec![alice]),
+            ("👋 Hallo Carol!", vec![text_mention(9, 5)], format!("👋 Hallo {c}!"), vec![carol]),
+            // Unsortierte Entities werden nach Position verarbeitet
+            ("@alice und Carol", vec![text_mention(11, 5), mention(0, 6)], format!("{a} und {c}"), vec![alice, carol]),
+            // Bei überlappenden Entities gewinnt die erste, die nächste wird übersprungen
+            ("@alice", vec![mention(0, 6), text_mention(0, 6)], a.clone(), vec![alice]),
+            ("@alice und", vec![text_mention(0, 10), mention(0, 6)], c.clone(), vec![carol]),
+            // Formatierungen um eine Erwähnung stören nicht
+            ("@alice und", vec![MessageEntity::bold(0, 10), mention(0, 6)], format!("{a} und"), vec![alice]),
+            ("👋 @bob", vec![mention(3, 4)], "👋 @bob".to_string(), vec![]),
+        ];
+
+        for (text, entities, expected, expected_mentioned) in cases {
+            let (replacements, mentioned) =
+                telegram_mention_replacements(text, &entities, &db, &bridge_keys, false).await;
+
+            let mut replaced = text.to_string();
+            for (range, replacement) in replacements.iter().rev() {
+                replaced.replace_range(range.clone(), replacement);
+            }
+            assert_eq!(replaced, expected, "{:?}", text);
+            assert_eq!(mentioned, expected_mentioned, "{:?}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_telegram_mention_replacements_puppets() {
+        let db = Database::new(":memory:").unwrap();
+        let bridge_keys = Keys::generate();
+        let dora = puppet::derive_puppet_keys(&bridge_keys, 9).unwrap().public_key();
+        let erik = puppet::derive_puppet_keys(&bridge_keys, 10).unwrap().public_key();
+
+        db.save_puppet(&crate::database::Puppet {
+            telegram_user_id: 9,
+            nostr_pubkey: dora.to_hex(),
+            display_name: "Dora".to_string(),
+            telegram_username: Some("dora".to_string()),
+            profile_published_at: None,
+        }).await.unwrap();
+
+        // Erik hat (noch) kein Puppet gespeichert, sein Schlüssel wird abgeleitet
+        let erik_user: teloxide::types::User =
+            serde_json::from_value(serde_json::json!({"id": 10, "is_bot": false, "first_name": "Erik"})).unwrap();
+        let text = "@dora und Erik";
+        let entities = vec![
+            MessageEntity::new(MessageEntityKind::Mention, 0, 5),
+            MessageEntity::new(MessageEntityKind::TextMention { user: erik_user }, 10, 4),
+        ];
+
+        let (replacements, mentioned) = telegram_mention_replacements(text, &entities, &db, &bridge_keys, true).await;
+        let translated = crate::formatting::telegram_to_markdown(text, &entities, &replacements);
+        assert_eq!(
+            translated,
+            format!("nostr:{} und nostr:{}", dora.to_bech32().unwrap(), erik.to_bech32().unwrap())
+        );
+        assert_eq!(mentioned, vec![dora, erik]);
+
+        // Ohne Puppets bleiben beide Erwähnungen unverändert
+        let (replacements, mentioned) = telegram_mention_replacements(text, &entities, &db, &bridge_keys, false).await;
+        assert!(replacements.is_empty());
+        assert!(mentioned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_render_nostr_references_escapes_names() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let client = Arc::new(Client::default());
+        let profiles = ProfileCache::new(client, db.clone(), 3600, Arc::new(crate::metrics::Metrics::new()));
+        let (linked, puppet, stranger) =
+            (Keys::generate().public_key(), Keys::generate().public_key(), Keys::generate().public_key());
+
+        db.create_pending_link(
+            &crate::database::IdentityLink {
+                telegram_user_id: 7,
+                telegram_username: None,
+                telegram_name: "*Eve*".to_string(),
+                nostr_pubkey: linked.to_hex(),
+                linked_at: 100,
+            },
+            "tg-link-00000007",
+        ).await.unwrap();
+        db.confirm_pending_link(&linked.to_hex(), "tg-link-00000007", 0, 100).await.unwrap();
+        db.save_puppet(&crate::database::Puppet {
+            telegram_user_id: 8,
+            nostr_pubkey: puppet.to_hex(),
+            display_name: "[klick](tg://user?id=1)".to_string(),
+            telegram_username: None,
+            profile_published_at: None,
+        }).await.unwrap();
+        db.upsert_profile(&crate::database::CachedProfile {
+            pubkey: stranger.to_hex(),
+            name: None,
+            display_name: Some("`x` _y_ ~~z~~".to_string()),
+            nip05: None,
+            nip05_verified: false,
+            event_created_at: 1000,
+            fetched_at: crate::unix_now(),
+        }).await.unwrap();
+
+        let content = format!(
+            "nostr:{} nostr:{} nostr:{}",
+            linked.to_bech32().unwrap(),
+            puppet.to_bech32().unwrap(),
+            stranger.to_bech32().unwrap()
+        );
+        let rendered = render_nostr_references(&content, &db, &profiles, "https://njump.me/{id}").await;
+        let html = crate::formatting::markdown_to_telegram_html(&rendered);
+
+        assert!(html.starts_with("*Eve* [klick](tg://user?id=1) `x` _y_ ~~z~~ (https://njump.me/npub1"), "{}", html);
+        for tag in ["<b>", "<i>", "<s>", "<code>", "<a "] {
+            assert!(!html.contains(tag), "{} in {}", tag, html);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use tracing::warn;
+use nostr_sdk::client::Error as ClientError;
+use nostr_sdk::pool::pool::Error as PoolError;
+use nostr_sdk::prelude::*;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use teloxide::RequestError;
+
+use crate::database::{Database, MessageDirection};
+use crate::{unix_now, BridgeError};
+
+/// Intervall, in dem Relay-Zustände und Warteschlangen abgetastet werden
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Buckets der Latenz-Histogramme in Sekunden (Empfang bis Bestätigung der Gegenseite)
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Prometheus-Metriken der Bridge, ausgeliefert unter `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    messages: IntCounterVec,
+    send_failures: IntCounterVec,
+    decrypt_failures: IntCounterVec,
+    relays: IntGaugeVec,
+    db_queue_depth: IntGauge,
+    forward_latency: HistogramVec,
+    profile_cache: IntCounterVec,
+    last_forward: IntGaugeVec,
+    heartbeats: IntGaugeVec,
+    restarts: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let messages = IntCounterVec::new(
+            Opts::new("bridge_messages_forwarded_total", "Weitergeleitete Nachrichten"),
+            &["direction", "mode"],
+        )
+        .unwrap();
+        let send_failures = IntCounterVec::new(
+            Opts::new("bridge_send_failures_total", "Fehlgeschlagene Weiterleitungen nach Ursache"),
+            &["direction", "cause"],
+        )
+        .unwrap();
+        let decrypt_failures = IntCounterVec::new(
+            Opts::new("bridge_decrypt_failures_total", "Nicht entschlüsselbare Nostr-DMs"),
+            &["kind"],
+        )
+        .unwrap();
+        let relays = IntGaugeVec::new(
+            Opts::new("bridge_relays", "Relays nach Verbindungszustand"),
+            &["status"],
+        )
+        .unwrap();
+        let db_queue_depth = IntGauge::new(
+            "bridge_db_queue_depth",
+            "Aufträge, die auf den Datenbank-Thread warten",
+        )
+        .unwrap();
+        let forward_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "bridge_forward_latency_seconds",
+                "Zeit vom Empfang einer Nachricht bis zur Bestätigung durch die Gegenseite",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["direction"],
+        )
+        .unwrap();
+        let profile_cache = IntCounterVec::new(
+            Opts::new("bridge_profile_cache_lookups_total", "Profil-Abfragen nach Ergebnis (hit, stale, miss)"),
+            &["result"],
+        )
+        .unwrap();
+        let last_forward = IntGaugeVec::new(
+            Opts::new("bridge_last_forward_timestamp_seconds", "Unix-Zeit der letzten erfolgreichen Weiterleitung"),
+            &["direction"],
+        )
+        .unwrap();
+        let heartbeats = IntGaugeVec::new(
+            Opts::new("bridge_heartbeat_timestamp_seconds", "Unix-Zeit des letzten Lebenszeichens einer Komponente"),
+            &["component"],
+        )
+        .unwrap();
+        let restarts = IntCounterVec::new(
+            Opts::new("bridge_component_restarts_total", "Neustarts einer Komponente durch den Supervisor"),
+            &["component"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(messages.clone())).unwrap();
+        registry.register(Box::new(send_failures.clone())).unwrap();
+        registry.register(Box::new(decrypt_failures.clone())).unwrap();
+        registry.register(Box::new(relays.clone())).unwrap();
+        registry.register(Box::new(db_queue_depth.clone())).unwrap();
+        registry.register(Box::new(forward_latency.clone())).unwrap();
+        registry.register(Box::new(profile_cache.clone())).unwrap();
+        registry.register(Box::new(last_forward.clone())).unwrap();
+        registry.register(Box::new(heartbeats.clone())).unwrap();
+        registry.register(Box::new(restarts.clone())).unwrap();
+
+        Metrics {
+            registry,
+            messages,
+            send_failures,
+            decrypt_failures,
+            relays,
+            db_queue_depth,
+            forward_latency,
+            profile_cache,
+            last_forward,
+            heartbeats,
+            restarts,
+        }
+    }
+
+    /// Zählt eine erfolgreiche Weiterleitung; `received` ist der Empfangszeitpunkt
+    pub fn record_forward(&self, direction: MessageDirection, mode: &str, received: Instant) {
+        self.messages.with_label_values(&[direction.to_string(), mode]).inc();
+        self.forward_latency
+            .with_label_values(&[direction.to_string()])
+            .observe(received.elapsed().as_secs_f64());
+        self.last_forward
+            .with_label_values(&[direction.to_string()])
+            .set(unix_now());
+    }
+
+    /// Unix-Zeit der letzten erfolgreichen Weiterleitung in `direction`
+    pub fn last_forward(&self, direction: MessageDirection) -> Option<i64> {
+        let timestamp = self.last_forward.with_label_values(&[direction.to_string()]).get();
+        (timestamp > 0).then_some(timestamp)
+    }
+
+    /// Meldet ein Lebenszeichen einer Komponente (siehe `health`)
+    pub fn heartbeat(&self, component: &str) {
+        self.heartbeats.with_label_values(&[component]).set(unix_now());
+    }
+
+    /// Unix-Zeit des letzten Lebenszeichens einer Komponente
+    pub fn last_heartbeat(&self, component: &str) -> Option<i64> {
+        let timestamp = self.heartbeats.with_label_values(&[component]).get();
+        (timestamp > 0).then_some(timestamp)
+    }
+
+    pub fn record_restart(&self, component: &str) {
+        self.restarts.with_label_values(&[component]).inc();
+    }
+
+    pub fn record_send_failure(&self, direction: MessageDirection, cause: &str) {
+        self.send_failures.with_label_values(&[direction.to_string(), cause]).inc();
+    }
+
+    pub fn record_decrypt_failure(&self, kind: Kind) {
+        let kind = match kind {
+            Kind::EncryptedDirectMessage => "nip04",
+            Kind::GiftWrap => "nip17",
+            _ => "other",
+        };
+        self.decrypt_failures.with_label_values(&[kind]).inc();
+    }
+
+    /// Ergebnis einer Profil-Abfrage: "hit", "stale" (abgelaufen, wird erneuert) oder "miss"
+    pub fn record_profile_lookup(&self, result: &str) {
+        self.profile_cache.with_label_values(&[result]).inc();
+    }
+
+    /// Metriken im Prometheus-Textformat
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            warn!("Fehler beim Kodieren der Metriken: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    /// Hintergrund-Task: tastet Relay-Zustände und die Datenbank-Warteschlange ab
+    pub async fn run_sampler(self: Arc<Self>, client: Arc<Client>, db: Arc<Database>) {
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.sample(&client, &db).await;
+        }
+    }
+
+    async fn sample(&self, client: &Client, db: &Database) {
+        let mut counts = [0i64; RELAY_STATUSES.len()];
+        for relay in client.relays().await.values() {
+            let label = relay_status_label(relay.status().await);
+            if let Some(index) = RELAY_STATUSES.iter().position(|status| *status == label) {
+                counts[index] += 1;
+            }
+        }
+        for (status, count) in RELAY_STATUSES.iter().zip(counts) {
+            self.relays.with_label_values(&[status]).set(count);
+        }
+
+        self.db_queue_depth.set(db.queue_depth() as i64);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+
+/// Alle Relay-Zustände, damit auch leere Zustände als 0 erscheinen
+const RELAY_STATUSES: &[&str] = &[
+    "initialized",
+    "pending",
+    "connecting",
+    "connected",
+    "disconnected",
+    "stopped",
+    "terminated",
+];
+
+pub fn relay_status_label(status: RelayStatus) -> &'static str {
+    match status {
+        RelayStatus::Initialized => "initialized",
+        RelayStatus::Pending => "pending",
+        RelayStatus::Connecting => "connecting",
+        RelayStatus::Connected => "connected",
+        RelayStatus::Disconnected => "disconnected",
+        RelayStatus::Stopped => "stopped",
+        RelayStatus::Terminated => "terminated",
+    }
+}
+
+/// Ursache eines fehlgeschlagenen Sendens an Nostr (Label `cause`)
+pub fn nostr_failure_cause(error: &BridgeError) -> &'static str {
+    match error {
+        BridgeError::Nostr(ClientError::RelayPool(PoolError::NoRelays | PoolError::NoRelaysSpecified)) => "no_relays",
+        BridgeError::Nostr(ClientError::RelayPool(
+            PoolError::EventNotPublished | PoolError::MsgNotSent | PoolError::MsgsNotSent,
+        )) => "not_published",
+        BridgeError::Nostr(ClientError::Relay(_) | ClientError::RelayPool(_)) => "relay",
+        BridgeError::Nostr(_) => "nostr",
+        BridgeError::Config(_) | BridgeError::KeyParsing(_) | BridgeError::EventBuild(_) => "build",
+        BridgeError::Database(_) | BridgeError::Archive(_) | BridgeError::TaskEnded(_) | BridgeError::Http(_) => "other",
+    }
+}
+
+/// Ursache eines fehlgeschlagenen Sendens an Telegram (Label `cause`)
+pub fn telegram_failure_cause(error: &RequestError) -> &'static str {
+    match error {
+        RequestError::RetryAfter(_) => "rate_limited",
+        RequestError::Network(_) | RequestError::Io(_) => "network",
+        RequestError::Api(_) | RequestError::MigrateToChatId(_) => "api",
+        RequestError::InvalidJson { .. } => "invalid_response",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_contains_recorded_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_forward(MessageDirection::TelegramToNostr, "nip17", Instant::now());
+        metrics.record_forward(MessageDirection::TelegramToNostr, "nip17", Instant::now());
+        metrics.record_send_failure(MessageDirection::NostrToTelegram, "rate_limited");
+        metrics.record_decrypt_failure(Kind::GiftWrap);
+        metrics.record_profile_lookup("hit");
+
+        let output = metrics.render();
+        assert!(output.contains(r#"bridge_messages_forwarded_total{direction="telegram_to_nostr",mode="nip17"} 2"#));
+        assert!(output.contains(r#"bridge_send_failures_total{cause="rate_limited",direction="nostr_to_telegram"} 1"#));
+        assert!(output.contains(r#"bridge_decrypt_failures_total{kind="nip17"} 1"#));
+        assert!(output.contains(r#"bridge_profile_cache_lookups_total{result="hit"} 1"#));
+        assert!(output.contains(r#"bridge_forward_latency_seconds_count{direction="telegram_to_nostr"} 2"#));
+        assert!(output.contains("bridge_db_queue_depth 0"));
+    }
+
+    #[test]
+    fn test_failure_causes() {
+        assert_eq!(
+            nostr_failure_cause(&BridgeError::Nostr(ClientError::RelayPool(PoolError::NoRelays))),
+            "no_relays"
+        );
+        assert_eq!(
+            nostr_failure_cause(&BridgeError::Nostr(ClientError::RelayPool(PoolError::EventNotPublished))),
+            "not_published"
+        );
+        assert_eq!(nostr_failure_cause(&BridgeError::EventBuild("x".to_string())), "build");
+        assert_eq!(telegram_failure_cause(&RequestError::RetryAfter(Duration::from_secs(3))), "rate_limited");
+        assert_eq!(telegram_failure_cause(&RequestError::MigrateToChatId(-100)), "api");
+    }
+}
@@ -0,0 +1,171 @@
+use futures::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::stop::{mk_stop_token, StopFlag, StopToken};
+use teloxide::types::{AllowedUpdate, Update};
+use teloxide::update_listeners::{StatefulListener, UpdateListener};
+use teloxide::RequestError;
+use tracing::warn;
+
+use crate::health;
+use crate::metrics::Metrics;
+
+/// Wartezeit eines `getUpdates`-Aufrufs (Long Polling)
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause nach einem fehlgeschlagenen `getUpdates`, damit ein Netzausfall nicht
+/// in einer Schleife von Anfragen endet
+const ERROR_DELAY: Duration = Duration::from_secs(1);
+
+struct Polling {
+    bot: Bot,
+    metrics: Arc<Metrics>,
+    offset: i32,
+    buffer: VecDeque<Update>,
+    allowed_updates: Option<Vec<AllowedUpdate>>,
+    stop_token: StopToken,
+    stop_flag: StopFlag,
+    stopped: bool,
+}
+
+/// Long Polling wie teloxides `polling_default`, aber jede erfolgreiche
+/// `getUpdates`-Runde – auch ohne Updates – ist ein Lebenszeichen des
+/// Telegram-Empfangs. So fällt ein hängender Empfang auch in einer ruhigen
+/// Gruppe auf.
+pub async fn listen(bot: Bot, metrics: Arc<Metrics>) -> impl UpdateListener<Err = RequestError> {
+    // Ein noch angemeldeter Webhook (z.B. nach einem Absturz) blockiert getUpdates
+    if let Err(e) = bot.delete_webhook().await {
+        warn!("Webhook konnte nicht abgemeldet werden: {}", e);
+    }
+
+    let (stop_token, stop_flag) = mk_stop_token();
+    let state = Polling {
+        bot,
+        metrics,
+        offset: 0,
+        buffer: VecDeque::new(),
+        allowed_updates: None,
+        stop_token,
+        stop_flag,
+        stopped: false,
+    };
+    StatefulListener::new_with_hints(
+        state,
+        updates,
+        |state: &mut Polling| state.stop_token.clone(),
+        Some(hint_allowed_updates),
+        Some(timeout_hint),
+    )
+}
+
+fn updates(state: &mut Polling) -> impl Stream<Item = Result<Update, RequestError>> + Send + '_ {
+    stream::unfold(state, |state| async move {
+        loop {
+            if let Some(update) = state.buffer.pop_front() {
+                return Some((Ok(update), state));
+            }
+            if state.stopped {
+                return None;
+            }
+
+            // Beim Stoppen nur noch den Offset bestätigen, damit Telegram
+            // verarbeitete Updates nicht erneut ausliefert
+            let stopping = state.stop_flag.is_stopped();
+            let mut request = state.bot.get_updates().offset(state.offset);
+            request = if stopping {
+                request.limit(1).timeout(0)
+            } else {
+                request.timeout(POLL_TIMEOUT.as_secs() as u32)
+            };
+            if let Some(allowed_updates) = state.allowed_updates.take() {
+                request = request.allowed_updates(allowed_updates);
+            }
+
+            match request.await {
+                Ok(updates) => {
+                    state.metrics.heartbeat(health::TELEGRAM);
+                    if stopping {
+                        state.stopped = true;
+                        return None;
+                    }
+                    if let Some(last) = updates.last() {
+                        state.offset = last.id + 1;
+                    }
+                    state.buffer.extend(updates);
+                }
+                Err(e) => {
+                    state.stopped = stopping;
+                    tokio::time::sleep(ERROR_DELAY).await;
+                    return Some((Err(e), state));
+                }
+            }
+        }
+    })
+}
+
+fn hint_allowed_updates(state: &mut Polling, hint: &mut dyn Iterator<Item = AllowedUpdate>) {
+    state.allowed_updates = Some(hint.collect());
+}
+
+fn timeout_hint(_: &Polling) -> Option<Duration> {
+    Some(POLL_TIMEOUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::Uri, Json};
+    use futures::StreamExt;
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use teloxide::update_listeners::AsUpdateStream;
+
+    /// Bot-API-Attrappe: die erste getUpdates-Runde liefert ein Update, danach keine
+    async fn mock_telegram_api() -> Bot {
+        async fn api(State(rounds): State<Arc<AtomicUsize>>, uri: Uri) -> Json<Value> {
+            if !uri.path().to_lowercase().ends_with("getupdates") {
+                return Json(json!({"ok": true, "result": true}));
+            }
+            let result = match rounds.fetch_add(1, Ordering::SeqCst) {
+                0 => json!([{
+                    "update_id": 41,
+                    "message": {
+                        "message_id": 1,
+                        "date": 1700000000,
+                        "chat": {"id": -100123, "type": "supergroup", "title": "Test"},
+                        "text": "Hallo"
+                    }
+                }]),
+                _ => json!([]),
+            };
+            Json(json!({"ok": true, "result": result}))
+        }
+
+        let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let router = axum::Router::new().fallback(api).with_state(Arc::new(AtomicUsize::new(0)));
+        tokio::spawn(axum::Server::from_tcp(socket).unwrap().serve(router.into_make_service()));
+        Bot::new("123:test").set_api_url(format!("http://{}/", addr).parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_polling_rounds_are_heartbeats() {
+        let metrics = Arc::new(Metrics::new());
+        let mut listener = listen(mock_telegram_api().await, metrics.clone()).await;
+        let stop_token = listener.stop_token();
+        let updates = listener.as_stream();
+        tokio::pin!(updates);
+
+        let update = updates.next().await.unwrap().unwrap();
+        assert_eq!(update.id, 41);
+        assert!(metrics.last_heartbeat(health::TELEGRAM).is_some());
+
+        // Nach dem Stoppen bestätigt eine letzte Runde den Offset, dann endet der Strom
+        stop_token.stop();
+        let ended = tokio::time::timeout(Duration::from_secs(5), updates.next()).await.unwrap();
+        assert!(ended.is_none());
+    }
+}
@@ -0,0 +1,490 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info};
+use tokio_postgres::{Client, Config, NoTls};
+
+use crate::database::{DbResult, Migration, MessageDirection, MessageMapping, STATE_HEALTH_CHECK};
+use crate::storage::{event_id_hash, Storage, CLAIM_TIMEOUT_SECS};
+
+/// Schlüssel in `bridge_state` für die Gesamtzahl gelöschter Mappings
+const STATE_PRUNED_ROWS: &str = "pruned_rows";
+
+/// Beliebige, feste Kennung für `pg_advisory_xact_lock`, damit mehrere
+/// gleichzeitig startende Bridge-Instanzen nicht parallel migrieren
+const MIGRATION_LOCK_ID: i64 = 0x6e6f_7374_7467_6272;
+
+/// Migrationen des PostgreSQL-Schemas. Wie bei SQLite gilt: bestehende
+/// Einträge nie ändern, sondern neue anhängen. Die angewendeten Versionen
+/// stehen in `schema_migrations`.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "message_mapping",
+        sql: "CREATE TABLE message_mapping (
+                id BIGSERIAL PRIMARY KEY,
+                telegram_chat_id BIGINT NOT NULL,
+                telegram_message_id BIGINT NOT NULL,
+                nostr_event_id TEXT NOT NULL UNIQUE,
+                nostr_recipient_pubkey TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                timestamp BIGINT NOT NULL,
+                UNIQUE(telegram_chat_id, telegram_message_id)
+            );
+            CREATE INDEX idx_mapping_timestamp ON message_mapping(timestamp);
+            CREATE TABLE pruned_events (
+                hash BIGINT PRIMARY KEY
+            );
+            CREATE TABLE bridge_state (
+                key TEXT PRIMARY KEY,
+                value BIGINT NOT NULL
+            );",
+    },
+    Migration {
+        version: 2,
+        description: "message_mapping_claims",
+        sql: "ALTER TABLE message_mapping
+                ALTER COLUMN telegram_message_id DROP NOT NULL,
+                ALTER COLUMN nostr_event_id DROP NOT NULL,
+                ALTER COLUMN nostr_recipient_pubkey SET DEFAULT '',
+                ADD COLUMN status TEXT NOT NULL DEFAULT 'sent';",
+    },
+    Migration {
+        version: 3,
+        description: "message_parts",
+        sql: "CREATE TABLE message_parts (
+                telegram_chat_id BIGINT NOT NULL,
+                telegram_message_id BIGINT NOT NULL,
+                nostr_event_id TEXT NOT NULL,
+                part INTEGER NOT NULL,
+                PRIMARY KEY (telegram_chat_id, telegram_message_id)
+            );
+            CREATE INDEX idx_parts_event ON message_parts(nostr_event_id);",
+    },
+    Migration {
+        version: 4,
+        description: "message_mapping_thread",
+        sql: "ALTER TABLE message_mapping ADD COLUMN telegram_thread_id BIGINT;",
+    },
+    Migration {
+        version: 5,
+        description: "pruned_events_timestamp",
+        sql: "ALTER TABLE pruned_events ADD COLUMN timestamp BIGINT NOT NULL DEFAULT 0;
+            UPDATE pruned_events SET timestamp = extract(epoch FROM now())::bigint;
+            CREATE INDEX idx_pruned_timestamp ON pruned_events(timestamp);",
+    },
+];
+
+/// Mapping-Speicher in PostgreSQL, geteilt von mehreren Bridge-Instanzen.
+///
+/// Alle Operationen sind einzelne Statements; der `tokio_postgres::Client`
+/// kann deshalb ohne Sperre von mehreren Tasks gleichzeitig genutzt werden.
+/// Bricht die Verbindung ab (Neustart des Servers, Netzausfall), baut der
+/// nächste Zugriff sie neu auf; nur die gerade laufenden Abfragen scheitern.
+pub struct PostgresStorage {
+    /// Verbindungsparameter für den Neuaufbau
+    config: Config,
+    client: Mutex<Arc<Client>>,
+}
+
+impl PostgresStorage {
+    /// Verbindet sich mit PostgreSQL (z.B. `host=/run/postgresql user=bridge`
+    /// oder `postgres://bridge@db/bridge`) und führt ausstehende Migrationen aus
+    pub async fn connect(url: &str) -> DbResult<Self> {
+        Self::with_config(url.parse()?).await
+    }
+
+    async fn with_config(config: Config) -> DbResult<Self> {
+        let mut client = open_connection(&config).await?;
+        run_migrations(&mut client, MIGRATIONS).await?;
+        let client = Mutex::new(Arc::new(client));
+        Ok(Self { config, client })
+    }
+
+    /// Gibt die Verbindung zurück und baut sie neu auf, falls sie beendet wurde.
+    /// Die Sperre sorgt dafür, dass gleichzeitige Zugriffe nur einmal neu verbinden.
+    async fn client(&self) -> DbResult<Arc<Client>> {
+        let mut client = self.client.lock().await;
+        if client.is_closed() {
+            *client = Arc::new(open_connection(&self.config).await?);
+            info!("🗄️ PostgreSQL-Verbindung wiederhergestellt");
+        }
+        Ok(client.clone())
+    }
+}
+
+/// Baut eine Verbindung auf; ihr Verbindungs-Task endet mit der Verbindung
+async fn open_connection(config: &Config) -> DbResult<Client> {
+    let (client, connection) = config.connect(NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("PostgreSQL-Verbindung beendet: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+/// Führt alle ausstehenden Migrationen in einer Transaktion aus. Die
+/// Advisory-Lock serialisiert parallel startende Instanzen.
+async fn run_migrations(client: &mut Client, migrations: &[Migration]) -> DbResult<()> {
+    let tx = client.transaction().await?;
+    tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID]).await?;
+    tx.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    )
+    .await?;
+
+    let current: i64 = tx
+        .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[])
+        .await?
+        .get(0);
+
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        info!("🗄️ PostgreSQL-Migration {}: {}", migration.version, migration.description);
+        tx.batch_execute(migration.sql).await?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, description) VALUES ($1, $2)",
+            &[&migration.version, &migration.description],
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    /// Der Upsert macht den Claim atomar: von mehreren gleichzeitigen Instanzen
+    /// erhält genau eine die Claim-ID; ein verfallener Claim wird dabei unter
+    /// Zeilensperre übernommen
+    async fn claim_telegram_message(&self, chat_id: i64, message_id: i64, timestamp: i64) -> DbResult<Option<i64>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "INSERT INTO message_mapping
+                 (telegram_chat_id, telegram_message_id, direction, timestamp, status)
+                 VALUES ($1, $2, $3, $4, 'pending')
+                 ON CONFLICT (telegram_chat_id, telegram_message_id) DO UPDATE SET timestamp = EXCLUDED.timestamp
+                 WHERE message_mapping.status = 'pending' AND message_mapping.timestamp < $5
+                 RETURNING id",
+                &[
+                    &chat_id,
+                    &message_id,
+                    &MessageDirection::TelegramToNostr.to_string(),
+                    &timestamp,
+                    &(timestamp - CLAIM_TIMEOUT_SECS),
+                ],
+            )
+            .await?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    async fn claim_nostr_event(&self, event_id: &str, chat_id: i64, timestamp: i64) -> DbResult<Option<i64>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "INSERT INTO message_mapping
+                 (telegram_chat_id, nostr_event_id, direction, timestamp, status)
+                 SELECT $1, $2, $3, $4, 'pending'
+                 WHERE NOT EXISTS(SELECT 1 FROM pruned_events WHERE hash = $5)
+                 ON CONFLICT (nostr_event_id) DO UPDATE
+                 SET telegram_chat_id = EXCLUDED.telegram_chat_id, timestamp = EXCLUDED.timestamp
+                 WHERE message_mapping.status = 'pending' AND message_mapping.timestamp < $6
+                 RETURNING id",
+                &[
+                    &chat_id,
+                    &event_id,
+                    &MessageDirection::NostrToTelegram.to_string(),
+                    &timestamp,
+                    &event_id_hash(event_id),
+                    &(timestamp - CLAIM_TIMEOUT_SECS),
+                ],
+            )
+            .await?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    async fn complete_claim(&self, claim_id: i64, mapping: &MessageMapping) -> DbResult<()> {
+        self.client()
+            .await?
+            .execute(
+                "UPDATE message_mapping
+                 SET telegram_chat_id = $2, telegram_message_id = $3, telegram_thread_id = $4,
+                     nostr_event_id = $5, nostr_recipient_pubkey = $6, timestamp = $7, status = 'sent'
+                 WHERE id = $1 AND status = 'pending'",
+                &[
+                    &claim_id,
+                    &mapping.telegram_chat_id,
+                    &mapping.telegram_message_id,
+                    &mapping.telegram_thread_id,
+                    &mapping.nostr_event_id,
+                    &mapping.nostr_recipient_pubkey,
+                    &mapping.timestamp,
+                ],
+            )
+            .await?;
+        debug!("Claim {} abgeschlossen", claim_id);
+        Ok(())
+    }
+
+    async fn release_claim(&self, claim_id: i64) -> DbResult<()> {
+        self.client()
+            .await?
+            .execute("DELETE FROM message_mapping WHERE id = $1 AND status = 'pending'", &[&claim_id])
+            .await?;
+        Ok(())
+    }
+
+    async fn save_message_parts(&self, chat_id: i64, nostr_event_id: &str, message_ids: &[i64]) -> DbResult<()> {
+        // Teil 1 steht im Mapping, die Aufzählung beginnt deshalb bei 2
+        self.client()
+            .await?
+            .execute(
+                "INSERT INTO message_parts (telegram_chat_id, telegram_message_id, nostr_event_id, part)
+                 SELECT $1, message_id, $2, (ordinality + 1)::INTEGER
+                 FROM UNNEST($3::BIGINT[]) WITH ORDINALITY AS parts(message_id, ordinality)",
+                &[&chat_id, &nostr_event_id, &message_ids],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn nostr_event_exists(&self, event_id: &str, now: i64) -> DbResult<bool> {
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "SELECT EXISTS(SELECT 1 FROM message_mapping WHERE nostr_event_id = $1
+                               AND (status = 'sent' OR timestamp >= $3))
+                     OR EXISTS(SELECT 1 FROM pruned_events WHERE hash = $2)",
+                &[&event_id, &event_id_hash(event_id), &(now - CLAIM_TIMEOUT_SECS)],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    /// Löschen, Merken der Hashes und Zählen laufen in einem Statement und
+    /// damit atomar, auch wenn mehrere Instanzen gleichzeitig bereinigen
+    async fn prune_mappings(&self, max_age_secs: Option<i64>, max_rows: Option<i64>, now: i64) -> DbResult<usize> {
+        if max_age_secs.is_none() && max_rows.is_none() {
+            return Ok(0);
+        }
+
+        let cutoff = max_age_secs.map(|age| now - age).unwrap_or(i64::MIN);
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                // LIMIT NULL bedeutet in PostgreSQL "ohne Begrenzung"
+                "WITH doomed AS (
+                    -- Offene Claims werden gerade gesendet und weder gezählt noch gelöscht
+                    DELETE FROM message_mapping
+                    WHERE status = 'sent'
+                      AND (timestamp < $1
+                           OR id NOT IN (SELECT id FROM message_mapping WHERE status = 'sent'
+                                         ORDER BY timestamp DESC, id DESC LIMIT $2))
+                    RETURNING nostr_event_id, timestamp
+                 ),
+                 parts AS (
+                    DELETE FROM message_parts WHERE nostr_event_id IN (SELECT nostr_event_id FROM doomed)
+                 ),
+                 remembered AS (
+                    INSERT INTO pruned_events (hash, timestamp)
+                    -- entspricht storage::event_id_hash: erste 8 Bytes von SHA-256, big-endian
+                    SELECT ('x' || left(encode(sha256(convert_to(nostr_event_id, 'UTF8')), 'hex'), 16))::bit(64)::bigint,
+                           MAX(timestamp)
+                    FROM doomed WHERE nostr_event_id IS NOT NULL
+                    GROUP BY 1
+                    ON CONFLICT (hash) DO UPDATE SET timestamp = GREATEST(pruned_events.timestamp, EXCLUDED.timestamp)
+                 ),
+                 counted AS (
+                    INSERT INTO bridge_state (key, value) SELECT $3, COUNT(*) FROM doomed
+                    ON CONFLICT (key) DO UPDATE SET value = bridge_state.value + excluded.value
+                 )
+                 SELECT COUNT(*) FROM doomed",
+                &[&cutoff, &max_rows, &STATE_PRUNED_ROWS],
+            )
+            .await?;
+
+        let pruned = row.get::<_, i64>(0) as usize;
+        if pruned > 0 {
+            debug!("{} Mappings gelöscht (Retention)", pruned);
+        }
+        Ok(pruned)
+    }
+
+    async fn forget_pruned_events(&self, before: i64) -> DbResult<usize> {
+        let forgotten = self
+            .client()
+            .await?
+            .execute("DELETE FROM pruned_events WHERE timestamp < $1", &[&before])
+            .await?;
+        Ok(forgotten as usize)
+    }
+
+    async fn get_pruned_count(&self) -> DbResult<i64> {
+        let row = self
+            .client()
+            .await?
+            .query_opt("SELECT value FROM bridge_state WHERE key = $1", &[&STATE_PRUNED_ROWS])
+            .await?;
+        Ok(row.map(|row| row.get(0)).unwrap_or(0))
+    }
+
+    async fn get_stats(&self) -> DbResult<(i64, i64, i64)> {
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "SELECT COUNT(*),
+                        COUNT(*) FILTER (WHERE direction = $1),
+                        COUNT(*) FILTER (WHERE direction = $2)
+                 FROM message_mapping WHERE status = 'sent'",
+                &[
+                    &MessageDirection::TelegramToNostr.to_string(),
+                    &MessageDirection::NostrToTelegram.to_string(),
+                ],
+            )
+            .await?;
+        Ok((row.get(0), row.get(1), row.get(2)))
+    }
+
+    async fn check_writable(&self, now: i64) -> DbResult<()> {
+        self.client()
+            .await?
+            .execute(
+                "INSERT INTO bridge_state (key, value) VALUES ($1, $2)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                &[&STATE_HEALTH_CHECK, &now],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::conformance::storage_conformance_tests;
+
+    /// Server der PostgreSQL-Tests (z.B. `host=/tmp port=5432 user=postgres`).
+    /// Die Tests sind `#[ignore]` und laufen mit `--include-ignored`; ohne die
+    /// Variable schlagen sie dann fehl, statt still zu bestehen.
+    fn test_postgres_url() -> String {
+        std::env::var("TEST_POSTGRES_URL").expect("TEST_POSTGRES_URL ist nicht gesetzt")
+    }
+
+    /// Verbindungsparameter des Testservers mit eigenem `search_path`
+    fn test_config(search_path: &str) -> Config {
+        let mut config: Config = test_postgres_url().parse().unwrap();
+        config.options(format!("-c search_path={}", search_path));
+        config
+    }
+
+    /// Verbindung für Tests, die den Storage umgehen
+    async fn test_client(search_path: &str) -> Client {
+        let (client, connection) = test_config(search_path)
+            .connect(NoTls)
+            .await
+            .expect("Verbindung zu TEST_POSTGRES_URL fehlgeschlagen");
+        tokio::spawn(connection);
+        client
+    }
+
+    /// Jeder Test arbeitet mit temporären Tabellen (`pg_temp`), die mit der
+    /// Verbindung verschwinden
+    async fn create_test_storage() -> PostgresStorage {
+        PostgresStorage::with_config(test_config("pg_temp")).await.unwrap()
+    }
+
+    storage_conformance_tests!(async { Box::new(create_test_storage().await) }, ignore = "needs TEST_POSTGRES_URL");
+
+    /// Zwei Instanzen mit eigener Verbindung auf gemeinsamen Tabellen: jede
+    /// doppelt zugestellte Nachricht wird von genau einer Instanz beansprucht
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore = "needs TEST_POSTGRES_URL"]
+    async fn test_claims_across_instances() {
+        let schema = format!("bridge_test_{:08x}", nostr_sdk::prelude::rand::random::<u32>());
+        let admin = test_client("public").await;
+        admin.batch_execute(&format!("CREATE SCHEMA {}", schema)).await.unwrap();
+
+        let mut instances = Vec::new();
+        for _ in 0..2 {
+            instances.push(Arc::new(PostgresStorage::with_config(test_config(&schema)).await.unwrap()));
+        }
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for message_id in 0..50 {
+            for instance in &instances {
+                let instance = instance.clone();
+                tasks.spawn(async move {
+                    instance.claim_telegram_message(-100, message_id, 0).await.unwrap().is_some() as usize
+                });
+            }
+        }
+        let mut claimed = 0;
+        while let Some(result) = tasks.join_next().await {
+            claimed += result.unwrap();
+        }
+
+        admin.batch_execute(&format!("DROP SCHEMA {} CASCADE", schema)).await.unwrap();
+        assert_eq!(claimed, 50);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_POSTGRES_URL"]
+    async fn test_migrations_are_idempotent() {
+        let mut client = test_client("pg_temp").await;
+        run_migrations(&mut client, MIGRATIONS).await.unwrap();
+        run_migrations(&mut client, MIGRATIONS).await.unwrap();
+
+        let applied: i64 = client
+            .query_one("SELECT COUNT(*) FROM schema_migrations", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+    }
+
+    /// Nach einem Abbruch der Verbindung (hier durch den Server beendet)
+    /// verbindet sich der nächste Zugriff neu
+    #[tokio::test]
+    #[ignore = "needs TEST_POSTGRES_URL"]
+    async fn test_reconnects_after_connection_loss() {
+        let suffix = format!("{:08x}", nostr_sdk::prelude::rand::random::<u32>());
+        let schema = format!("bridge_test_{}", suffix);
+        let application = format!("bridge-reconnect-{}", suffix);
+
+        let admin = test_client("public").await;
+        admin.batch_execute(&format!("CREATE SCHEMA {}", schema)).await.unwrap();
+
+        let mut config = test_config(&schema);
+        config.application_name(&application);
+        let storage = PostgresStorage::with_config(config).await.unwrap();
+        storage.check_writable(1).await.unwrap();
+
+        admin
+            .execute("SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE application_name = $1", &[&application])
+            .await
+            .unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !storage.client.lock().await.is_closed() {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        storage.check_writable(2).await.unwrap();
+        assert_eq!(storage.get_stats().await.unwrap(), (0, 0, 0));
+
+        admin.batch_execute(&format!("DROP SCHEMA {} CASCADE", schema)).await.unwrap();
+    }
+}
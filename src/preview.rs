@@ -0,0 +1,270 @@
+use futures::future::join_all;
+use nostr_sdk::prelude::*;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::formatting::{self, Node};
+use crate::mentions;
+use crate::profile::ProfileCache;
+
+/// Timeout für Verbindungsaufbau und Abruf eines referenzierten Events
+const PREVIEW_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Höchstzahl der Relay-Hinweise, die je Referenz abgefragt werden
+const MAX_RELAY_HINTS: usize = 3;
+
+/// Höchstzahl der Vorschauen je Nachricht
+const MAX_PREVIEWS: usize = 3;
+
+/// Frist für alle Vorschauen einer Nachricht zusammen. Die Abrufe laufen im
+/// Nostr-Listener; bis dahin Fehlendes erscheint ohne Vorschau.
+const PREVIEWS_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Länge der Zusammenfassung, wenn das Event keine eigene hat
+const SUMMARY_CHARS: usize = 280;
+
+/// Baut Vorschauen für `nostr:nevent1…`, `nostr:note1…` und `nostr:naddr1…` in
+/// einer Nachricht (Markdown-Blöcke, durch Leerzeilen getrennt). Nicht
+/// gefundene Events haben keine Vorschau; der Link im Text bleibt. Die
+/// Referenzen werden gleichzeitig abgerufen, insgesamt höchstens
+/// `PREVIEWS_DEADLINE` lang.
+pub async fn render_previews(client: &Client, content: &str, profiles: &ProfileCache, web_client_url: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let references: Vec<_> = mentions::find_nostr_references(content)
+        .into_iter()
+        .filter(|reference| matches!(reference.entity, Nip19::EventId(_) | Nip19::Event(_) | Nip19::Coordinate(_)))
+        .filter(|reference| seen.insert(reference.bech32.clone()))
+        .take(MAX_PREVIEWS)
+        .collect();
+
+    let deadline = Instant::now() + PREVIEWS_DEADLINE;
+    let previews = references.iter().map(|reference| async move {
+        let fetched = tokio::time::timeout_at(deadline, fetch_referenced_event(client, &reference.entity)).await;
+        let Ok(Some(event)) = fetched else {
+            debug!("Referenziertes Event nicht gefunden: {}", reference.bech32);
+            return None;
+        };
+        let author = match tokio::time::timeout_at(deadline, profiles.name(&event.pubkey)).await {
+            Ok(Some(name)) => name,
+            _ => event.pubkey.to_bech32().unwrap_or_default(),
+        };
+        let link = mentions::web_client_link(web_client_url, &reference.bech32);
+        Some(render_preview(&event, &author, &link))
+    });
+
+    join_all(previews).await.into_iter().flatten().collect()
+}
+
+/// Ruft ein referenziertes Event ab: zuerst von den Relay-Hinweisen der
+/// Referenz (über eine eigene Verbindung, damit die Bridge dort nichts
+/// veröffentlicht), dann von den Relays der Bridge. Die Hinweise stammen aus
+/// fremden DMs; nur öffentliche `wss://`-Relays werden kontaktiert.
+async fn fetch_referenced_event(client: &Client, entity: &Nip19) -> Option<Event> {
+    let (filter, mut hints) = match entity {
+        Nip19::EventId(id) => (Filter::new().id(*id), Vec::new()),
+        Nip19::Event(event) => (Filter::new().id(event.event_id), event.relays.clone()),
+        Nip19::Coordinate(coordinate) => (Filter::from(coordinate), coordinate.relays.clone()),
+        _ => return None,
+    };
+
+    hints.retain(|hint| {
+        let allowed = is_public_relay_hint(hint);
+        if !allowed {
+            debug!("Relay-Hinweis verworfen: {}", hint);
+        }
+        allowed
+    });
+    if !hints.is_empty() {
+        let hint_client = ClientBuilder::new()
+            .opts(Options::new().connection_timeout(Some(PREVIEW_TIMEOUT)))
+            .build();
+        for hint in hints.iter().take(MAX_RELAY_HINTS) {
+            if let Err(e) = hint_client.add_relay(hint.as_str()).await {
+                debug!("Ungültiger Relay-Hinweis {}: {}", hint, e);
+            }
+        }
+        hint_client.connect().await;
+        let events = hint_client.get_events_of(vec![filter.clone()], Some(PREVIEW_TIMEOUT)).await;
+        if let Err(e) = hint_client.shutdown().await {
+            debug!("Fehler beim Trennen der Hinweis-Relays: {}", e);
+        }
+        if let Some(event) = newest_match(entity, events.unwrap_or_default()) {
+            return Some(event);
+        }
+    }
+
+    match client.get_events_of(vec![filter], Some(PREVIEW_TIMEOUT)).await {
+        Ok(events) => newest_match(entity, events),
+        Err(e) => {
+            debug!("Konnte referenziertes Event nicht abrufen: {}", e);
+            None
+        }
+    }
+}
+
+/// Nur `wss://`-URLs mit öffentlichem Host; Loopback, private und
+/// Link-Local-Adressen sowie `localhost` werden abgelehnt
+fn is_public_relay_hint(hint: &str) -> bool {
+    let Ok(url) = Url::parse(hint) else { return false };
+    if url.scheme() != "wss" {
+        return false;
+    }
+    match url.host() {
+        Some(url::Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost") && !domain.ends_with(".local")
+        }
+        Some(url::Host::Ipv4(ip)) => is_public_ip(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => is_public_ip(IpAddr::V6(ip)),
+        None => false,
+    }
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !is_internal_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => !is_internal_ipv4(ip),
+            None => !is_internal_ipv6(ip),
+        },
+    }
+}
+
+fn is_internal_ipv4(ip: Ipv4Addr) -> bool {
+    // 100.64.0.0/10: Carrier-Grade-NAT
+    let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+    ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast() || shared
+}
+
+fn is_internal_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    // fc00::/7 (Unique Local) und fe80::/10 (Link-Local)
+    ip.is_loopback() || ip.is_unspecified() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+}
+
+/// Neuestes gültiges Event, das wirklich zur Referenz passt (Relays können
+/// beliebige Events liefern)
+fn newest_match(entity: &Nip19, events: Vec<Event>) -> Option<Event> {
+    events
+        .into_iter()
+        .filter(|event| event.verify().is_ok())
+        .filter(|event| match entity {
+            Nip19::EventId(id) => event.id == *id,
+            Nip19::Event(reference) => event.id == reference.event_id,
+            Nip19::Coordinate(coordinate) => {
+                event.kind == coordinate.kind
+                    && event.pubkey == coordinate.public_key
+                    && (coordinate.identifier.is_empty() || event.identifier() == Some(coordinate.identifier.as_str()))
+            }
+            _ => false,
+        })
+        .max_by_key(|event| event.created_at)
+}
+
+/// Vorschau als Markdown: Symbol, Titel (fett) und Autor, Zusammenfassung, Link
+fn render_preview(event: &Event, author: &str, link: &str) -> String {
+    let title = tag_value(event, "title");
+    let (symbol, fallback_title) = match event.kind {
+        Kind::TextNote => ("📝", None),
+        Kind::LongFormTextNote => ("📰", None),
+        kind => ("📎", Some(format!("Event (Kind {})", kind.as_u64()))),
+    };
+
+    // Nur bei Notizen und Artikeln ist der Inhalt lesbarer Text
+    let summary = tag_value(event, "summary").or_else(|| {
+        matches!(event.kind, Kind::TextNote | Kind::LongFormTextNote).then(|| {
+            let mut summary: String = event.content.chars().take(SUMMARY_CHARS).collect();
+            if event.content.chars().count() > SUMMARY_CHARS {
+                summary.push('…');
+            }
+            summary
+        })
+    });
+
+    let mut nodes = vec![Node::Text(format!("{} ", symbol))];
+    match title.or(fallback_title) {
+        Some(title) => {
+            nodes.push(Node::Bold(vec![Node::Text(title)]));
+            nodes.push(Node::Text(format!(" – {}", author)));
+        }
+        None => nodes.push(Node::Text(author.to_string())),
+    }
+    if let Some(summary) = summary.filter(|summary| !summary.trim().is_empty()) {
+        nodes.push(Node::Text(format!("\n{}", summary.trim())));
+    }
+    nodes.push(Node::Text(format!("\n🔗 {}", link)));
+    formatting::to_markdown(&nodes)
+}
+
+fn tag_value(event: &Event, name: &str) -> Option<String> {
+    event.tags.iter().find_map(|tag| {
+        let values = tag.as_vec();
+        (values.first().map(String::as_str) == Some(name))
+            .then(|| values.get(1).cloned())
+            .flatten()
+            .filter(|value| !value.is_empty())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_preview() {
+        let keys = Keys::generate();
+        let article = EventBuilder::new(Kind::LongFormTextNote, "# Lang\n\nSehr langer Text", [
+            Tag::Generic(TagKind::Custom("title".to_string()), vec!["Über *Nostr*".to_string()]),
+            Tag::Generic(TagKind::Custom("summary".to_string()), vec!["Kurz gesagt".to_string()]),
+            Tag::Identifier("artikel".to_string()),
+        ])
+        .to_event(&keys)
+        .unwrap();
+        assert_eq!(
+            render_preview(&article, "Alice", "https://njump.me/naddr1x"),
+            "📰 **Über \\*Nostr\\*** – Alice\nKurz gesagt\n🔗 https://njump.me/naddr1x"
+        );
+
+        let note = EventBuilder::text_note("a".repeat(SUMMARY_CHARS + 1), []).to_event(&keys).unwrap();
+        let preview = render_preview(&note, "Bob", "https://njump.me/nevent1x");
+        assert!(preview.starts_with("📝 Bob\naaa"));
+        assert!(preview.contains("a…\n🔗"));
+
+        // Referenzen müssen zum Event passen
+        let coordinate = Coordinate::new(Kind::LongFormTextNote, keys.public_key()).identifier("artikel");
+        assert!(newest_match(&Nip19::Coordinate(coordinate.clone()), vec![article.clone()]).is_some());
+        assert!(newest_match(&Nip19::Coordinate(coordinate.identifier("anders")), vec![article]).is_none());
+        assert!(newest_match(&Nip19::EventId(EventId::all_zeros()), vec![note]).is_none());
+    }
+
+    #[test]
+    fn test_relay_hints_must_be_public_wss() {
+        for hint in ["wss://relay.damus.io", "wss://nos.lol/", "wss://1.1.1.1:7777", "wss://[2606:4700::1111]"] {
+            assert!(is_public_relay_hint(hint), "{}", hint);
+        }
+        for hint in [
+            "ws://relay.damus.io",
+            "https://relay.damus.io",
+            "wss://localhost:8080",
+            "wss://relay.localhost",
+            "wss://printer.local",
+            "wss://127.0.0.1",
+            "wss://10.0.0.5",
+            "wss://192.168.1.1",
+            "wss://172.16.0.1",
+            "wss://169.254.169.254",
+            "wss://100.64.0.1",
+            "wss://0.0.0.0",
+            "wss://[::1]",
+            "wss://[fd00::1]",
+            "wss://[fe80::1]",
+            "wss://[::ffff:127.0.0.1]",
+            "kein relay",
+        ] {
+            assert!(!is_public_relay_hint(hint), "{}", hint);
+        }
+    }
+}
@@ -0,0 +1,504 @@
+use nostr_sdk::prelude::*;
+use tracing::{info, warn, debug};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::BridgeProfileConfig;
+use crate::database::{CachedProfile, Database};
+use crate::metrics::Metrics;
+use crate::supervisor::Shutdown;
+use crate::{unix_now, BridgeError, Result};
+
+/// Subscription-ID der Dauer-Subscription auf Kind 0 bekannter Pubkeys
+const PROFILE_SUBSCRIPTION_ID: &str = "bridge-profile-cache";
+
+/// Komponentenname des Hintergrund-Tasks für Supervisor und Metriken
+pub const PROFILE_CACHE: &str = "profile_cache";
+
+/// Timeout für den direkten Abruf eines unbekannten Profils
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Baut die Kind-0-Metadata für den Bridge-Key aus der Konfiguration
+pub fn build_bridge_metadata(profile: &BridgeProfileConfig) -> Metadata {
+    let mut metadata = Metadata::new()
+        .name(&profile.name)
+        .display_name(&profile.name);
+
+    if let Some(ref about) = profile.about {
+        metadata = metadata.about(about);
+    }
+
+    if let Some(ref picture) = profile.picture {
+        match Url::parse(picture) {
+            Ok(url) => metadata = metadata.picture(url),
+            Err(e) => warn!("Ungültige Avatar-URL '{}' ignoriert: {}", picture, e),
+        }
+    }
+
+    if let Some(ref nip05) = profile.nip05 {
+        metadata = metadata.nip05(nip05);
+    }
+
+    if profile.bot {
+        metadata = metadata.custom_field("bot", true);
+    }
+
+    metadata
+}
+
+/// Veröffentlicht das Profil des Bridge-Keys auf allen Relays
+pub async fn publish_bridge_profile(
+    client: &Client,
+    profile: &BridgeProfileConfig,
+) -> std::result::Result<EventId, nostr_sdk::client::Error> {
+    let metadata = build_bridge_metadata(profile);
+    let event_id = client.set_metadata(&metadata).await?;
+    info!("👤 Bridge-Profil veröffentlicht: {} (Event-ID: {})", profile.name, event_id);
+    Ok(event_id)
+}
+
+/// Veröffentlicht das Bridge-Profil sofort und danach periodisch erneut,
+/// damit Relays mit kurzer Aufbewahrung es nicht verlieren
+pub async fn run_profile_refresh(client: Arc<Client>, profile: BridgeProfileConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(profile.refresh_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = publish_bridge_profile(&client, &profile).await {
+            warn!("Fehler beim Veröffentlichen des Bridge-Profils: {}", e);
+        }
+    }
+}
+
+/// Cache für Nostr-Profile (Kind 0) in SQLite mit TTL.
+///
+/// Unbekannte Profile werden einmalig direkt abgerufen, abgelaufene im
+/// Hintergrund erneuert. Zusätzlich hält der Cache eine Dauer-Subscription
+/// auf Kind 0 aller bekannten Pubkeys, sodass Profiländerungen ohne erneuten
+/// Abruf ankommen.
+#[derive(Clone)]
+pub struct ProfileCache {
+    client: Arc<Client>,
+    db: Arc<Database>,
+    ttl_secs: i64,
+    watched: Arc<Mutex<HashSet<PublicKey>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl ProfileCache {
+    pub fn new(client: Arc<Client>, db: Arc<Database>, ttl_secs: u64, metrics: Arc<Metrics>) -> Self {
+        ProfileCache {
+            client,
+            db,
+            ttl_secs: ttl_secs as i64,
+            watched: Arc::new(Mutex::new(HashSet::new())),
+            metrics,
+        }
+    }
+
+    /// Gibt den Anzeigenamen eines Pubkeys zurück, z.B. "Alice ✓ (npub1...)"
+    pub async fn display_name(&self, pubkey: &PublicKey) -> String {
+        let profile = self.get(pubkey).await;
+        format_display_name(profile.as_ref(), pubkey)
+    }
+
+    /// Gibt nur den Namen aus dem Profil zurück (ohne npub), falls vorhanden
+    pub async fn name(&self, pubkey: &PublicKey) -> Option<String> {
+        let profile = self.get(pubkey).await?;
+        profile.display_name.or(profile.name)
+    }
+
+    /// Liefert ein Profil aus dem Cache bzw. ruft unbekannte Profile ab
+    async fn get(&self, pubkey: &PublicKey) -> Option<CachedProfile> {
+        let profile = match self.lookup(pubkey).await {
+            Some(profile) => {
+                if unix_now() - profile.fetched_at > self.ttl_secs {
+                    self.metrics.record_profile_lookup("stale");
+                    // Abgelaufen: sofort den alten Namen verwenden, im Hintergrund erneuern
+                    let cache = self.clone();
+                    let pubkey = *pubkey;
+                    tokio::spawn(async move {
+                        cache.refresh(&pubkey).await;
+                    });
+                } else {
+                    self.metrics.record_profile_lookup("hit");
+                }
+                Some(profile)
+            }
+            None => {
+                self.metrics.record_profile_lookup("miss");
+                self.refresh(pubkey).await
+            }
+        };
+
+        self.watch(*pubkey).await;
+        profile
+    }
+
+    /// Liest ein Profil aus dem Cache (ohne Netzwerkzugriff)
+    pub async fn lookup(&self, pubkey: &PublicKey) -> Option<CachedProfile> {
+        self.db
+            .get_cached_profile(&pubkey.to_hex()).await
+            .unwrap_or_else(|e| {
+                warn!("Fehler beim Lesen des Profil-Caches: {}", e);
+                None
+            })
+    }
+
+    /// Ruft das Profil von den Relays ab und aktualisiert den Cache
+    pub async fn refresh(&self, pubkey: &PublicKey) -> Option<CachedProfile> {
+        let filter = Filter::new()
+            .kind(Kind::Metadata)
+            .author(*pubkey);
+
+        match self.client.get_events_of(vec![filter], Some(FETCH_TIMEOUT)).await {
+            Ok(events) => {
+                match newest_event(events) {
+                    Some(event) => self.store_metadata_event(&event, true).await,
+                    None => self.store_missing_profile(pubkey).await,
+                }
+            }
+            Err(e) => debug!("Konnte Metadata nicht abrufen: {}", e),
+        }
+
+        self.lookup(pubkey).await
+    }
+
+    /// Speichert ein Kind-0-Event im Cache und verifiziert bei Bedarf NIP-05
+    pub async fn store_metadata_event(&self, event: &Event, reverify: bool) {
+        let metadata = match Metadata::from_json(&event.content) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                debug!("Ungültige Metadata von {}: {}", event.pubkey, e);
+                return;
+            }
+        };
+
+        let pubkey_hex = event.pubkey.to_hex();
+        let existing = self.lookup(&event.pubkey).await;
+
+        // Verifizierung bleibt gültig solange sich der NIP-05-Eintrag nicht ändert
+        let nip05_verified = existing
+            .as_ref()
+            .map(|p| p.nip05_verified && p.nip05 == metadata.nip05)
+            .unwrap_or(false);
+
+        let profile = CachedProfile {
+            pubkey: pubkey_hex.clone(),
+            name: metadata.name.filter(|s| !s.is_empty()),
+            display_name: metadata.display_name.filter(|s| !s.is_empty()),
+            nip05: metadata.nip05.filter(|s| !s.is_empty()),
+            nip05_verified,
+            event_created_at: event.created_at.as_u64() as i64,
+            fetched_at: unix_now(),
+        };
+
+        match self.db.upsert_profile(&profile).await {
+            Ok(true) => {
+                debug!("Profil-Cache aktualisiert: {}", pubkey_hex);
+                if let Some(nip05) = profile.nip05 {
+                    if reverify || !nip05_verified {
+                        self.verify_nip05(event.pubkey, nip05).await;
+                    }
+                }
+            }
+            Ok(false) => {
+                if let Err(e) = self.db.touch_profile(&pubkey_hex, unix_now()).await {
+                    warn!("Fehler beim Aktualisieren des Profil-Caches: {}", e);
+                }
+            }
+            Err(e) => warn!("Fehler beim Speichern im Profil-Cache: {}", e),
+        }
+    }
+
+    /// Merkt sich, dass für einen Pubkey kein Profil existiert (negativer Cache)
+    async fn store_missing_profile(&self, pubkey: &PublicKey) {
+        let pubkey_hex = pubkey.to_hex();
+        let result = match self.lookup(pubkey).await {
+            Some(_) => self.db.touch_profile(&pubkey_hex, unix_now()).await,
+            None => self.db
+                .upsert_profile(&CachedProfile {
+                    pubkey: pubkey_hex,
+                    name: None,
+                    display_name: None,
+                    nip05: None,
+                    nip05_verified: false,
+                    event_created_at: 0,
+                    fetched_at: unix_now(),
+                }).await
+                .map(|_| ()),
+        };
+
+        if let Err(e) = result {
+            warn!("Fehler beim Speichern im Profil-Cache: {}", e);
+        }
+    }
+
+    async fn verify_nip05(&self, pubkey: PublicKey, nip05: String) {
+        let verified = match nip05::verify(pubkey, &nip05, None).await {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("NIP-05-Verifizierung für {} fehlgeschlagen: {}", nip05, e);
+                false
+            }
+        };
+
+        if let Err(e) = self.db.set_nip05_verified(&pubkey.to_hex(), &nip05, verified).await {
+            warn!("Fehler beim Speichern des NIP-05-Status: {}", e);
+        }
+    }
+
+    /// Nimmt einen Pubkey in die Dauer-Subscription auf
+    pub async fn watch(&self, pubkey: PublicKey) {
+        let inserted = self.watched.lock().unwrap().insert(pubkey);
+        if inserted {
+            self.resubscribe().await;
+        }
+    }
+
+    async fn resubscribe(&self) {
+        let authors: Vec<PublicKey> = self.watched.lock().unwrap().iter().copied().collect();
+        if authors.is_empty() {
+            return;
+        }
+
+        let filter = Filter::new()
+            .kind(Kind::Metadata)
+            .authors(authors)
+            .since(Timestamp::now());
+
+        self.client
+            .subscribe_with_id(SubscriptionId::new(PROFILE_SUBSCRIPTION_ID), vec![filter], None)
+            .await;
+    }
+
+    /// Hintergrund-Task: hält den Cache über die Kind-0-Subscription aktuell.
+    /// Endet beim Shutdown oder mit Fehler, wenn der Notification-Stream
+    /// geschlossen wird (der Supervisor startet ihn dann neu).
+    pub async fn run(self, shutdown: Shutdown) -> Result<()> {
+        let known = self.db.cached_profile_pubkeys().await.unwrap_or_else(|e| {
+            warn!("Fehler beim Lesen des Profil-Caches: {}", e);
+            Vec::new()
+        });
+        {
+            let mut watched = self.watched.lock().unwrap();
+            watched.extend(known.iter().filter_map(|hex| PublicKey::from_hex(hex).ok()));
+        }
+        self.resubscribe().await;
+
+        let subscription_id = SubscriptionId::new(PROFILE_SUBSCRIPTION_ID);
+        let mut notifications = self.client.notifications();
+
+        loop {
+            let notification = tokio::select! {
+                result = notifications.recv() => match result {
+                    Ok(notification) => notification,
+                    // Verpasste Profiländerungen holt die Erneuerung nach Ablauf der TTL nach
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Profil-Cache: {} Notifications verpasst", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => {
+                        return Err(BridgeError::TaskEnded("Notification-Stream geschlossen".to_string()));
+                    }
+                },
+                _ = shutdown.triggered() => return Ok(()),
+            };
+
+            if let RelayPoolNotification::Event { subscription_id: sub_id, event, .. } = notification {
+                if sub_id == subscription_id && event.kind == Kind::Metadata {
+                    self.store_metadata_event(&event, false).await;
+                }
+            }
+        }
+    }
+}
+
+/// Relays können veraltete Versionen liefern: das neueste Event gewinnt,
+/// unabhängig von der Reihenfolge der Antworten
+fn newest_event(events: Vec<Event>) -> Option<Event> {
+    events.into_iter().max_by_key(|event| event.created_at)
+}
+
+/// Formatiert den Anzeigenamen; ✓ markiert eine verifizierte NIP-05-Adresse
+fn format_display_name(profile: Option<&CachedProfile>, pubkey: &PublicKey) -> String {
+    let npub = pubkey.to_bech32().unwrap_or_else(|_| "unknown".to_string());
+
+    let name = profile.and_then(|p| p.display_name.as_ref().or(p.name.as_ref()));
+    match (name, profile) {
+        (Some(name), Some(profile)) if profile.nip05_verified => format!("{} ✓ ({})", name, npub),
+        (Some(name), _) => format!("{} ({})", name, npub),
+        // Fallback: Nur npub
+        (None, _) => npub,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::database::{MemoryDatabase, MemoryDatabaseOptions};
+
+    fn metadata_event(keys: &Keys, metadata: &Metadata, created_at: u64) -> Event {
+        EventBuilder::metadata(metadata)
+            .custom_created_at(Timestamp::from(created_at))
+            .to_event(keys)
+            .unwrap()
+    }
+
+    fn profile(pubkey: &PublicKey, display_name: Option<&str>, name: Option<&str>, verified: bool) -> CachedProfile {
+        CachedProfile {
+            pubkey: pubkey.to_hex(),
+            name: name.map(str::to_string),
+            display_name: display_name.map(str::to_string),
+            nip05: verified.then(|| "alice@example.org".to_string()),
+            nip05_verified: verified,
+            event_created_at: 1000,
+            fetched_at: unix_now(),
+        }
+    }
+
+    fn bridge_profile() -> BridgeProfileConfig {
+        BridgeProfileConfig {
+            name: "Bridge".to_string(),
+            about: Some("Telegram ↔ Nostr".to_string()),
+            picture: Some("https://example.org/avatar.png".to_string()),
+            nip05: Some("bridge@example.org".to_string()),
+            bot: true,
+            refresh_interval_secs: 3600,
+        }
+    }
+
+    #[test]
+    fn test_build_bridge_metadata() {
+        let metadata = build_bridge_metadata(&bridge_profile());
+        assert_eq!(metadata.name.as_deref(), Some("Bridge"));
+        assert_eq!(metadata.display_name.as_deref(), Some("Bridge"));
+        assert_eq!(metadata.about.as_deref(), Some("Telegram ↔ Nostr"));
+        assert_eq!(metadata.picture.as_deref(), Some("https://example.org/avatar.png"));
+        assert_eq!(metadata.nip05.as_deref(), Some("bridge@example.org"));
+        assert_eq!(metadata.custom.get("bot"), Some(&serde_json::Value::Bool(true)));
+
+        // Ungültige Avatar-URL wird ignoriert, ohne Bot-Markierung kein `bot`-Feld
+        let minimal = BridgeProfileConfig {
+            about: None,
+            picture: Some("kein url".to_string()),
+            nip05: None,
+            bot: false,
+            ..bridge_profile()
+        };
+        let metadata = build_bridge_metadata(&minimal);
+        assert_eq!(metadata.name.as_deref(), Some("Bridge"));
+        assert_eq!(metadata.about, None);
+        assert_eq!(metadata.picture, None);
+        assert_eq!(metadata.nip05, None);
+        assert!(metadata.custom.is_empty());
+    }
+
+    /// Ein nicht verbundener Relay lässt das Senden scheitern; das Event landet
+    /// trotzdem in der Datenbank des Clients und die Schleife läuft weiter
+    #[tokio::test]
+    async fn test_run_profile_refresh_publishes_immediately() {
+        let keys = Keys::generate();
+        let client = ClientBuilder::new()
+            .signer(keys.clone())
+            .database(MemoryDatabase::with_opts(MemoryDatabaseOptions { events: true, ..Default::default() }))
+            .build();
+        client.add_relay("ws://127.0.0.1:1").await.unwrap();
+
+        let refresh = tokio::spawn(run_profile_refresh(Arc::new(client.clone()), bridge_profile()));
+        let filter = Filter::new().kind(Kind::Metadata).author(keys.public_key());
+        let event = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(event) = client.database().query(vec![filter.clone()], Order::Desc).await.unwrap().pop() {
+                    return event;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Bridge-Profil wurde nicht veröffentlicht");
+
+        assert_eq!(Metadata::from_json(&event.content).unwrap(), build_bridge_metadata(&bridge_profile()));
+        assert!(!refresh.is_finished());
+        refresh.abort();
+    }
+
+    #[test]
+    fn test_format_display_name() {
+        let pubkey = Keys::generate().public_key();
+        let npub = pubkey.to_bech32().unwrap();
+
+        let cases = [
+            (Some(profile(&pubkey, Some("Alice"), Some("alice"), true)), format!("Alice ✓ ({npub})")),
+            (Some(profile(&pubkey, Some("Alice"), Some("alice"), false)), format!("Alice ({npub})")),
+            (Some(profile(&pubkey, None, Some("alice"), false)), format!("alice ({npub})")),
+            // Ohne Namen hilft auch eine verifizierte NIP-05-Adresse nicht
+            (Some(profile(&pubkey, None, None, true)), npub.clone()),
+            (None, npub.clone()),
+        ];
+
+        for (profile, expected) in cases {
+            assert_eq!(format_display_name(profile.as_ref(), &pubkey), expected);
+        }
+    }
+
+    #[test]
+    fn test_newest_event_wins() {
+        let keys = Keys::generate();
+        let events = [200, 300, 100]
+            .map(|created_at| metadata_event(&keys, &Metadata::new().name(created_at.to_string()), created_at));
+
+        let newest = newest_event(events.to_vec()).unwrap();
+        assert_eq!(newest.created_at, Timestamp::from(300));
+        assert!(newest_event(Vec::new()).is_none());
+    }
+
+    /// Ohne Relays beantwortet der Client Abrufe aus seiner Event-Datenbank,
+    /// die hier die Antworten der Relays vorgibt
+    #[tokio::test]
+    async fn test_get_hit_stale_and_miss() {
+        let client = ClientBuilder::new()
+            .database(MemoryDatabase::with_opts(MemoryDatabaseOptions { events: true, ..Default::default() }))
+            .build();
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let metrics = Arc::new(Metrics::new());
+        let cache = ProfileCache::new(Arc::new(client.clone()), db.clone(), 60, metrics.clone());
+
+        let (alice, bob, carol, dave) = (Keys::generate(), Keys::generate(), Keys::generate(), Keys::generate());
+
+        // Treffer: frisches Profil mit verifizierter NIP-05-Adresse
+        db.upsert_profile(&profile(&alice.public_key(), Some("Alice"), None, true)).await.unwrap();
+        assert_eq!(
+            cache.display_name(&alice.public_key()).await,
+            format!("Alice ✓ ({})", alice.public_key().to_bech32().unwrap())
+        );
+
+        // Abgelaufen: sofort der alte Name, die Erneuerung läuft im Hintergrund
+        let stale = CachedProfile { fetched_at: unix_now() - 3600, ..profile(&bob.public_key(), Some("Bob"), None, false) };
+        db.upsert_profile(&stale).await.unwrap();
+        let renamed = metadata_event(&bob, &Metadata::new().display_name("Robert"), 2000);
+        client.database().save_event(&renamed).await.unwrap();
+        assert_eq!(cache.name(&bob.public_key()).await, Some("Bob".to_string()));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while cache.lookup(&bob.public_key()).await.and_then(|p| p.display_name) != Some("Robert".to_string()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Profil wurde nicht im Hintergrund erneuert");
+
+        // Unbekannt: direkter Abruf, ohne Profil ein negativer Eintrag
+        let event = metadata_event(&carol, &Metadata::new().name("carol"), 1500);
+        client.database().save_event(&event).await.unwrap();
+        assert_eq!(cache.name(&carol.public_key()).await, Some("carol".to_string()));
+        assert_eq!(cache.name(&dave.public_key()).await, None);
+        assert!(cache.lookup(&dave.public_key()).await.is_some());
+
+        let output = metrics.render();
+        assert!(output.contains(r#"bridge_profile_cache_lookups_total{result="hit"} 1"#));
+        assert!(output.contains(r#"bridge_profile_cache_lookups_total{result="stale"} 1"#));
+        assert!(output.contains(r#"bridge_profile_cache_lookups_total{result="miss"} 2"#));
+    }
+}
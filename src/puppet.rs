@@ -0,0 +1,244 @@
+use tracing::{debug, info, warn};
+use nostr_sdk::prelude::*;
+use nostr_sdk::hashes::Hash;
+use teloxide::types::User;
+
+use crate::database::{Database, Puppet};
+use crate::unix_now;
+
+/// Domain-Separation für die Schlüsselableitung (nie ändern, sonst neue Identitäten!)
+const PUPPET_KDF_SALT: &[u8] = b"nostr-telegram-bridge/puppet/v1";
+
+/// Puppet-Profile werden spätestens nach dieser Zeit erneut veröffentlicht
+const PUPPET_PROFILE_REFRESH_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Leitet den Puppet-Key eines Telegram-Users deterministisch aus dem
+/// Bridge-Secret ab (HKDF-SHA256). Gleicher Bridge-Key und gleiche User-ID
+/// ergeben immer dieselbe Nostr-Identität, ohne Keys speichern zu müssen.
+pub fn derive_puppet_keys(bridge_keys: &Keys, telegram_user_id: i64) -> std::result::Result<Keys, String> {
+    let secret = bridge_keys.secret_key().map_err(|e| e.to_string())?;
+    let prk = hkdf::extract(PUPPET_KDF_SALT, &secret.secret_bytes());
+
+    // Ungültige Skalare (0 oder >= n) sind praktisch ausgeschlossen, der Zähler fängt sie trotzdem ab
+    for counter in 0u8..=255 {
+        let info = format!("telegram-user:{}:{}", telegram_user_id, counter);
+        let okm = hkdf::expand(prk.as_byte_array(), info.as_bytes(), 32);
+
+        if let Ok(secret_key) = SecretKey::from_slice(&okm) {
+            return Ok(Keys::new(secret_key));
+        }
+    }
+
+    Err("Keine gültige Puppet-Schlüsselableitung gefunden".to_string())
+}
+
+/// Baut die Kind-0-Metadata eines Puppets mit Verweis auf die Bridge
+pub fn build_puppet_metadata(user: &User, bridge_pubkey: &PublicKey) -> Metadata {
+    let bridge_npub = bridge_pubkey.to_bech32().unwrap_or_default();
+    let mut metadata = Metadata::new()
+        .name(user.full_name())
+        .display_name(user.full_name())
+        .custom_field("bot", false)
+        .custom_field("bridge", bridge_npub.clone());
+
+    match user.username {
+        Some(ref username) => {
+            metadata = metadata.about(format!(
+                "Telegram-User @{} – über die Bridge {} gespiegelt",
+                username, bridge_npub
+            ));
+            // Öffentliche Telegram-Profilbilder gibt es nur für User mit Username
+            if let Ok(url) = Url::parse(&format!("https://t.me/i/userpic/320/{}.jpg", username)) {
+                metadata = metadata.picture(url);
+            }
+            if let Ok(url) = Url::parse(&format!("https://t.me/{}", username)) {
+                metadata = metadata.website(url);
+            }
+        }
+        None => {
+            metadata = metadata.about(format!(
+                "Telegram-User – über die Bridge {} gespiegelt",
+                bridge_npub
+            ));
+        }
+    }
+
+    metadata
+}
+
+/// Gibt die Signier-Keys für einen Telegram-User zurück und veröffentlicht
+/// bei Bedarf (neu, Name geändert oder veraltet) das Profil des Puppets
+pub async fn puppet_keys_for(
+    client: &Client,
+    db: &Database,
+    bridge_keys: &Keys,
+    user: &User,
+) -> std::result::Result<Keys, String> {
+    let telegram_user_id = user.id.0 as i64;
+    let keys = derive_puppet_keys(bridge_keys, telegram_user_id)?;
+
+    let existing = db.get_puppet(telegram_user_id).await.unwrap_or_else(|e| {
+        warn!("Fehler beim Lesen des Puppets: {}", e);
+        None
+    });
+
+    let display_name = user.full_name();
+    let needs_profile = match existing {
+        Some(ref puppet) => {
+            puppet.display_name != display_name
+                || puppet.telegram_username != user.username
+                || puppet
+                    .profile_published_at
+                    .is_none_or(|published| unix_now() - published > PUPPET_PROFILE_REFRESH_SECS)
+        }
+        None => true,
+    };
+
+    if needs_profile {
+        let metadata = build_puppet_metadata(user, &bridge_keys.public_key());
+        let published_at = match publish_metadata(client, &keys, &metadata).await {
+            Ok(event_id) => {
+                info!("👤 Puppet-Profil für Telegram-User {} veröffentlicht: {}", telegram_user_id, event_id);
+                Some(unix_now())
+            }
+            Err(e) => {
+                warn!("Fehler beim Veröffentlichen des Puppet-Profils: {}", e);
+                existing.as_ref().and_then(|p| p.profile_published_at)
+            }
+        };
+
+        let puppet = Puppet {
+            telegram_user_id,
+            nostr_pubkey: keys.public_key().to_hex(),
+            display_name,
+            telegram_username: user.username.clone(),
+            profile_published_at: published_at,
+        };
+        if let Err(e) = db.save_puppet(&puppet).await {
+            warn!("Fehler beim Speichern des Puppets: {}", e);
+        }
+    } else {
+        debug!("Puppet-Profil für Telegram-User {} aktuell", telegram_user_id);
+    }
+
+    Ok(keys)
+}
+
+async fn publish_metadata(client: &Client, keys: &Keys, metadata: &Metadata) -> std::result::Result<EventId, String> {
+    let event = EventBuilder::metadata(metadata)
+        .to_event(keys)
+        .map_err(|e| e.to_string())?;
+    client.send_event(event).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::database::{MemoryDatabase, MemoryDatabaseOptions};
+
+    /// Client mit nicht verbundenem Relay: Senden scheitert nach kurzem Timeout,
+    /// das Event landet aber in der Datenbank des Clients und zeigt so jede
+    /// Veröffentlichung an
+    async fn client() -> Client {
+        let client = ClientBuilder::new()
+            .opts(Options::new().send_timeout(Some(std::time::Duration::from_millis(50))))
+            .database(MemoryDatabase::with_opts(MemoryDatabaseOptions { events: true, ..Default::default() }))
+            .build();
+        client.add_relay("ws://127.0.0.1:1").await.unwrap();
+        client
+    }
+
+    async fn published_profile(client: &Client, pubkey: PublicKey) -> Option<Metadata> {
+        let filter = Filter::new().kind(Kind::Metadata).author(pubkey);
+        let event = client.database().query(vec![filter], Order::Desc).await.unwrap().pop()?;
+        Some(Metadata::from_json(&event.content).unwrap())
+    }
+
+    fn user(first_name: &str, username: Option<&str>) -> User {
+        serde_json::from_value(serde_json::json!({
+            "id": 1001,
+            "is_bot": false,
+            "first_name": first_name,
+            "username": username,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_puppet_keys_for_new_user() {
+        let db = Database::new(":memory:").unwrap();
+        let bridge_keys = Keys::generate();
+        let client = client().await;
+        let alice = user("Alice", Some("alice"));
+
+        let keys = puppet_keys_for(&client, &db, &bridge_keys, &alice).await.unwrap();
+        assert_eq!(keys.public_key(), derive_puppet_keys(&bridge_keys, 1001).unwrap().public_key());
+
+        assert_eq!(
+            published_profile(&client, keys.public_key()).await,
+            Some(build_puppet_metadata(&alice, &bridge_keys.public_key()))
+        );
+        let puppet = db.get_puppet(1001).await.unwrap().unwrap();
+        assert_eq!(puppet.nostr_pubkey, keys.public_key().to_hex());
+        assert_eq!(puppet.display_name, "Alice");
+        assert_eq!(puppet.telegram_username.as_deref(), Some("alice"));
+        // Das Senden ist gescheitert, daher gilt das Profil als nicht veröffentlicht
+        assert_eq!(puppet.profile_published_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_puppet_profile_republish_rules() {
+        let bridge_keys = Keys::generate();
+        let pubkey = derive_puppet_keys(&bridge_keys, 1001).unwrap().public_key();
+        let day = 24 * 60 * 60;
+
+        // (Name, Username, veröffentlicht vor Sekunden, erneut veröffentlichen?)
+        let cases = [
+            ("Alice", Some("alice"), Some(day), false),
+            ("Alice", Some("alice"), Some(6 * day), false),
+            ("Alice", Some("alice"), Some(8 * day), true),
+            ("Alice", Some("alice"), None, true),
+            ("Alice Smith", Some("alice"), Some(day), true),
+            ("Alice", Some("alice_s"), Some(day), true),
+            ("Alice", None, Some(day), true),
+        ];
+
+        for (name, username, published_ago, republish) in cases {
+            let db = Database::new(":memory:").unwrap();
+            let client = client().await;
+            db.save_puppet(&Puppet {
+                telegram_user_id: 1001,
+                nostr_pubkey: pubkey.to_hex(),
+                display_name: "Alice".to_string(),
+                telegram_username: Some("alice".to_string()),
+                profile_published_at: published_ago.map(|ago| unix_now() - ago),
+            }).await.unwrap();
+
+            let current = user(name, username);
+            puppet_keys_for(&client, &db, &bridge_keys, &current).await.unwrap();
+
+            let case = (name, username, published_ago);
+            assert_eq!(published_profile(&client, pubkey).await.is_some(), republish, "{:?}", case);
+            let puppet = db.get_puppet(1001).await.unwrap().unwrap();
+            assert_eq!(puppet.display_name, name, "{:?}", case);
+            assert_eq!(puppet.telegram_username.as_deref(), username, "{:?}", case);
+        }
+    }
+
+    #[test]
+    fn test_puppet_keys_are_deterministic_and_distinct() {
+        let bridge_keys = Keys::generate();
+
+        let alice = derive_puppet_keys(&bridge_keys, 1001).unwrap();
+        let alice_again = derive_puppet_keys(&bridge_keys, 1001).unwrap();
+        let bob = derive_puppet_keys(&bridge_keys, 1002).unwrap();
+
+        assert_eq!(alice.public_key(), alice_again.public_key());
+        assert_ne!(alice.public_key(), bob.public_key());
+        assert_ne!(alice.public_key(), bridge_keys.public_key());
+
+        // Anderer Bridge-Key ergibt andere Puppets
+        let other_bridge = Keys::generate();
+        assert_ne!(derive_puppet_keys(&other_bridge, 1001).unwrap().public_key(), alice.public_key());
+    }
+}
@@ -0,0 +1,51 @@
+use tracing::{debug, info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::RetentionConfig;
+use crate::database::Database;
+use crate::storage::Storage;
+use crate::unix_now;
+
+/// Puffer beim Neu-Abonnieren ab dem Since-Cursor, da Relays Events nicht streng sortiert liefern
+pub const RESYNC_SLACK_SECS: i64 = 60;
+
+/// NIP-59: `created_at` eines Gift Wraps liegt bis zu zwei Tage vor dem Versand
+const GIFT_WRAP_JITTER_SECS: i64 = 2 * 24 * 60 * 60;
+
+/// Ältester Zeitpunkt, den ein Neu-Abonnieren ab dem Since-Cursor `since` noch
+/// abfragt (NIP-17 mit Jitter). Ältere Events liefern die Relays nicht erneut aus.
+pub fn resync_floor(since: i64) -> i64 {
+    since - RESYNC_SLACK_SECS - GIFT_WRAP_JITTER_SECS
+}
+
+/// Hintergrund-Task: löscht regelmäßig alte Mappings gemäß der Retention-Regeln.
+///
+/// Der Loop-Schutz für Nostr-Events bleibt über die gespeicherten Event-Hashes
+/// erhalten, bis der Since-Cursor so weit vorgerückt ist, dass ein
+/// Neu-Abonnieren die Events nicht mehr abfragt. Für Telegram ist das nicht
+/// nötig, da bestätigte Updates von der Bot-API nicht erneut ausgeliefert werden.
+pub async fn run_retention(storage: Arc<dyn Storage>, db: Arc<Database>, retention: RetentionConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(retention.interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        match storage.prune_mappings(retention.max_age_secs, retention.max_rows, unix_now()).await {
+            Ok(0) => {}
+            Ok(pruned) => info!("🧹 Retention: {} alte Mappings gelöscht", pruned),
+            Err(e) => warn!("Fehler bei der Retention-Bereinigung: {}", e),
+        }
+
+        // Der Zeitstempel eines Mappings liegt nie vor dem created_at seines Events
+        match db.nostr_since().await {
+            Ok(Some(since)) => match storage.forget_pruned_events(resync_floor(since)).await {
+                Ok(0) => {}
+                Ok(forgotten) => debug!("Retention: {} Event-Hashes außerhalb des Abonnier-Fensters vergessen", forgotten),
+                Err(e) => warn!("Fehler beim Bereinigen der Event-Hashes: {}", e),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("Since-Cursor nicht lesbar: {}", e),
+        }
+    }
+}
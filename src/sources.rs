@@ -0,0 +1,199 @@
+use nostr_sdk::prelude::*;
+use std::env;
+
+use crate::config::{ConfigError, EncryptionType};
+
+/// Format, in dem ein Telegram-Kanal auf Nostr veröffentlicht
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelFormat {
+    /// Öffentliche Notiz (Kind 1)
+    Note,
+    /// Artikel nach NIP-23 (Kind 30023), erste Zeile als Titel
+    Article,
+}
+
+/// Weitere Telegram-Quellen neben der Gruppe: Kanäle (nur Telegram → Nostr)
+/// aus TELEGRAM_CHANNELS und Privatchats mit dem Bot, jeweils mit eigenem
+/// DM-Partner, aus TELEGRAM_PRIVATE_CHATS
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    channels: Vec<(i64, ChannelFormat)>,
+    private_chats: Vec<(i64, PublicKey)>,
+}
+
+impl Sources {
+    /// Liest TELEGRAM_CHANNELS (`-1001234567890:note,-1009876543210:article`)
+    /// und TELEGRAM_PRIVATE_CHATS (`<Telegram-User-ID>:npub1…`)
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let read = |var: &str| env::var(var).ok().filter(|value| !value.trim().is_empty());
+        let invalid = |var: &str| {
+            let var = var.to_string();
+            move |msg| ConfigError::InvalidValue { var, msg }
+        };
+
+        let channels = match read("TELEGRAM_CHANNELS") {
+            Some(value) => parse_channels(&value).map_err(invalid("TELEGRAM_CHANNELS"))?,
+            None => Vec::new(),
+        };
+        let private_chats = match read("TELEGRAM_PRIVATE_CHATS") {
+            Some(value) => parse_private_chats(&value).map_err(invalid("TELEGRAM_PRIVATE_CHATS"))?,
+            None => Vec::new(),
+        };
+        Ok(Sources { channels, private_chats })
+    }
+
+    /// Veröffentlichungsformat eines Kanals, `None` für nicht eingerichtete Kanäle
+    pub fn channel(&self, chat_id: i64) -> Option<ChannelFormat> {
+        self.channels.iter().find(|(id, _)| *id == chat_id).map(|(_, format)| *format)
+    }
+
+    /// DM-Partner eines Privatchats (Chat-ID = Telegram-User-ID)
+    pub fn private_chat_partner(&self, chat_id: i64) -> Option<PublicKey> {
+        self.private_chats.iter().find(|(id, _)| *id == chat_id).map(|(_, partner)| *partner)
+    }
+
+    /// Privatchat, in den DMs dieses Partners gehören
+    pub fn private_chat_for_partner(&self, pubkey: &PublicKey) -> Option<i64> {
+        self.private_chats.iter().find(|(_, partner)| partner == pubkey).map(|(id, _)| *id)
+    }
+
+    pub fn has_private_chats(&self) -> bool {
+        !self.private_chats.is_empty()
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn private_chat_count(&self) -> usize {
+        self.private_chats.len()
+    }
+}
+
+/// DM-Format der Privatchats: NIP-17 im nip17-Modus, sonst NIP-04
+pub fn private_chat_route(route: &EncryptionType) -> EncryptionType {
+    match route {
+        EncryptionType::Nip17 => EncryptionType::Nip17,
+        _ => EncryptionType::Nip04,
+    }
+}
+
+/// Zerlegt eine Liste `<Chat-ID>:<Ziel>`, prüft die IDs und Doubletten
+fn parse_entries<T>(value: &str, parse_target: impl Fn(&str) -> Result<T, String>) -> Result<Vec<(i64, T)>, String> {
+    let mut entries: Vec<(i64, T)> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (chat, target) = entry
+            .rsplit_once(':')
+            .ok_or_else(|| format!("'{}' muss die Form <Chat-ID>:<Ziel> haben", entry))?;
+        let chat_id = chat
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| format!("'{}' ist keine gültige Chat-ID", chat.trim()))?;
+        if entries.iter().any(|(id, _)| *id == chat_id) {
+            return Err(format!("Chat {} ist mehrfach eingetragen", chat_id));
+        }
+        entries.push((chat_id, parse_target(target.trim())?));
+    }
+    Ok(entries)
+}
+
+fn parse_channels(value: &str) -> Result<Vec<(i64, ChannelFormat)>, String> {
+    parse_entries(value, |format| match format.to_lowercase().as_str() {
+        "note" => Ok(ChannelFormat::Note),
+        "article" => Ok(ChannelFormat::Article),
+        _ => Err(format!("'{}' muss 'note' oder 'article' sein", format)),
+    })
+}
+
+fn parse_private_chats(value: &str) -> Result<Vec<(i64, PublicKey)>, String> {
+    let chats = parse_entries(value, |partner| {
+        PublicKey::parse(partner).map_err(|_| format!("'{}' ist kein gültiger Pubkey (npub…)", partner))
+    })?;
+    // Privatchats haben positive IDs (= User-ID); ein Partner gehört zu genau einem Chat
+    if let Some((chat_id, _)) = chats.iter().find(|(id, _)| *id <= 0) {
+        return Err(format!("{} ist keine Telegram-User-ID", chat_id));
+    }
+    for (index, (_, partner)) in chats.iter().enumerate() {
+        if chats[..index].iter().any(|(_, other)| other == partner) {
+            return Err(format!("{} ist mehreren Chats zugeordnet", partner.to_bech32().unwrap_or_default()));
+        }
+    }
+    Ok(chats)
+}
+
+/// Event für einen Kanalbeitrag. Artikel bekommen die erste Zeile als Titel
+/// (ohne Markdown-Überschrift) und `d` = `telegram-<Chat>-<Nachricht>`, damit
+/// ein erneutes Veröffentlichen denselben Artikel ersetzt.
+pub fn channel_post_event(
+    format: ChannelFormat,
+    markdown: &str,
+    chat_id: i64,
+    message_id: i32,
+    published_at: i64,
+    extra_tags: Vec<Tag>,
+) -> EventBuilder {
+    match format {
+        ChannelFormat::Note => EventBuilder::text_note(markdown, extra_tags),
+        ChannelFormat::Article => {
+            let (first_line, rest) = markdown.split_once('\n').unwrap_or((markdown, ""));
+            let title = first_line.trim_start_matches('#').trim();
+            // Einzeilige Beiträge bleiben als Inhalt erhalten
+            let body = if rest.trim().is_empty() { markdown } else { rest.trim_start_matches('\n') };
+
+            let mut tags = vec![
+                Tag::Identifier(format!("telegram-{}-{}", chat_id, message_id)),
+                Tag::Generic(TagKind::Custom("title".to_string()), vec![title.to_string()]),
+                Tag::Generic(TagKind::Custom("published_at".to_string()), vec![published_at.to_string()]),
+            ];
+            tags.extend(extra_tags);
+            EventBuilder::new(Kind::LongFormTextNote, body, tags)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources() {
+        let channels = parse_channels("-1001234567890:note, -1009876543210:Article").unwrap();
+        assert_eq!(channels, vec![(-1001234567890, ChannelFormat::Note), (-1009876543210, ChannelFormat::Article)]);
+        assert!(parse_channels("-100123:longform").is_err());
+        assert!(parse_channels("-100123:note,-100123:article").is_err());
+
+        let alice = Keys::generate().public_key();
+        let sources = Sources {
+            channels,
+            private_chats: parse_private_chats(&format!("4711:{}", alice.to_bech32().unwrap())).unwrap(),
+        };
+        assert_eq!(sources.channel(-1009876543210), Some(ChannelFormat::Article));
+        assert_eq!(sources.channel(-1), None);
+        assert_eq!(sources.private_chat_partner(4711), Some(alice));
+        assert_eq!(sources.private_chat_for_partner(&alice), Some(4711));
+
+        let bech32 = alice.to_bech32().unwrap();
+        assert!(parse_private_chats(&format!("-100123:{}", bech32)).is_err());
+        assert!(parse_private_chats(&format!("1:{},2:{}", bech32, bech32)).is_err());
+        assert!(parse_private_chats("1:npub1kaputt").is_err());
+    }
+
+    #[test]
+    fn test_channel_post_event() {
+        let keys = Keys::generate();
+        let article = channel_post_event(ChannelFormat::Article, "## Neuigkeiten\n\nDer **Text**", -100123, 42, 1700000000, Vec::new())
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(article.kind, Kind::LongFormTextNote);
+        assert_eq!(article.content, "Der **Text**");
+        assert_eq!(article.identifier(), Some("telegram--100123-42"));
+        assert_eq!(article.tags[1].as_vec(), vec!["title", "Neuigkeiten"]);
+        assert_eq!(article.tags[2].as_vec(), vec!["published_at", "1700000000"]);
+
+        let note = channel_post_event(ChannelFormat::Note, "Hallo", -100123, 43, 1700000000, Vec::new())
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(note.kind, Kind::TextNote);
+        assert_eq!(note.content, "Hallo");
+    }
+}
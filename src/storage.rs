@@ -0,0 +1,453 @@
+use async_trait::async_trait;
+use nostr_sdk::hashes::{sha256, Hash};
+
+use crate::database::{DbResult, MessageMapping};
+
+/// Speicher für Nachrichten-Mappings (Loop-Schutz, Zuordnung, Statistiken).
+///
+/// SQLite (`Database`) ist der Standard. Mit dem Cargo-Feature `postgres`
+/// können mehrere Bridge-Instanzen ihre Mappings zentral in PostgreSQL
+/// ablegen (`PostgresStorage`). Profil-Cache, Verknüpfungen, Puppets, das
+/// Archiv und der Nostr-Abo-Zeitpunkt bleiben in der lokalen SQLite-Datenbank.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Reserviert eine Telegram-Nachricht vor dem Weiterleiten. Gibt die
+    /// Claim-ID zurück oder `None`, wenn die Nachricht bereits verarbeitet
+    /// wird oder wurde – auch von einer anderen Bridge-Instanz. Ein Claim,
+    /// der länger als `CLAIM_TIMEOUT_SECS` offen ist (Absturz zwischen Claim
+    /// und Abschluss), wird übernommen.
+    async fn claim_telegram_message(&self, chat_id: i64, message_id: i64, timestamp: i64) -> DbResult<Option<i64>>;
+
+    /// Reserviert ein Nostr-Event vor dem Weiterleiten nach `chat_id`, analog zu
+    /// `claim_telegram_message`. Durch die Retention gelöschte Events gelten
+    /// als verarbeitet.
+    async fn claim_nostr_event(&self, event_id: &str, chat_id: i64, timestamp: i64) -> DbResult<Option<i64>>;
+
+    /// Schließt einen Claim nach erfolgreichem Senden mit dem vollständigen Mapping ab
+    async fn complete_claim(&self, claim_id: i64, mapping: &MessageMapping) -> DbResult<()>;
+
+    /// Gibt einen Claim nach fehlgeschlagenem Senden wieder frei
+    async fn release_claim(&self, claim_id: i64) -> DbResult<()>;
+
+    /// Speichert die weiteren Telegram-Nachrichten eines aufgeteilten Nostr-Events
+    /// (ab Teil 2, der erste Teil steht im Mapping), damit Antworten auf jeden
+    /// Teil dem Event zugeordnet werden. Die Retention löscht sie mit dem Mapping.
+    async fn save_message_parts(&self, chat_id: i64, nostr_event_id: &str, message_ids: &[i64]) -> DbResult<()>;
+
+    /// Prüft ob ein Nostr-Event bereits verarbeitet wurde oder gerade wird
+    /// (Loop-Schutz). Berücksichtigt auch Events, deren Mapping durch die
+    /// Retention gelöscht wurde; verfallene Claims zählen nicht.
+    async fn nostr_event_exists(&self, event_id: &str, now: i64) -> DbResult<bool>;
+
+    /// Löscht Mappings älter als `max_age_secs` bzw. über `max_rows` hinaus (die
+    /// ältesten zuerst). Offene Claims bleiben unberührt und zählen nicht mit.
+    /// Die Event-IDs bleiben als Hash erhalten, damit der Loop-Schutz alte
+    /// Events weiterhin erkennt. Gibt die Anzahl gelöschter Zeilen zurück.
+    async fn prune_mappings(&self, max_age_secs: Option<i64>, max_rows: Option<i64>, now: i64) -> DbResult<usize>;
+
+    /// Vergisst die Hashes gelöschter Mappings mit Zeitstempel vor `before`.
+    /// Solche Events fragt die Bridge beim Neu-Abonnieren nicht mehr ab, der
+    /// Loop-Schutz braucht sie also nicht mehr. Gibt die Anzahl zurück.
+    async fn forget_pruned_events(&self, before: i64) -> DbResult<usize>;
+
+    /// Gesamtzahl der durch die Retention gelöschten Mappings
+    async fn get_pruned_count(&self) -> DbResult<i64>;
+
+    /// Gibt Statistiken zurück: (gesamt, Telegram → Nostr, Nostr → Telegram)
+    async fn get_stats(&self) -> DbResult<(i64, i64, i64)>;
+
+    /// Prüft per echtem Schreibzugriff, ob der Speicher beschreibbar ist (für `/readyz`)
+    async fn check_writable(&self, now: i64) -> DbResult<()>;
+}
+
+/// Nach dieser Zeit gilt ein offener Claim als verwaist (Bridge zwischen Claim
+/// und Abschluss beendet) und die Nachricht kann erneut weitergeleitet werden.
+/// Deutlich länger als jedes Senden samt Wiederholungen und Shutdown-Frist.
+pub const CLAIM_TIMEOUT_SECS: i64 = 300;
+
+/// Kompakter 64-Bit-Hash einer Nostr-Event-ID für gelöschte Mappings.
+/// Falsch-positive Treffer (ein neues Event gilt als bekannt) sind bei
+/// 2^64 möglichen Werten praktisch ausgeschlossen, falsch-negative unmöglich.
+pub fn event_id_hash(event_id: &str) -> i64 {
+    let digest = sha256::Hash::hash(event_id.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest.as_byte_array()[..8]);
+    i64::from_be_bytes(bytes)
+}
+
+/// Gemeinsame Tests, die jedes Storage-Backend bestehen muss
+#[cfg(test)]
+pub mod conformance {
+    use super::*;
+    use crate::database::MessageDirection;
+    use crate::unix_now;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::task::JoinSet;
+
+    /// Erzeugt die Conformance-Tests für ein Backend. `$make` liefert
+    /// `Box<dyn Storage>`; folgende Attribute (z.B. `ignore` für Backends mit
+    /// externem Server) gelten für jeden Test.
+    macro_rules! storage_conformance_tests {
+        ($make:expr $(, $attr:meta)*) => {
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_telegram_message_is_claimed_once() {
+                let storage = $make.await;
+                $crate::storage::conformance::telegram_message_is_claimed_once(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_nostr_event_is_claimed_once() {
+                let storage = $make.await;
+                $crate::storage::conformance::nostr_event_is_claimed_once(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_completed_claim_protects_sent_event() {
+                let storage = $make.await;
+                $crate::storage::conformance::completed_claim_protects_sent_event(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_message_parts() {
+                let storage = $make.await;
+                $crate::storage::conformance::message_parts(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_duplicate_prevention() {
+                let storage = $make.await;
+                $crate::storage::conformance::duplicate_prevention(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_released_claim_can_be_retried() {
+                let storage = $make.await;
+                $crate::storage::conformance::released_claim_can_be_retried(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_stale_claim_can_be_taken_over() {
+                let storage = $make.await;
+                $crate::storage::conformance::stale_claim_can_be_taken_over(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_retention_prunes_and_keeps_loop_protection() {
+                let storage = $make.await;
+                $crate::storage::conformance::retention_prunes_and_keeps_loop_protection(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_retention_keeps_open_claims() {
+                let storage = $make.await;
+                $crate::storage::conformance::retention_keeps_open_claims(&*storage).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+            $(#[$attr])*
+            async fn test_concurrent_duplicates_are_forwarded_once() {
+                let storage = $make.await;
+                let storage: Box<dyn $crate::storage::Storage> = storage;
+                $crate::storage::conformance::concurrent_duplicates_are_forwarded_once(storage.into()).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_check_writable() {
+                let storage = $make.await;
+                $crate::storage::conformance::writable(&*storage).await;
+            }
+
+            #[tokio::test]
+            $(#[$attr])*
+            async fn test_statistics() {
+                let storage = $make.await;
+                $crate::storage::conformance::statistics(&*storage).await;
+            }
+        };
+    }
+    pub(crate) use storage_conformance_tests;
+
+    /// Mapping Telegram → Nostr mit aktuellem Zeitstempel; Tests überschreiben
+    /// abweichende Felder per `..mapping(…)`
+    fn mapping(chat_id: i64, message_id: i64, event_id: &str) -> MessageMapping {
+        MessageMapping {
+            id: None,
+            telegram_chat_id: chat_id,
+            telegram_message_id: message_id,
+            telegram_thread_id: None,
+            nostr_event_id: event_id.to_string(),
+            nostr_recipient_pubkey: "npub1test".to_string(),
+            direction: MessageDirection::TelegramToNostr,
+            timestamp: unix_now(),
+        }
+    }
+
+    /// Leitet ein Mapping wie die Bridge weiter: Claim zum Zeitstempel des
+    /// Mappings, dann Abschluss. Gibt die Claim-ID zurück.
+    async fn forward(storage: &dyn Storage, mapping: &MessageMapping) -> i64 {
+        let claim_id = match mapping.direction {
+            MessageDirection::TelegramToNostr => {
+                storage.claim_telegram_message(mapping.telegram_chat_id, mapping.telegram_message_id, mapping.timestamp).await
+            }
+            MessageDirection::NostrToTelegram => {
+                storage.claim_nostr_event(&mapping.nostr_event_id, mapping.telegram_chat_id, mapping.timestamp).await
+            }
+        };
+        let claim_id = claim_id.unwrap().expect("Claim erwartet");
+        storage.complete_claim(claim_id, mapping).await.unwrap();
+        claim_id
+    }
+
+    pub async fn telegram_message_is_claimed_once(storage: &dyn Storage) {
+        forward(storage, &mapping(-1001234567890, 123, "abc123")).await;
+
+        assert!(storage.claim_telegram_message(-1001234567890, 123, unix_now()).await.unwrap().is_none());
+        assert!(storage.claim_telegram_message(-1001234567890, 999, unix_now()).await.unwrap().is_some());
+        assert!(storage.claim_telegram_message(-1009999999999, 123, unix_now()).await.unwrap().is_some());
+    }
+
+    pub async fn nostr_event_is_claimed_once(storage: &dyn Storage) {
+        let mapping = MessageMapping {
+            direction: MessageDirection::NostrToTelegram,
+            ..mapping(-1001234567890, 456, "def456")
+        };
+        forward(storage, &mapping).await;
+
+        assert!(storage.nostr_event_exists("def456", unix_now()).await.unwrap());
+        assert!(!storage.nostr_event_exists("xyz999", unix_now()).await.unwrap());
+        assert!(storage.claim_nostr_event("def456", -1001234567890, unix_now()).await.unwrap().is_none());
+    }
+
+    /// Der Abschluss trägt die Event-ID der Gegenseite ein: liefert ein Relay
+    /// das von der Bridge veröffentlichte Event zurück, greift der Loop-Schutz
+    pub async fn completed_claim_protects_sent_event(storage: &dyn Storage) {
+        let claim_id = storage.claim_telegram_message(-1001234567890, 789, unix_now()).await.unwrap().unwrap();
+        assert!(!storage.nostr_event_exists("ghi789", unix_now()).await.unwrap());
+
+        storage.complete_claim(claim_id, &mapping(-1001234567890, 789, "ghi789")).await.unwrap();
+
+        assert!(storage.nostr_event_exists("ghi789", unix_now()).await.unwrap());
+        assert!(storage.claim_nostr_event("ghi789", -1001234567890, unix_now()).await.unwrap().is_none());
+        assert_eq!(storage.get_stats().await.unwrap(), (1, 1, 0));
+    }
+
+    pub async fn message_parts(storage: &dyn Storage) {
+        let mapping = MessageMapping {
+            direction: MessageDirection::NostrToTelegram,
+            timestamp: 1000,
+            ..mapping(-1001234567890, 500, "long-event")
+        };
+        forward(storage, &mapping).await;
+        storage.save_message_parts(-1001234567890, "long-event", &[501, 502]).await.unwrap();
+
+        // Jede Telegram-Nachricht gehört zu höchstens einem Event
+        assert!(storage.save_message_parts(-1001234567890, "other-event", &[502]).await.is_err());
+        assert_eq!(storage.get_stats().await.unwrap().0, 1);
+
+        // Die Retention löscht die Teile mit dem Mapping
+        assert_eq!(storage.prune_mappings(Some(10), None, 2000).await.unwrap(), 1);
+        storage.save_message_parts(-1001234567890, "other-event", &[501, 502]).await.unwrap();
+    }
+
+    pub async fn duplicate_prevention(storage: &dyn Storage) {
+        let claim_id = forward(storage, &mapping(-1001234567890, 111, "unique123")).await;
+
+        // Ein abgeschlossener Claim lässt sich nicht mehr freigeben
+        storage.release_claim(claim_id).await.unwrap();
+        assert!(storage.claim_telegram_message(-1001234567890, 111, unix_now()).await.unwrap().is_none());
+
+        // Eine Event-ID gehört zu genau einem Mapping
+        let claim_id = storage.claim_telegram_message(-1001234567890, 112, unix_now()).await.unwrap().unwrap();
+        assert!(storage.complete_claim(claim_id, &mapping(-1001234567890, 112, "unique123")).await.is_err());
+        assert_eq!(storage.get_stats().await.unwrap().0, 1);
+    }
+
+    pub async fn retention_prunes_and_keeps_loop_protection(storage: &dyn Storage) {
+        for i in 0..10 {
+            let mapping = MessageMapping {
+                timestamp: 1000 + i * 100,
+                ..mapping(-1001234567890, i, &format!("retention-event{}", i))
+            };
+            forward(storage, &mapping).await;
+        }
+
+        // Nach Alter: Zeitstempel 1000..1400 sind bei now = 2050 älter als 600s
+        assert_eq!(storage.prune_mappings(Some(600), None, 2050).await.unwrap(), 5);
+        // Nach Anzahl: von den verbleibenden 5 bleiben die 3 neuesten
+        assert_eq!(storage.prune_mappings(None, Some(3), 2050).await.unwrap(), 2);
+        assert_eq!(storage.prune_mappings(Some(600), Some(3), 2050).await.unwrap(), 0);
+
+        assert_eq!(storage.get_stats().await.unwrap().0, 3);
+        assert_eq!(storage.get_pruned_count().await.unwrap(), 7);
+        assert!(storage.claim_telegram_message(-1001234567890, 9, 2050).await.unwrap().is_none());
+
+        // Gelöschte Events werden vom Loop-Schutz weiterhin erkannt
+        assert!(storage.nostr_event_exists("retention-event0", unix_now()).await.unwrap());
+        assert!(storage.nostr_event_exists("retention-event6", unix_now()).await.unwrap());
+        assert!(!storage.nostr_event_exists("retention-event-new", unix_now()).await.unwrap());
+        assert_eq!(storage.claim_nostr_event("retention-event0", -1001234567890, 2050).await.unwrap(), None);
+
+        // Hashes außerhalb des Neu-Abonnier-Fensters werden vergessen (Zeitstempel 1000..1600)
+        assert_eq!(storage.forget_pruned_events(1250).await.unwrap(), 3);
+        assert!(!storage.nostr_event_exists("retention-event0", unix_now()).await.unwrap());
+        assert!(storage.nostr_event_exists("retention-event3", unix_now()).await.unwrap());
+        assert_eq!(storage.forget_pruned_events(1250).await.unwrap(), 0);
+        assert_eq!(storage.get_pruned_count().await.unwrap(), 7);
+    }
+
+    /// Ein Claim, der während der Retention noch gesendet wird, bleibt erhalten
+    /// und lässt sich danach abschließen
+    pub async fn retention_keeps_open_claims(storage: &dyn Storage) {
+        for i in 0..3 {
+            let mapping = MessageMapping {
+                timestamp: 1000 + i,
+                ..mapping(-1001234567890, i, &format!("sent-event{}", i))
+            };
+            forward(storage, &mapping).await;
+        }
+        let telegram_claim = storage.claim_telegram_message(-1001234567890, 10, 1000).await.unwrap().unwrap();
+        let nostr_claim = storage.claim_nostr_event("open-event", -1001234567890, 1000).await.unwrap().unwrap();
+
+        // Offene Claims zählen weder gegen das Alter noch gegen die Zeilenzahl
+        assert_eq!(storage.prune_mappings(None, Some(1), 1050).await.unwrap(), 2);
+        assert_eq!(storage.prune_mappings(Some(10), None, 1050).await.unwrap(), 1);
+        assert_eq!(storage.get_pruned_count().await.unwrap(), 3);
+        assert!(storage.nostr_event_exists("open-event", 1050).await.unwrap());
+
+        storage.complete_claim(telegram_claim, &MessageMapping {
+            timestamp: 1050,
+            ..mapping(-1001234567890, 10, "late-event")
+        }).await.unwrap();
+        storage.complete_claim(nostr_claim, &MessageMapping {
+            direction: MessageDirection::NostrToTelegram,
+            timestamp: 1050,
+            ..mapping(-1001234567890, 11, "open-event")
+        }).await.unwrap();
+        assert_eq!(storage.get_stats().await.unwrap(), (2, 1, 1));
+        assert!(storage.nostr_event_exists("late-event", 1050).await.unwrap());
+    }
+
+    /// Simuliert doppelt zugestellte Updates bzw. Events (oder mehrere
+    /// Instanzen): alle Zustellungen laufen gleichzeitig durch Claim → Senden →
+    /// Abschluss, gesendet werden darf jeweils nur einmal.
+    pub async fn concurrent_duplicates_are_forwarded_once(storage: Arc<dyn Storage>) {
+        const DELIVERIES: usize = 16;
+        let sent_to_nostr = Arc::new(AtomicUsize::new(0));
+        let sent_to_telegram = Arc::new(AtomicUsize::new(0));
+        let mut tasks = JoinSet::new();
+
+        for i in 0..DELIVERIES {
+            let (task_storage, sent) = (storage.clone(), sent_to_nostr.clone());
+            tasks.spawn(async move {
+                if let Some(claim_id) = task_storage.claim_telegram_message(-1001234567890, 42, unix_now()).await.unwrap() {
+                    sent.fetch_add(1, Ordering::SeqCst);
+                    let mapping = mapping(-1001234567890, 42, &format!("sent-event{}", i));
+                    task_storage.complete_claim(claim_id, &mapping).await.unwrap();
+                }
+            });
+
+            let (task_storage, sent) = (storage.clone(), sent_to_telegram.clone());
+            tasks.spawn(async move {
+                if let Some(claim_id) = task_storage.claim_nostr_event("incoming-event", -1001234567890, unix_now()).await.unwrap() {
+                    sent.fetch_add(1, Ordering::SeqCst);
+                    let mapping = MessageMapping {
+                        direction: MessageDirection::NostrToTelegram,
+                        nostr_recipient_pubkey: "npub1sender".to_string(),
+                        ..mapping(-1001234567890, 1000 + i as i64, "incoming-event")
+                    };
+                    task_storage.complete_claim(claim_id, &mapping).await.unwrap();
+                }
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            result.unwrap();
+        }
+
+        assert_eq!(sent_to_nostr.load(Ordering::SeqCst), 1);
+        assert_eq!(sent_to_telegram.load(Ordering::SeqCst), 1);
+        assert_eq!(storage.get_stats().await.unwrap(), (2, 1, 1));
+        assert!(storage.nostr_event_exists("incoming-event", unix_now()).await.unwrap());
+    }
+
+    pub async fn released_claim_can_be_retried(storage: &dyn Storage) {
+        let claim_id = storage.claim_telegram_message(-1001234567890, 7, unix_now()).await.unwrap().unwrap();
+
+        // Offene Claims blockieren Duplikate, zählen aber nicht als Mapping
+        assert!(storage.claim_telegram_message(-1001234567890, 7, unix_now()).await.unwrap().is_none());
+        assert_eq!(storage.get_stats().await.unwrap().0, 0);
+
+        // Nach fehlgeschlagenem Senden wird der Claim freigegeben
+        storage.release_claim(claim_id).await.unwrap();
+        assert!(storage.claim_telegram_message(-1001234567890, 7, unix_now()).await.unwrap().is_some());
+
+        let claim_id = storage.claim_nostr_event("retry-event", -1001234567890, unix_now()).await.unwrap().unwrap();
+        assert!(storage.nostr_event_exists("retry-event", unix_now()).await.unwrap());
+        storage.release_claim(claim_id).await.unwrap();
+        assert!(storage.claim_nostr_event("retry-event", -1001234567890, unix_now()).await.unwrap().is_some());
+    }
+
+    pub async fn stale_claim_can_be_taken_over(storage: &dyn Storage) {
+        let now = unix_now();
+        let stale = now - CLAIM_TIMEOUT_SECS - 1;
+
+        // Ein frischer Claim blockiert, ein verwaister wird genau einmal übernommen
+        storage.claim_telegram_message(-1001234567890, 8, now).await.unwrap().unwrap();
+        assert!(storage.claim_telegram_message(-1001234567890, 8, now).await.unwrap().is_none());
+
+        let claim_id = storage.claim_telegram_message(-1001234567890, 9, stale).await.unwrap().unwrap();
+        let taken_over = storage.claim_telegram_message(-1001234567890, 9, now).await.unwrap();
+        assert_eq!(taken_over, Some(claim_id));
+        assert!(storage.claim_telegram_message(-1001234567890, 9, now).await.unwrap().is_none());
+
+        storage.claim_nostr_event("stale-event", -1001234567890, stale).await.unwrap().unwrap();
+        assert!(!storage.nostr_event_exists("stale-event", now).await.unwrap());
+        let claim_id = storage.claim_nostr_event("stale-event", -1001234567890, now).await.unwrap().unwrap();
+        assert!(storage.nostr_event_exists("stale-event", now).await.unwrap());
+
+        // Abgeschlossene Mappings verfallen nie
+        let mapping = MessageMapping {
+            direction: MessageDirection::NostrToTelegram,
+            nostr_recipient_pubkey: "npub1sender".to_string(),
+            timestamp: now,
+            ..mapping(-1001234567890, 10, "stale-event")
+        };
+        storage.complete_claim(claim_id, &mapping).await.unwrap();
+        let later = now + CLAIM_TIMEOUT_SECS + 1;
+        assert!(storage.nostr_event_exists("stale-event", later).await.unwrap());
+        assert!(storage.claim_nostr_event("stale-event", -1001234567890, later).await.unwrap().is_none());
+    }
+
+    pub async fn writable(storage: &dyn Storage) {
+        storage.check_writable(unix_now()).await.unwrap();
+        storage.check_writable(unix_now()).await.unwrap();
+        assert_eq!(storage.get_stats().await.unwrap().0, 0);
+    }
+
+    pub async fn statistics(storage: &dyn Storage) {
+        for i in 0..5 {
+            let mapping = MessageMapping {
+                direction: if i % 2 == 0 { MessageDirection::TelegramToNostr } else { MessageDirection::NostrToTelegram },
+                ..mapping(-1001234567890, i, &format!("event{}", i))
+            };
+            forward(storage, &mapping).await;
+        }
+        // Offene Claims zählen nicht mit
+        storage.claim_telegram_message(-1001234567890, 5, unix_now()).await.unwrap().unwrap();
+
+        let (total, t_to_n, n_to_t) = storage.get_stats().await.unwrap();
+        assert_eq!(total, 5);
+        assert_eq!(t_to_n, 3); // 0, 2, 4
+        assert_eq!(n_to_t, 2); // 1, 3
+    }
+}
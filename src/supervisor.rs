@@ -0,0 +1,164 @@
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::metrics::Metrics;
+use crate::Result;
+
+/// Wartezeit vor dem ersten Neustart einer Komponente
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Obergrenze der Wartezeit zwischen zwei Neustarts
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Lief eine Komponente mindestens so lange, beginnt der Backoff wieder von vorn
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Gemeinsames Shutdown-Signal für alle Komponenten. Nach `trigger` werden keine
+/// Komponenten mehr neu gestartet, und laufende beenden sich nach ihrer aktuellen Arbeit.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Shutdown { sender: Arc::new(sender) }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Wartet, bis `trigger` aufgerufen wurde
+    pub async fn triggered(&self) {
+        let mut receiver = self.sender.subscribe();
+        // Der Sender lebt so lange wie `self`, ein Fehler ist daher ausgeschlossen
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hält eine Komponente (Telegram-Polling, Nostr-Listener) am Laufen: endet sie
+/// mit Fehler, regulär oder durch Panic, wird sie mit exponentiellem Backoff
+/// neu gestartet. Kehrt erst zurück, wenn die Komponente nach `shutdown` endet.
+pub async fn supervise<F, Fut>(name: &'static str, metrics: Arc<Metrics>, shutdown: Shutdown, mut start: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let started = Instant::now();
+        // Eigener Task, damit ein Panic nur die Komponente beendet
+        let result = tokio::spawn(start().instrument(info_span!("component", component = name))).await;
+
+        if shutdown.is_triggered() {
+            match result {
+                Ok(Err(e)) => warn!("{} beim Herunterfahren mit Fehler beendet: {}", name, e),
+                Err(e) => warn!("{} beim Herunterfahren abgestürzt: {}", name, e),
+                Ok(Ok(())) => debug!("{} beendet", name),
+            }
+            return;
+        }
+
+        match result {
+            Ok(Ok(())) => warn!("{} unerwartet beendet", name),
+            Ok(Err(e)) => error!("{} mit Fehler beendet: {}", name, e),
+            Err(e) => error!("{} abgestürzt: {}", name, e),
+        }
+
+        let delay = restart_delay(backoff, started.elapsed());
+        backoff = (delay * 2).min(MAX_BACKOFF);
+        metrics.record_restart(name);
+        info!("🔁 {} wird in {:?} neu gestartet", name, delay);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.triggered() => return,
+        }
+    }
+}
+
+/// Wartezeit vor dem nächsten Neustart: der aktuelle Backoff, nach einer
+/// stabilen Laufzeit wieder `INITIAL_BACKOFF`
+fn restart_delay(backoff: Duration, ran_for: Duration) -> Duration {
+    if ran_for >= STABLE_AFTER {
+        INITIAL_BACKOFF
+    } else {
+        backoff
+    }
+}
+
+/// Wartet auf ein Shutdown-Signal (Ctrl-C bzw. SIGINT, unter Unix auch SIGTERM)
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Fehler beim Installieren des Shutdown-Handlers");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Fehler beim Installieren des SIGTERM-Handlers")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("🛑 SIGINT erhalten, Bridge wird beendet..."),
+        _ = terminate => info!("🛑 SIGTERM erhalten, Bridge wird beendet..."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BridgeError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_restart_delay_resets_after_stable_run() {
+        let short = Duration::from_secs(1);
+        assert_eq!(restart_delay(Duration::from_secs(8), short), Duration::from_secs(8));
+        assert_eq!(restart_delay(MAX_BACKOFF, STABLE_AFTER), INITIAL_BACKOFF);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_component_is_restarted() {
+        let metrics = Arc::new(Metrics::new());
+        let starts = Arc::new(AtomicUsize::new(0));
+
+        let counter = starts.clone();
+        let shutdown = Shutdown::new();
+        let supervisor = tokio::spawn(supervise("test", metrics.clone(), shutdown.clone(), move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 1 {
+                    panic!("Absturz im Test");
+                }
+                Err(BridgeError::TaskEnded("Test".to_string()))
+            }
+        }));
+
+        // Backoff 1s + 2s: nach 5s ist die Komponente dreimal gestartet, der vierte Start steht aus
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+        assert!(metrics.render().contains(r#"bridge_component_restarts_total{component="test"} 3"#));
+
+        // Nach dem Shutdown kein weiterer Neustart, der Supervisor kehrt zurück
+        shutdown.trigger();
+        supervisor.await.unwrap();
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+    }
+}
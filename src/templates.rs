@@ -0,0 +1,362 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use std::env;
+
+use crate::config::{ConfigError, EncryptionType};
+
+/// Sprache der mitgelieferten Vorlagen (MESSAGE_LANGUAGE)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    De,
+    En,
+}
+
+impl Language {
+    pub fn from_str(s: &str) -> Result<Self, ConfigError> {
+        match s.to_lowercase().as_str() {
+            "de" => Ok(Language::De),
+            "en" => Ok(Language::En),
+            _ => Err(ConfigError::InvalidValue {
+                var: "MESSAGE_LANGUAGE".to_string(),
+                msg: "Muss 'de' oder 'en' sein".to_string(),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::De => "de",
+            Language::En => "en",
+        }
+    }
+}
+
+/// Variablen, die in Vorlagen als `{name}` verwendet werden können
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variable {
+    /// Name des Absenders (bei verknüpften Usern inkl. npub)
+    Sender,
+    /// Uhrzeit (HH:MM) in TIMEZONE
+    Time,
+    /// Datum (JJJJ-MM-TT)
+    Date,
+    /// Datum und Uhrzeit (JJJJ-MM-TT HH:MM:SS)
+    DateTime,
+    /// Titel des Telegram-Chats
+    ChatTitle,
+    /// Absender und Anfang der Nachricht, auf die geantwortet wird
+    Reply,
+    /// Ursprünglicher Absender bzw. Chat einer weitergeleiteten Nachricht
+    Forwarded,
+    /// Sprache der Vorlagen (`de`/`en`)
+    Language,
+    /// Modus wie in ENCRYPTION_TYPE
+    Route,
+    /// Der eigentliche Nachrichtentext
+    Text,
+}
+
+impl Variable {
+    const ALL: [Variable; 10] = [
+        Variable::Sender,
+        Variable::Time,
+        Variable::Date,
+        Variable::DateTime,
+        Variable::ChatTitle,
+        Variable::Reply,
+        Variable::Forwarded,
+        Variable::Language,
+        Variable::Route,
+        Variable::Text,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Variable::Sender => "sender",
+            Variable::Time => "time",
+            Variable::Date => "date",
+            Variable::DateTime => "datetime",
+            Variable::ChatTitle => "chat_title",
+            Variable::Reply => "reply",
+            Variable::Forwarded => "forwarded",
+            Variable::Language => "language",
+            Variable::Route => "route",
+            Variable::Text => "text",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|variable| variable.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+    /// `{?name}…{/name}`: nur ausgeben, wenn die Variable nicht leer ist
+    Section(Variable, Vec<Part>),
+}
+
+/// Vorlage für den Rahmen einer weitergeleiteten Nachricht, z.B.
+/// `👤 Von: {sender} ({time})\n\n{?reply}↩️ {reply}\n{/reply}{text}`.
+/// `{{` und `}}` stehen für geschweifte Klammern, `\n` für einen Zeilenumbruch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// Werte für die Variablen einer Vorlage
+#[derive(Debug, Clone)]
+pub struct MessageContext<'a> {
+    pub sender: &'a str,
+    pub time: DateTime<Tz>,
+    pub chat_title: Option<&'a str>,
+    pub reply: Option<&'a str>,
+    pub forwarded: Option<&'a str>,
+    pub route: &'a EncryptionType,
+    pub language: Language,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let source = source.replace("\\n", "\n");
+        let mut chars = source.chars().peekable();
+        let mut stack: Vec<(Option<Variable>, Vec<Part>)> = vec![(None, Vec::new())];
+        let mut literal = String::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("'{{{}' wird nicht geschlossen", name)),
+                        }
+                    }
+
+                    let parts = &mut stack.last_mut().expect("Vorlage hat eine Wurzel").1;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    let (marker, name) = match name.chars().next() {
+                        Some(marker @ ('?' | '/')) => (Some(marker), &name[1..]),
+                        _ => (None, name.as_str()),
+                    };
+                    let variable = Variable::from_name(name)
+                        .ok_or_else(|| format!("Unbekannte Variable '{{{}}}'", name))?;
+
+                    match marker {
+                        Some('?') => stack.push((Some(variable), Vec::new())),
+                        Some(_) => {
+                            let (open, section) = stack.pop().expect("Vorlage hat eine Wurzel");
+                            if open != Some(variable) {
+                                return Err(format!("'{{/{}}}' ohne passendes '{{?{}}}'", name, name));
+                            }
+                            stack.last_mut().expect("Abschnitt liegt in der Wurzel").1.push(Part::Section(variable, section));
+                        }
+                        None => parts.push(Part::Variable(variable)),
+                    }
+                }
+                '}' => return Err("Einzelne '}' muss als '}}' geschrieben werden".to_string()),
+                c => literal.push(c),
+            }
+        }
+
+        let (open, mut parts) = stack.pop().expect("Vorlage hat eine Wurzel");
+        if let Some(variable) = open {
+            return Err(format!("Abschnitt '{{?{}}}' wird nicht geschlossen", variable.name()));
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if !contains_text(&parts) {
+            return Err("Die Vorlage muss {text} enthalten".to_string());
+        }
+        Ok(Template { parts })
+    }
+
+    /// Setzt die Werte ein. Vorlagentext und Variablen laufen durch `escape`
+    /// (z.B. für Telegram-HTML), `text` wird unverändert übernommen.
+    pub fn render(&self, context: &MessageContext, text: &str, escape: impl Fn(&str) -> String) -> String {
+        let mut out = String::new();
+        render_parts(&self.parts, context, text, &escape, &mut out);
+        out
+    }
+}
+
+fn contains_text(parts: &[Part]) -> bool {
+    parts.iter().any(|part| match part {
+        Part::Variable(Variable::Text) => true,
+        Part::Section(_, parts) => contains_text(parts),
+        _ => false,
+    })
+}
+
+fn render_parts(parts: &[Part], context: &MessageContext, text: &str, escape: &dyn Fn(&str) -> String, out: &mut String) {
+    for part in parts {
+        match part {
+            Part::Literal(literal) => out.push_str(&escape(literal)),
+            Part::Variable(Variable::Text) => out.push_str(text),
+            Part::Variable(variable) => out.push_str(&escape(&value(*variable, context))),
+            Part::Section(Variable::Text, section) if !text.is_empty() => render_parts(section, context, text, escape, out),
+            Part::Section(Variable::Text, _) => {}
+            Part::Section(variable, section) => {
+                if !value(*variable, context).is_empty() {
+                    render_parts(section, context, text, escape, out);
+                }
+            }
+        }
+    }
+}
+
+fn value(variable: Variable, context: &MessageContext) -> String {
+    match variable {
+        Variable::Sender => context.sender.to_string(),
+        Variable::Time => context.time.format("%H:%M").to_string(),
+        Variable::Date => context.time.format("%Y-%m-%d").to_string(),
+        Variable::DateTime => context.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        Variable::ChatTitle => context.chat_title.unwrap_or_default().to_string(),
+        Variable::Reply => context.reply.unwrap_or_default().to_string(),
+        Variable::Forwarded => context.forwarded.unwrap_or_default().to_string(),
+        Variable::Language => context.language.as_str().to_string(),
+        Variable::Route => context.route.as_str().to_string(),
+        Variable::Text => String::new(),
+    }
+}
+
+/// Vorlagen für beide Richtungen des konfigurierten Modus
+#[derive(Debug, Clone)]
+pub struct Templates {
+    pub language: Language,
+    pub telegram_to_nostr: Template,
+    pub nostr_to_telegram: Template,
+}
+
+impl Templates {
+    /// Lädt die Vorlagen: zuerst `TEMPLATE_<RICHTUNG>_<MODUS>` (z.B.
+    /// `TEMPLATE_TELEGRAM_TO_NOSTR_PUBLIC`), dann `TEMPLATE_<RICHTUNG>`, sonst
+    /// die Standardvorlage in MESSAGE_LANGUAGE (Standard: de)
+    pub fn from_env(route: &EncryptionType) -> Result<Self, ConfigError> {
+        let language = match env::var("MESSAGE_LANGUAGE") {
+            Ok(value) if !value.is_empty() => Language::from_str(&value)?,
+            _ => Language::De,
+        };
+
+        Ok(Templates {
+            language,
+            telegram_to_nostr: load("TEMPLATE_TELEGRAM_TO_NOSTR", route, default_telegram_to_nostr(language, route))?,
+            nostr_to_telegram: load("TEMPLATE_NOSTR_TO_TELEGRAM", route, default_nostr_to_telegram(language))?,
+        })
+    }
+}
+
+fn load(prefix: &str, route: &EncryptionType, default: &str) -> Result<Template, ConfigError> {
+    let route_var = format!("{}_{}", prefix, route.as_str().to_uppercase());
+    let (var, source) = match (env::var(&route_var), env::var(prefix)) {
+        (Ok(source), _) if !source.is_empty() => (route_var, source),
+        (_, Ok(source)) if !source.is_empty() => (prefix.to_string(), source),
+        _ => return Ok(Template::parse(default).expect("Standardvorlagen sind gültig")),
+    };
+    Template::parse(&source).map_err(|msg| ConfigError::InvalidValue { var, msg })
+}
+
+fn default_telegram_to_nostr(language: Language, route: &EncryptionType) -> &'static str {
+    match (language, route) {
+        (Language::De, EncryptionType::Public) => {
+            "Von: {sender} ({time})\n\n{?forwarded}↪️ Weitergeleitet von {forwarded}\n{/forwarded}{?reply}↩️ {reply}\n{/reply}{text}"
+        }
+        (Language::De, EncryptionType::Group) => {
+            "📱 Telegram → Nostr Gruppe\n👤 Von: {sender} ({time})\n\n{?forwarded}↪️ Weitergeleitet von {forwarded}\n{/forwarded}{?reply}↩️ {reply}\n{/reply}{text}"
+        }
+        (Language::De, _) => {
+            "📱 Telegram-Nachricht\n👤 Von: {sender}\n📅 Zeit: {datetime}\n\n{?forwarded}↪️ Weitergeleitet von {forwarded}\n{/forwarded}{?reply}↩️ {reply}\n{/reply}{text}"
+        }
+        (Language::En, EncryptionType::Public) => {
+            "From: {sender} ({time})\n\n{?forwarded}↪️ Forwarded from {forwarded}\n{/forwarded}{?reply}↩️ {reply}\n{/reply}{text}"
+        }
+        (Language::En, EncryptionType::Group) => {
+            "📱 Telegram → Nostr group\n👤 From: {sender} ({time})\n\n{?forwarded}↪️ Forwarded from {forwarded}\n{/forwarded}{?reply}↩️ {reply}\n{/reply}{text}"
+        }
+        (Language::En, _) => {
+            "📱 Telegram message\n👤 From: {sender}\n📅 Time: {datetime}\n\n{?forwarded}↪️ Forwarded from {forwarded}\n{/forwarded}{?reply}↩️ {reply}\n{/reply}{text}"
+        }
+    }
+}
+
+fn default_nostr_to_telegram(language: Language) -> &'static str {
+    match language {
+        Language::De => "📨 Nostr-DM\n👤 Von: {sender}\n\n{?reply}↩️ {reply}\n{/reply}{text}",
+        Language::En => "📨 Nostr DM\n👤 From: {sender}\n\n{?reply}↩️ {reply}\n{/reply}{text}",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context<'a>(reply: Option<&'a str>, route: &'a EncryptionType) -> MessageContext<'a> {
+        MessageContext {
+            sender: "Alice <3",
+            time: chrono_tz::Europe::Berlin.with_ymd_and_hms(2024, 5, 1, 14, 30, 5).unwrap(),
+            chat_title: Some("Bitcoin-Stammtisch"),
+            reply,
+            forwarded: None,
+            route,
+            language: Language::En,
+        }
+    }
+
+    #[test]
+    fn test_default_templates_keep_previous_headers() {
+        let route = EncryptionType::Nip04;
+        let template = Template::parse(default_telegram_to_nostr(Language::De, &route)).unwrap();
+        assert_eq!(
+            template.render(&context(None, &route), "Hallo", str::to_string),
+            "📱 Telegram-Nachricht\n👤 Von: Alice <3\n📅 Zeit: 2024-05-01 14:30:05\n\nHallo"
+        );
+
+        let forwarded = MessageContext { forwarded: Some("Bob (@bob)"), ..context(None, &route) };
+        assert_eq!(
+            template.render(&forwarded, "Hallo", str::to_string),
+            "📱 Telegram-Nachricht\n👤 Von: Alice <3\n📅 Zeit: 2024-05-01 14:30:05\n\n↪️ Weitergeleitet von Bob (@bob)\nHallo"
+        );
+
+        let template = Template::parse(default_nostr_to_telegram(Language::En)).unwrap();
+        assert_eq!(
+            template.render(&context(Some("Bob: Wann?"), &route), "<b>Hi</b>", crate::formatting::escape_html),
+            "📨 Nostr DM\n👤 From: Alice &lt;3\n\n↩️ Bob: Wann?\n<b>Hi</b>"
+        );
+    }
+
+    #[test]
+    fn test_custom_template_variables_and_sections() {
+        let route = EncryptionType::Group;
+        let template = Template::parse(r"[{chat_title}|{route}|{language}] {sender} {date} {time}{?reply} ({reply}){/reply}:\n{text} {{ok}}").unwrap();
+
+        assert_eq!(
+            template.render(&context(None, &route), "Text", str::to_string),
+            "[Bitcoin-Stammtisch|group|en] Alice <3 2024-05-01 14:30:\nText {ok}"
+        );
+        assert_eq!(
+            template.render(&context(Some("Bob"), &route), "Text", str::to_string),
+            "[Bitcoin-Stammtisch|group|en] Alice <3 2024-05-01 14:30 (Bob):\nText {ok}"
+        );
+
+        assert!(Template::parse("{sender}: {unbekannt} {text}").is_err());
+        assert!(Template::parse("{?reply}{reply}").is_err());
+        assert!(Template::parse("Nur {sender}").is_err());
+    }
+}
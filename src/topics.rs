@@ -0,0 +1,121 @@
+use nostr_sdk::prelude::*;
+use std::env;
+
+use crate::config::{ConfigError, EncryptionType};
+
+/// Nostr-Ziel eines Telegram-Forum-Themas, passend zum Modus
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopicDestination {
+    /// Eigener DM-Partner (nip04/nip17)
+    DirectMessage(PublicKey),
+    /// Eigene NIP-29-Gruppe, Event-ID als Hex (group)
+    Group(String),
+    /// Hashtag ohne `#`, als `t`-Tag und im Text (public)
+    Hashtag(String),
+}
+
+/// Zuordnung von Forum-Themen (`message_thread_id`) zu Nostr-Zielen aus
+/// TELEGRAM_TOPICS. Themen ohne Eintrag nutzen das Standardziel.
+#[derive(Debug, Clone, Default)]
+pub struct TopicRoutes {
+    routes: Vec<(i32, TopicDestination)>,
+}
+
+impl TopicRoutes {
+    /// Liest TELEGRAM_TOPICS, z.B. `12:npub1…,34:npub1…` (nip04/nip17),
+    /// `12:#bitcoin` (public) oder `12:<Gruppen-Event-ID>` (group)
+    pub fn from_env(route: &EncryptionType) -> Result<Self, ConfigError> {
+        match env::var("TELEGRAM_TOPICS") {
+            Ok(value) if !value.trim().is_empty() => Self::parse(&value, route).map_err(|msg| ConfigError::InvalidValue {
+                var: "TELEGRAM_TOPICS".to_string(),
+                msg,
+            }),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn parse(value: &str, route: &EncryptionType) -> Result<Self, String> {
+        let mut routes: Vec<(i32, TopicDestination)> = Vec::new();
+
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (thread, destination) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("'{}' muss die Form <Themen-ID>:<Ziel> haben", entry))?;
+            let thread_id = thread
+                .trim()
+                .parse::<i32>()
+                .ok()
+                .filter(|id| *id > 0)
+                .ok_or_else(|| format!("'{}' ist keine gültige Themen-ID", thread.trim()))?;
+            if routes.iter().any(|(id, _)| *id == thread_id) {
+                return Err(format!("Thema {} ist mehrfach zugeordnet", thread_id));
+            }
+
+            let destination = destination.trim();
+            let destination = match route {
+                EncryptionType::Nip04 | EncryptionType::Nip17 => PublicKey::parse(destination)
+                    .map(TopicDestination::DirectMessage)
+                    .map_err(|_| format!("'{}' ist kein gültiger Pubkey (npub…)", destination))?,
+                EncryptionType::Group => EventId::from_hex(destination)
+                    .map(|id| TopicDestination::Group(id.to_hex()))
+                    .map_err(|_| format!("'{}' ist keine gültige Gruppen-Event-ID", destination))?,
+                EncryptionType::Public => {
+                    let hashtag = destination.trim_start_matches('#');
+                    if hashtag.is_empty() || hashtag.contains(char::is_whitespace) {
+                        return Err(format!("'{}' ist kein gültiger Hashtag", destination));
+                    }
+                    TopicDestination::Hashtag(hashtag.to_lowercase())
+                }
+            };
+            routes.push((thread_id, destination));
+        }
+
+        Ok(TopicRoutes { routes })
+    }
+
+    /// Ziel eines Themas, `None` für das Standardziel
+    pub fn destination(&self, thread_id: i32) -> Option<&TopicDestination> {
+        self.routes.iter().find(|(id, _)| *id == thread_id).map(|(_, destination)| destination)
+    }
+
+    /// Thema, in das DMs dieses Partners gehören
+    pub fn thread_for_partner(&self, pubkey: &PublicKey) -> Option<i32> {
+        self.routes
+            .iter()
+            .find(|(_, destination)| *destination == TopicDestination::DirectMessage(*pubkey))
+            .map(|(id, _)| *id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_topic_routes() {
+        let alice = Keys::generate().public_key();
+        let value = format!("12:{}, 34:{}", alice.to_bech32().unwrap(), Keys::generate().public_key().to_hex());
+        let routes = TopicRoutes::parse(&value, &EncryptionType::Nip04).unwrap();
+        assert_eq!(routes.destination(12), Some(&TopicDestination::DirectMessage(alice)));
+        assert_eq!(routes.thread_for_partner(&alice), Some(12));
+        assert_eq!(routes.destination(99), None);
+
+        let routes = TopicRoutes::parse("5:#Bitcoin,6:nostr", &EncryptionType::Public).unwrap();
+        assert_eq!(routes.destination(5), Some(&TopicDestination::Hashtag("bitcoin".to_string())));
+        assert_eq!(routes.destination(6), Some(&TopicDestination::Hashtag("nostr".to_string())));
+
+        assert!(TopicRoutes::parse("5:#bitcoin", &EncryptionType::Nip04).is_err());
+        assert!(TopicRoutes::parse("5:npub1kaputt", &EncryptionType::Nip17).is_err());
+        assert!(TopicRoutes::parse("x:#a", &EncryptionType::Public).is_err());
+        assert!(TopicRoutes::parse("5:#a,5:#b", &EncryptionType::Public).is_err());
+        assert!(TopicRoutes::parse("5:abc", &EncryptionType::Group).is_err());
+    }
+}
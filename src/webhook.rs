@@ -0,0 +1,353 @@
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::convert::Infallible;
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::stop::StopToken;
+use teloxide::types::{AllowedUpdate, InputFile};
+use teloxide::update_listeners::{webhooks, AsUpdateStream, UpdateListener};
+use thiserror::Error;
+use tokio::sync::watch;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+
+use crate::config::{WebhookConfig, WebhookTls};
+use crate::health;
+use crate::metrics::Metrics;
+
+#[derive(Error, Debug)]
+pub enum WebhookError {
+    #[error("Listener auf {addr} nicht verfügbar: {source}")]
+    Bind { addr: SocketAddr, source: std::io::Error },
+    #[error("TLS-Konfiguration ungültig: {0}")]
+    Tls(String),
+    #[error("setWebhook fehlgeschlagen: {0}")]
+    Telegram(#[from] teloxide::RequestError),
+}
+
+/// Startet den Webhook-Listener und meldet den Webhook bei Telegram an.
+///
+/// Der Listener läuft, bis der Dispatcher ihn stoppt; danach wird der Webhook
+/// wieder abgemeldet. Fällt der HTTP-Server vorher aus, endet der Update-Strom
+/// und damit der Dispatcher, der dann neu gestartet werden kann. Telegram-Anfragen ohne passendes
+/// `X-Telegram-Bot-Api-Secret-Token` werden mit 401 abgewiesen. Bei einem
+/// Fehler ist nichts angemeldet und der Aufrufer kann auf Polling zurückfallen.
+pub async fn listen(
+    bot: Bot,
+    config: &WebhookConfig,
+    metrics: Arc<Metrics>,
+) -> Result<impl UpdateListener<Err = Infallible>, WebhookError> {
+    // Zuerst Zertifikat und Port, damit Telegram keinen toten Webhook bekommt
+    let acceptor = config.tls.as_ref().map(load_tls).transpose()?;
+    let socket = TcpListener::bind(config.listen_addr)
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+        .map_err(|source| WebhookError::Bind { addr: config.listen_addr, source })?;
+
+    let mut options = webhooks::Options::new(config.listen_addr, config.url.clone());
+    if let Some(ref secret) = config.secret_token {
+        options = options.secret_token(secret.clone());
+    }
+    if let Some(tls) = config.tls.as_ref().filter(|tls| tls.self_signed) {
+        options = options.certificate(InputFile::file(&tls.cert_path));
+    }
+
+    let (listener, stop, router) = webhooks::axum_to_router(bot, options).await?;
+    let (server_failed, failed) = watch::channel(false);
+    info!("🪝 Telegram-Webhook aktiv: {} (lauscht auf {}{})",
+        config.url,
+        config.listen_addr,
+        if acceptor.is_some() { ", HTTPS" } else { "" });
+
+    tokio::spawn(async move {
+        let result = match acceptor {
+            Some(acceptor) => serve_tls(socket, acceptor, router, stop).await,
+            None => match axum::Server::from_tcp(socket) {
+                Ok(server) => server
+                    .serve(router.into_make_service())
+                    .with_graceful_shutdown(stop)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+        };
+        match result {
+            Ok(()) => info!("Telegram-Webhook beendet"),
+            Err(e) => {
+                error!("Webhook-Listener beendet: {}", e);
+                let _ = server_failed.send(true);
+            }
+        }
+    });
+
+    Ok(WebhookListener { inner: listener, failed, metrics })
+}
+
+/// Update-Listener des Webhooks, dessen Update-Strom endet, wenn der
+/// HTTP-Server ausfällt. Der Strom von teloxide endet erst, wenn keine
+/// Verbindung mehr den Router hält – mit Keep-Alive also womöglich nie.
+///
+/// Telegram ruft den Webhook nur bei neuen Updates auf. Lebenszeichen des
+/// Empfangs ist deshalb jede Abfrage des Stroms durch den Dispatcher, die
+/// mindestens sekündlich erfolgt, solange er nicht hängt.
+struct WebhookListener<L> {
+    inner: L,
+    failed: watch::Receiver<bool>,
+    metrics: Arc<Metrics>,
+}
+
+impl<'a, L: UpdateListener<Err = Infallible>> AsUpdateStream<'a> for WebhookListener<L> {
+    type StreamErr = Infallible;
+    type Stream = BoxStream<'a, Result<Update, Infallible>>;
+
+    fn as_stream(&'a mut self) -> Self::Stream {
+        let mut failed = self.failed.clone();
+        // Ein regulär beendeter Server schließt den Strom selbst
+        let server_failed = async move {
+            if failed.wait_for(|failed| *failed).await.is_err() {
+                future::pending::<()>().await;
+            }
+        };
+        let mut updates = self.inner.as_stream().take_until(server_failed).boxed();
+        let metrics = self.metrics.clone();
+        stream::poll_fn(move |cx| {
+            metrics.heartbeat(health::TELEGRAM);
+            updates.poll_next_unpin(cx)
+        })
+        .boxed()
+    }
+}
+
+impl<L: UpdateListener<Err = Infallible>> UpdateListener for WebhookListener<L> {
+    type Err = Infallible;
+
+    fn stop_token(&mut self) -> StopToken {
+        self.inner.stop_token()
+    }
+
+    fn hint_allowed_updates(&mut self, hint: &mut dyn Iterator<Item = AllowedUpdate>) {
+        self.inner.hint_allowed_updates(hint);
+    }
+}
+
+/// Liest Zertifikatskette und privaten Schlüssel (PEM)
+fn load_tls(tls: &WebhookTls) -> Result<TlsAcceptor, WebhookError> {
+    let read = |path: &str| {
+        File::open(path)
+            .and_then(|file| rustls_pemfile::read_all(&mut BufReader::new(file)))
+            .map_err(|e| WebhookError::Tls(format!("{}: {}", path, e)))
+    };
+
+    let certs: Vec<rustls::Certificate> = read(&tls.cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(rustls::Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        return Err(WebhookError::Tls(format!("{}: kein Zertifikat gefunden", tls.cert_path)));
+    }
+    let key = read(&tls.key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der) | rustls_pemfile::Item::RSAKey(der) | rustls_pemfile::Item::ECKey(der) => {
+                Some(rustls::PrivateKey(der))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| WebhookError::Tls(format!("{}: kein privater Schlüssel gefunden", tls.key_path)))?;
+
+    let server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| WebhookError::Tls(e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// HTTPS-Server für den Webhook: jede Verbindung in einem eigenen Task, bis `stop`
+async fn serve_tls(
+    socket: TcpListener,
+    acceptor: TlsAcceptor,
+    router: axum::Router,
+    stop: impl Future<Output = ()>,
+) -> Result<(), String> {
+    let listener = tokio::net::TcpListener::from_std(socket).map_err(|e| e.to_string())?;
+    tokio::pin!(stop);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Webhook-Verbindung nicht angenommen: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut stop => return Ok(()),
+        };
+
+        let acceptor = acceptor.clone();
+        let router = router.clone();
+        tokio::spawn(async move {
+            match acceptor.accept(stream).await {
+                Ok(stream) => {
+                    if let Err(e) = hyper::server::conn::Http::new().serve_connection(stream, router).await {
+                        debug!("Webhook-Verbindung von {} abgebrochen: {}", peer, e);
+                    }
+                }
+                Err(e) => debug!("TLS-Handshake mit {} fehlgeschlagen: {}", peer, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::Uri, response::IntoResponse, Json};
+    use serde_json::json;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use teloxide::update_listeners::AsUpdateStream;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Aufgerufene Bot-API-Methoden (kleingeschrieben) mit ihrem Body
+    type Calls = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// Lokale Attrappe der Telegram-Bot-API; `fail_set_webhook` lehnt setWebhook ab
+    async fn mock_telegram_api(fail_set_webhook: bool) -> (Bot, Calls) {
+        async fn api(State((calls, fail)): State<(Calls, bool)>, uri: Uri, body: String) -> impl IntoResponse {
+            let method = uri.path().rsplit('/').next().unwrap_or_default().to_lowercase();
+            let failed = fail && method == "setwebhook";
+            calls.lock().unwrap().push((method, body));
+            if failed {
+                Json(json!({"ok": false, "error_code": 400, "description": "Bad Request: bad webhook: Failed to resolve host"}))
+            } else {
+                Json(json!({"ok": true, "result": true}))
+            }
+        }
+
+        let calls = Calls::default();
+        let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let router = axum::Router::new().fallback(api).with_state((calls.clone(), fail_set_webhook));
+        tokio::spawn(axum::Server::from_tcp(socket).unwrap().serve(router.into_make_service()));
+
+        let bot = Bot::new("123:test").set_api_url(format!("http://{}/", addr).parse().unwrap());
+        (bot, calls)
+    }
+
+    fn webhook_config() -> WebhookConfig {
+        // Freien Port ermitteln; der Listener bindet ihn gleich wieder
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        WebhookConfig {
+            url: "https://bridge.example.org/telegram".parse().unwrap(),
+            listen_addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            secret_token: Some("geheim_123".to_string()),
+            tls: None,
+        }
+    }
+
+    /// Minimaler HTTP-Client: sendet ein Update an den Webhook, gibt den Statuscode zurück
+    async fn post_update(addr: SocketAddr, secret: &str, body: &str) -> u16 {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "POST /telegram HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             X-Telegram-Bot-Api-Secret-Token: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            addr, secret, body.len(), body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_webhook_receives_updates_with_secret() {
+        let (bot, calls) = mock_telegram_api(false).await;
+        let config = webhook_config();
+        let mut listener = listen(bot, &config, Arc::new(Metrics::new())).await.unwrap();
+
+        let (method, body) = calls.lock().unwrap()[0].clone();
+        assert_eq!(method, "setwebhook");
+        // setWebhook geht als multipart/form-data (wegen des optionalen Zertifikats)
+        assert!(body.contains("https://bridge.example.org/telegram"));
+        assert!(body.contains("geheim_123"));
+
+        let update = json!({
+            "update_id": 7,
+            "message": {
+                "message_id": 1,
+                "date": 1700000000,
+                "chat": {"id": -100123, "type": "supergroup", "title": "Test"},
+                "text": "Hallo"
+            }
+        })
+        .to_string();
+        assert_eq!(post_update(config.listen_addr, "falsch", &update).await, 401);
+        assert_eq!(post_update(config.listen_addr, "geheim_123", &update).await, 200);
+
+        let stop_token = listener.stop_token();
+        {
+            let updates = listener.as_stream();
+            tokio::pin!(updates);
+            let received = tokio::time::timeout(Duration::from_secs(5), updates.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(received.id, 7);
+        }
+
+        // Beim Stoppen wird der Webhook wieder abgemeldet
+        stop_token.stop();
+        for _ in 0..50 {
+            if calls.lock().unwrap().iter().any(|(method, _)| method == "deletewebhook") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("deleteWebhook wurde nicht aufgerufen");
+    }
+
+    #[tokio::test]
+    async fn test_server_failure_ends_update_stream() {
+        let config = webhook_config();
+        let (listener, _stop, _router) = webhooks::axum_no_setup(webhooks::Options::new(config.listen_addr, config.url));
+        let (server_failed, failed) = watch::channel(false);
+        let metrics = Arc::new(Metrics::new());
+        let mut listener = WebhookListener { inner: listener, failed, metrics: metrics.clone() };
+
+        let updates = listener.as_stream();
+        tokio::pin!(updates);
+        assert!(tokio::time::timeout(Duration::from_millis(100), updates.next()).await.is_err());
+        assert!(metrics.last_heartbeat(health::TELEGRAM).is_some());
+
+        // Der Router (und damit der Sender) lebt weiter, trotzdem endet der Strom
+        server_failed.send(true).unwrap();
+        let ended = tokio::time::timeout(Duration::from_secs(5), updates.next()).await.unwrap();
+        assert!(ended.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_setup_failure_allows_polling_fallback() {
+        let (bot, calls) = mock_telegram_api(true).await;
+        let config = webhook_config();
+        assert!(matches!(listen(bot.clone(), &config, Arc::new(Metrics::new())).await, Err(WebhookError::Telegram(_))));
+        assert_eq!(calls.lock().unwrap().len(), 1);
+
+        // Ein belegter Port verhindert die Anmeldung bei Telegram
+        let _busy = TcpListener::bind(config.listen_addr).unwrap();
+        assert!(matches!(listen(bot, &config, Arc::new(Metrics::new())).await, Err(WebhookError::Bind { .. })));
+        assert_eq!(calls.lock().unwrap().len(), 1);
+
+        let tls = WebhookTls { cert_path: "/nicht/vorhanden.pem".to_string(), key_path: "/nicht/vorhanden.key".to_string(), self_signed: false };
+        assert!(matches!(load_tls(&tls), Err(WebhookError::Tls(_))));
+    }
+}
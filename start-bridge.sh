@@ -32,8 +32,29 @@ tmux new-session -d -s bridge "cd $(pwd) && RUST_LOG=info cargo run --release"
 # Kurz warten
 sleep 2
 
+# Bereitschaft über /readyz prüfen, falls der HTTP-Server aktiv ist
+HTTP_ADDR=$(grep -E '^HTTP_LISTEN_ADDR=' .env 2>/dev/null | cut -d= -f2- | tr -d '"')
+READY=""
+if [ -n "$HTTP_ADDR" ] && command -v curl >/dev/null; then
+    echo -e "${YELLOW}Warte auf http://${HTTP_ADDR}/readyz ...${NC}"
+    # Großzügiges Zeitfenster, da cargo run ggf. noch kompiliert
+    for _ in $(seq 1 120); do
+        if curl -fsS "http://${HTTP_ADDR}/readyz" >/dev/null 2>&1; then
+            READY="yes"
+            break
+        fi
+        tmux has-session -t bridge 2>/dev/null || break
+        sleep 1
+    done
+    if [ -z "$READY" ]; then
+        echo -e "${RED}❌ Bridge meldet sich nicht bereit:${NC}"
+        curl -sS "http://${HTTP_ADDR}/readyz" 2>&1
+        echo ""
+    fi
+fi
+
 # Status prüfen
-if tmux has-session -t bridge 2>/dev/null; then
+if tmux has-session -t bridge 2>/dev/null && { [ -z "$HTTP_ADDR" ] || [ -n "$READY" ]; }; then
     echo -e "${GREEN}✅ Bridge läuft in tmux-Session 'bridge'${NC}"
     echo ""
     echo "Nützliche Befehle:"
@@ -11,8 +11,17 @@ echo -e "${GREEN}=== Nostr-Telegram-Bridge Stopper ===${NC}"
 
 # tmux-Session beenden
 if tmux has-session -t bridge 2>/dev/null; then
-    echo -e "${YELLOW}Beende tmux-Session 'bridge'...${NC}"
-    tmux kill-session -t bridge
+    # Ctrl-C statt kill-session, damit die Bridge laufende Weiterleitungen abschließt
+    echo -e "${YELLOW}Fahre Bridge herunter (Ctrl-C)...${NC}"
+    tmux send-keys -t bridge C-c
+    for _ in $(seq 1 40); do
+        tmux has-session -t bridge 2>/dev/null || break
+        sleep 1
+    done
+    if tmux has-session -t bridge 2>/dev/null; then
+        echo -e "${YELLOW}Beende tmux-Session 'bridge'...${NC}"
+        tmux kill-session -t bridge
+    fi
     echo -e "${GREEN}✅ tmux-Session beendet${NC}"
 else
     echo -e "${YELLOW}⚠️  Keine tmux-Session 'bridge' gefunden${NC}"
@@ -0,0 +1,24 @@
+-- Schema einer bridge.db vor Einführung der Migrationen (user_version = 0)
+CREATE TABLE IF NOT EXISTS message_mapping (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    telegram_chat_id INTEGER NOT NULL,
+    telegram_message_id INTEGER NOT NULL,
+    nostr_event_id TEXT NOT NULL,
+    nostr_recipient_pubkey TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    UNIQUE(telegram_chat_id, telegram_message_id),
+    UNIQUE(nostr_event_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_telegram_lookup 
+ ON message_mapping(telegram_chat_id, telegram_message_id);
+
+CREATE INDEX IF NOT EXISTS idx_nostr_lookup 
+ ON message_mapping(nostr_event_id);
+
+INSERT INTO message_mapping
+ (telegram_chat_id, telegram_message_id, nostr_event_id, nostr_recipient_pubkey, direction, timestamp)
+VALUES
+ (-1001234567890, 1, 'baseline-event-1', 'npub1baseline', 'telegram_to_nostr', 1700000000),
+ (-1001234567890, 2, 'baseline-event-2', 'npub1baseline', 'nostr_to_telegram', 1700000060);